@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use early_adopter_airdrop::client;
+
+use crate::AdminError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AirdropEntry {
+    pub wallet: Pubkey,
+    pub user_account: Pubkey,
+    pub amount: u64,
+}
+
+// Reads `wallet,user_account,amount` rows. A header row, blank lines and
+// lines starting with `#` are skipped.
+pub fn parse_airdrop_csv(csv: &str) -> Result<Vec<AirdropEntry>, AdminError> {
+    let mut entries = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| AdminError::Csv {
+            line: index + 1,
+            message,
+        };
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != 3 {
+            return Err(error(format!("expected 3 fields, found {}", fields.len())));
+        }
+        if entries.is_empty() && fields == ["wallet", "user_account", "amount"] {
+            continue;
+        }
+        let wallet = Pubkey::from_str(fields[0]).map_err(|_| error(format!("invalid wallet {}", fields[0])))?;
+        let user_account =
+            Pubkey::from_str(fields[1]).map_err(|_| error(format!("invalid user account {}", fields[1])))?;
+        let amount = fields[2]
+            .parse::<u64>()
+            .map_err(|_| error(format!("invalid amount {}", fields[2])))?;
+        entries.push(AirdropEntry {
+            wallet,
+            user_account,
+            amount,
+        });
+    }
+    Ok(entries)
+}
+
+// One `batch_reward_early_adopters` instruction per `batch_size` entries.
+// Each recipient adds three accounts, so transaction size caps the batch.
+pub fn airdrop_batches(mint: &Pubkey, authority: &Pubkey, entries: &[AirdropEntry], batch_size: usize) -> Vec<Instruction> {
+    entries
+        .chunks(batch_size.max(1))
+        .map(|batch| {
+            let recipients: Vec<_> = batch
+                .iter()
+                .map(|entry| (entry.user_account, entry.wallet, entry.amount))
+                .collect();
+            client::batch_reward_early_adopters(mint, authority, &recipients)
+        })
+        .collect()
+}
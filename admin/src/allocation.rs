@@ -0,0 +1,351 @@
+use std::str::FromStr;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::keccak;
+use early_adopter_airdrop::{LoyaltyState, UserAccount};
+use serde::{Deserialize, Deserializer};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+// Leaves and interior nodes are hashed with different prefixes so a node
+// can't be passed off as a leaf.
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AllocationError {
+    // A user account appears twice in the snapshot.
+    DuplicateUser(Pubkey),
+    // Two eligible user accounts belong to the same wallet, which would
+    // otherwise get an allocation and a Merkle leaf for each.
+    DuplicateWallet(Pubkey),
+    // The pool can't give every eligible user the floor amount.
+    PoolBelowFloors { required: u64, pool: u64 },
+    // A score or amount doesn't fit in the integer types.
+    Overflow,
+}
+
+impl std::fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocationError::DuplicateUser(user_account) => write!(f, "user account {user_account} is listed twice"),
+            AllocationError::DuplicateWallet(wallet) => write!(f, "wallet {wallet} has more than one eligible user account"),
+            AllocationError::PoolBelowFloors { required, pool } => {
+                write!(f, "floors need {required} but the pool holds {pool}")
+            }
+            AllocationError::Overflow => write!(f, "allocation overflows"),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+fn pubkey_from_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Pubkey::from_str(&value).map_err(serde::de::Error::custom)
+}
+
+// Users' on-chain activity at `taken_at`. Registration times aren't stored
+// on-chain, so they come from `UserInitialized` events, e.g. via the indexer.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub taken_at: i64,
+    pub users: Vec<SnapshotUser>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotUser {
+    #[serde(deserialize_with = "pubkey_from_str")]
+    pub wallet: Pubkey,
+    #[serde(deserialize_with = "pubkey_from_str")]
+    pub user_account: Pubkey,
+    pub loyalty_points: u64,
+    pub loyalty_tier: u8,
+    pub registered_at: i64,
+    pub referrals: u32,
+}
+
+impl SnapshotUser {
+    pub fn from_accounts(
+        user_account: &Pubkey,
+        user_info: &UserAccount,
+        loyalty_state: &LoyaltyState,
+        registered_at: i64,
+    ) -> Self {
+        Self {
+            wallet: user_info.user,
+            user_account: *user_account,
+            loyalty_points: loyalty_state.loyalty_points,
+            loyalty_tier: user_info.loyalty_tier,
+            registered_at,
+            referrals: user_info.referrals,
+        }
+    }
+}
+
+// Token base units per unit of each activity measure.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Weights {
+    pub points: u64,
+    pub tier: u64,
+    pub tenure_day: u64,
+    pub referral: u64,
+}
+
+// How snapshot activity turns into token amounts. A user's score is the
+// weighted sum of their points, tier, whole days since registering and
+// referrals. Without a pool the score is the amount; with one, the pool is
+// split in proportion to score. Either way eligible users get at least
+// `floor` and at most `cap`.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AllocationFormula {
+    pub weights: Weights,
+    pub pool: Option<u64>,
+    pub floor: u64,
+    pub cap: Option<u64>,
+    pub min_points: u64,
+    pub min_tier: u8,
+    pub min_tenure_days: u64,
+    pub min_referrals: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub wallet: Pubkey,
+    pub user_account: Pubkey,
+    pub amount: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllocationResult {
+    // Nonzero allocations, ordered by user account.
+    pub allocations: Vec<Allocation>,
+    pub total: u64,
+    // What's left of the pool when every user with a score hit the cap.
+    pub unallocated: u64,
+}
+
+impl AllocationFormula {
+    fn tenure_days(&self, user: &SnapshotUser, taken_at: i64) -> u64 {
+        (taken_at.saturating_sub(user.registered_at) / SECONDS_PER_DAY).max(0) as u64
+    }
+
+    pub fn is_eligible(&self, user: &SnapshotUser, taken_at: i64) -> bool {
+        user.loyalty_points >= self.min_points
+            && user.loyalty_tier >= self.min_tier
+            && user.referrals >= self.min_referrals
+            && self.tenure_days(user, taken_at) >= self.min_tenure_days
+    }
+
+    pub fn score(&self, user: &SnapshotUser, taken_at: i64) -> Result<u128, AllocationError> {
+        let terms = [
+            (user.loyalty_points, self.weights.points),
+            (user.loyalty_tier as u64, self.weights.tier),
+            (self.tenure_days(user, taken_at), self.weights.tenure_day),
+            (user.referrals as u64, self.weights.referral),
+        ];
+        terms.iter().try_fold(0u128, |score, &(measure, weight)| {
+            score
+                .checked_add(measure as u128 * weight as u128)
+                .ok_or(AllocationError::Overflow)
+        })
+    }
+
+    fn cap(&self) -> u64 {
+        self.cap.unwrap_or(u64::MAX)
+    }
+
+    // The result depends only on the snapshot's contents, not the order of
+    // its users.
+    pub fn allocate(&self, snapshot: &Snapshot) -> Result<AllocationResult, AllocationError> {
+        let mut users: Vec<&SnapshotUser> = snapshot
+            .users
+            .iter()
+            .filter(|user| self.is_eligible(user, snapshot.taken_at))
+            .collect();
+        users.sort_by_key(|user| user.user_account.to_bytes());
+        if let Some(pair) = users.windows(2).find(|pair| pair[0].user_account == pair[1].user_account) {
+            return Err(AllocationError::DuplicateUser(pair[0].user_account));
+        }
+        let mut wallets: Vec<[u8; 32]> = users.iter().map(|user| user.wallet.to_bytes()).collect();
+        wallets.sort();
+        if let Some(pair) = wallets.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(AllocationError::DuplicateWallet(Pubkey::new_from_array(pair[0])));
+        }
+        let scores = users
+            .iter()
+            .map(|user| self.score(user, snapshot.taken_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        let floor = self.floor.min(self.cap());
+
+        let (amounts, unallocated): (Vec<u64>, u64) = match self.pool {
+            None => {
+                let amounts = scores
+                    .iter()
+                    .map(|&score| u64::try_from(score).unwrap_or(u64::MAX).clamp(floor, self.cap()))
+                    .collect();
+                (amounts, 0)
+            }
+            Some(pool) => {
+                let required = (floor as u128 * users.len() as u128).try_into().unwrap_or(u64::MAX);
+                if required > pool {
+                    return Err(AllocationError::PoolBelowFloors { required, pool });
+                }
+                let headroom = self.cap() - floor;
+                let (shares, unallocated) = split_pool(pool - required, &scores, headroom)?;
+                (shares.into_iter().map(|share| floor + share).collect(), unallocated)
+            }
+        };
+
+        let allocations: Vec<Allocation> = users
+            .iter()
+            .zip(amounts)
+            .filter(|&(_, amount)| amount != 0)
+            .map(|(user, amount)| Allocation {
+                wallet: user.wallet,
+                user_account: user.user_account,
+                amount,
+            })
+            .collect();
+        let total = allocations
+            .iter()
+            .try_fold(0u64, |total, allocation| total.checked_add(allocation.amount))
+            .ok_or(AllocationError::Overflow)?;
+        Ok(AllocationResult {
+            allocations,
+            total,
+            unallocated,
+        })
+    }
+}
+
+// Splits `pool` in proportion to `scores`, giving no one more than
+// `headroom`. Whatever capped users can't take is split among the rest.
+// Units lost to rounding go one each to the largest remainders, ties to the
+// earlier user. Returns the shares and what no one could take.
+fn split_pool(pool: u64, scores: &[u128], headroom: u64) -> Result<(Vec<u64>, u64), AllocationError> {
+    let mut shares = vec![0u64; scores.len()];
+    let mut active: Vec<usize> = (0..scores.len())
+        .filter(|&index| scores[index] != 0 && headroom != 0)
+        .collect();
+    let mut remaining = pool;
+    while remaining != 0 && !active.is_empty() {
+        let total_score = active
+            .iter()
+            .try_fold(0u128, |total, &index| total.checked_add(scores[index]))
+            .ok_or(AllocationError::Overflow)?;
+        let mut exact = Vec::with_capacity(active.len());
+        for &index in &active {
+            let product = (remaining as u128)
+                .checked_mul(scores[index])
+                .ok_or(AllocationError::Overflow)?;
+            exact.push((index, product / total_score, product % total_score));
+        }
+        let capped: Vec<usize> = exact
+            .iter()
+            .filter(|&&(_, share, _)| share >= headroom as u128)
+            .map(|&(index, _, _)| index)
+            .collect();
+        if !capped.is_empty() {
+            for &index in &capped {
+                shares[index] = headroom;
+                remaining -= headroom;
+            }
+            active.retain(|index| !capped.contains(index));
+            continue;
+        }
+        // Every share is below the cap, so the extra unit still fits.
+        for &(index, share, _) in &exact {
+            shares[index] = share as u64;
+            remaining -= share as u64;
+        }
+        exact.sort_by_key(|&(_, _, remainder)| std::cmp::Reverse(remainder));
+        for &(index, _, _) in exact.iter().take(remaining as usize) {
+            shares[index] += 1;
+        }
+        remaining = 0;
+    }
+    Ok((shares, remaining))
+}
+
+pub fn allocation_leaf(allocation: &Allocation) -> [u8; 32] {
+    keccak::hashv(&[
+        LEAF_PREFIX,
+        allocation.wallet.as_ref(),
+        allocation.user_account.as_ref(),
+        &allocation.amount.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+// Pairs are hashed in sorted order, so proofs don't need leaf positions.
+fn node_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    keccak::hashv(&[NODE_PREFIX, left, right]).to_bytes()
+}
+
+// Each level of the tree, leaves first. A node without a sibling moves up
+// unchanged.
+fn merkle_levels(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [node] => *node,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+pub fn verify_allocation_proof(allocation: &Allocation, proof: &[[u8; 32]], root: &[u8; 32]) -> bool {
+    let computed = proof
+        .iter()
+        .fold(allocation_leaf(allocation), |node, sibling| node_hash(&node, sibling));
+    computed == *root
+}
+
+impl AllocationResult {
+    // All zeros when there are no allocations.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let leaves = self.allocations.iter().map(allocation_leaf).collect::<Vec<_>>();
+        if leaves.is_empty() {
+            return [0; 32];
+        }
+        merkle_levels(leaves).last().unwrap()[0]
+    }
+
+    // Sibling hashes from the leaf of `allocations[index]` up to the root.
+    pub fn merkle_proof(&self, index: usize) -> Vec<[u8; 32]> {
+        let leaves = self.allocations.iter().map(allocation_leaf).collect::<Vec<_>>();
+        let mut position = index;
+        let mut proof = Vec::new();
+        for level in merkle_levels(leaves).iter().filter(|level| level.len() > 1) {
+            if let Some(sibling) = level.get(position ^ 1) {
+                proof.push(*sibling);
+            }
+            position /= 2;
+        }
+        proof
+    }
+
+    // In the `wallet,user_account,amount` format the `airdrop` command reads.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("wallet,user_account,amount\n");
+        for allocation in &self.allocations {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                allocation.wallet, allocation.user_account, allocation.amount
+            ));
+        }
+        csv
+    }
+}
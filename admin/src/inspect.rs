@@ -0,0 +1,175 @@
+use anchor_lang::prelude::Pubkey;
+use early_adopter_airdrop::{
+    Config, LoyaltyState, Proposal, ProposalAction, ProposalStatus, SocialPlatform, UserAccount, VoteMode,
+    VotingPower,
+};
+use serde_json::{json, Value};
+
+// Keys are rendered as base58 strings, hashes as hex and enums by their
+// snake_case names.
+
+pub fn config_json(address: &Pubkey, config: &Config) -> Value {
+    json!({
+        "address": address.to_string(),
+        "admin": config.admin.to_string(),
+        "guardian": config.guardian.to_string(),
+        "paused": config.paused,
+        "tier_thresholds": config.tier_thresholds,
+        "redemption_rate": config.redemption_rate,
+        "referral_reward": config.referral_reward,
+        "emission_budget": config.emission_budget,
+        "emitted": config.emitted,
+        "redeem_window": config.redeem_window,
+        "global_redeem_limit": config.global_redeem_limit,
+        "user_redeem_limit": config.user_redeem_limit,
+        "global_redemptions": {
+            "start": config.global_redemptions.start,
+            "used": config.global_redemptions.used,
+        },
+        "voting_period": config.voting_period,
+        "timelock_delay": config.timelock_delay,
+        "quorum": config.quorum,
+        "executive_voting_power": voting_power_json(&config.executive_voting_power),
+        "governance_mint": config.governance_mint.to_string(),
+        "deposit_vault": config.deposit_vault.to_string(),
+        "proposal_min_tokens": config.proposal_min_tokens,
+        "proposal_min_tier": config.proposal_min_tier,
+        "proposal_deposit": config.proposal_deposit,
+        "max_active_proposals": config.max_active_proposals,
+    })
+}
+
+pub fn user_account_json(address: &Pubkey, user_account: &UserAccount) -> Value {
+    let socials: Vec<Value> = user_account
+        .socials
+        .iter()
+        .map(|social| {
+            let platform = match social.platform {
+                SocialPlatform::Twitter => "twitter",
+                SocialPlatform::Discord => "discord",
+                SocialPlatform::Telegram => "telegram",
+                SocialPlatform::Github => "github",
+            };
+            json!({ "platform": platform, "handle": social.handle })
+        })
+        .collect();
+    json!({
+        "address": address.to_string(),
+        "version": user_account.version,
+        "user": user_account.user.to_string(),
+        "loyalty_tier": user_account.loyalty_tier,
+        "tokens_held": user_account.tokens_held,
+        "badges": user_account.badges,
+        "badges_minted": user_account.badges_minted,
+        "redemptions": user_account.redemptions,
+        "referrals": user_account.referrals,
+        "votes_cast": user_account.votes_cast,
+        "active_proposals": user_account.active_proposals,
+        "username": (user_account.username != Pubkey::default()).then(|| user_account.username.to_string()),
+        "early_adopter_rewarded": user_account.early_adopter_rewarded,
+        "name": user_account.name,
+        "bio": user_account.bio,
+        "avatar_uri": user_account.avatar_uri,
+        "socials": socials,
+    })
+}
+
+pub fn loyalty_state_json(address: &Pubkey, loyalty_state: &LoyaltyState) -> Value {
+    let lots: Vec<Value> = loyalty_state
+        .lots()
+        .iter()
+        .map(|lot| json!({ "period": lot.period, "points": lot.points, "expires_at": lot.expires_at }))
+        .collect();
+    json!({
+        "address": address.to_string(),
+        "user": loyalty_state.user.to_string(),
+        "user_account": loyalty_state.user_account.to_string(),
+        "loyalty_points": loyalty_state.loyalty_points,
+        "last_activity": loyalty_state.last_activity,
+        "point_lots": lots,
+    })
+}
+
+fn voting_power_json(voting_power: &VotingPower) -> Value {
+    match *voting_power {
+        VotingPower::Tokens => json!("tokens"),
+        VotingPower::LoyaltyPoints => json!("loyalty_points"),
+        VotingPower::Tier => json!("tier"),
+        VotingPower::Quadratic => json!("quadratic"),
+        VotingPower::Blend {
+            token_bps,
+            points_bps,
+            tier_bps,
+        } => json!({ "blend": { "token_bps": token_bps, "points_bps": points_bps, "tier_bps": tier_bps } }),
+    }
+}
+
+pub fn proposal_json(address: &Pubkey, proposal: &Proposal) -> Value {
+    let vote_mode = match proposal.vote_mode {
+        VoteMode::SingleChoice => "single_choice",
+        VoteMode::Approval => "approval",
+        VoteMode::WeightedSplit => "weighted_split",
+    };
+    let status = match proposal.status {
+        ProposalStatus::Active => "active",
+        ProposalStatus::Succeeded => "succeeded",
+        ProposalStatus::Defeated => "defeated",
+        ProposalStatus::Executed => "executed",
+    };
+    let action = proposal.action.map(|action| match action {
+        ProposalAction::UpdateRewardParams {
+            redemption_rate,
+            referral_reward,
+        } => json!({ "update_reward_params": { "redemption_rate": redemption_rate, "referral_reward": referral_reward } }),
+        ProposalAction::SetTierThresholds { tier_thresholds } => {
+            json!({ "set_tier_thresholds": { "tier_thresholds": tier_thresholds } })
+        }
+        ProposalAction::SetEmissionBudget { emission_budget } => {
+            json!({ "set_emission_budget": { "emission_budget": emission_budget } })
+        }
+        ProposalAction::RotateAdmin { new_admin } => json!({ "rotate_admin": { "new_admin": new_admin.to_string() } }),
+    });
+    let options: Vec<Value> = proposal
+        .options
+        .iter()
+        .map(|option| json!({ "label": option.label, "votes": option.votes }))
+        .collect();
+    let instructions: Vec<Value> = proposal
+        .instructions
+        .iter()
+        .map(|instruction| {
+            json!({
+                "program_id": instruction.program_id.to_string(),
+                "accounts": instruction.accounts.iter().map(|meta| json!({
+                    "pubkey": meta.pubkey.to_string(),
+                    "is_signer": meta.is_signer,
+                    "is_writable": meta.is_writable,
+                })).collect::<Vec<_>>(),
+                "data": instruction.data,
+            })
+        })
+        .collect();
+    json!({
+        "address": address.to_string(),
+        "version": proposal.version,
+        "title": proposal.title,
+        "uri": proposal.uri,
+        "content_hash": proposal.content_hash.iter().map(|byte| format!("{byte:02x}")).collect::<String>(),
+        "creator": proposal.creator.to_string(),
+        "creator_account": proposal.creator_account.to_string(),
+        "status": status,
+        "vote_mode": vote_mode,
+        "voting_power": voting_power_json(&proposal.voting_power),
+        "votes_for": proposal.votes_for,
+        "votes_against": proposal.votes_against,
+        "turnout": proposal.turnout,
+        "options": options,
+        "winning_option": proposal.winning_option,
+        "deposit": proposal.deposit,
+        "created_at": proposal.created_at,
+        "voting_ends_at": proposal.voting_ends_at,
+        "executable_at": proposal.executable_at,
+        "action": action,
+        "instructions": instructions,
+    })
+}
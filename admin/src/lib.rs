@@ -0,0 +1,92 @@
+//! Library side of the `airdrop-admin` command-line tool: airdrop CSV
+//! parsing and batching, allocation of an airdrop from activity snapshots,
+//! JSON rendering of program accounts, and a runner that sends or simulates
+//! transactions over RPC.
+
+mod airdrop;
+mod allocation;
+mod inspect;
+mod runner;
+
+pub use airdrop::{airdrop_batches, parse_airdrop_csv, AirdropEntry};
+pub use allocation::{
+    allocation_leaf, verify_allocation_proof, Allocation, AllocationError, AllocationFormula, AllocationResult,
+    Snapshot, SnapshotUser, Weights,
+};
+pub use inspect::{config_json, loyalty_state_json, proposal_json, user_account_json};
+pub use runner::{Outcome, Runner};
+
+use anchor_lang::prelude::Pubkey;
+use early_adopter_airdrop::LoyaltyProgramError;
+use solana_sdk::transaction::TransactionError;
+
+#[derive(Debug)]
+pub enum AdminError {
+    // A line of the airdrop CSV couldn't be parsed. Lines count from 1.
+    Csv { line: usize, message: String },
+    Rpc(Box<solana_client::client_error::ClientError>),
+    // The transaction failed, in simulation or on-chain.
+    Transaction {
+        error: TransactionError,
+        program_error: Option<LoyaltyProgramError>,
+        logs: Vec<String>,
+    },
+    AccountNotFound(Pubkey),
+    Decode(anchor_lang::error::Error),
+    Io(std::io::Error),
+    Keypair(String),
+    Json(serde_json::Error),
+    Allocation(AllocationError),
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::Csv { line, message } => write!(f, "line {line}: {message}"),
+            AdminError::Rpc(err) => write!(f, "rpc error: {err}"),
+            AdminError::Transaction {
+                program_error: Some(program_error),
+                ..
+            } => write!(f, "transaction failed: {program_error}"),
+            AdminError::Transaction { error, .. } => write!(f, "transaction failed: {error}"),
+            AdminError::AccountNotFound(address) => write!(f, "account {address} not found"),
+            AdminError::Decode(err) => write!(f, "couldn't decode account: {err}"),
+            AdminError::Io(err) => write!(f, "{err}"),
+            AdminError::Keypair(message) => write!(f, "couldn't read keypair: {message}"),
+            AdminError::Json(err) => write!(f, "invalid JSON: {err}"),
+            AdminError::Allocation(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl From<solana_client::client_error::ClientError> for AdminError {
+    fn from(err: solana_client::client_error::ClientError) -> Self {
+        AdminError::Rpc(Box::new(err))
+    }
+}
+
+impl From<anchor_lang::error::Error> for AdminError {
+    fn from(err: anchor_lang::error::Error) -> Self {
+        AdminError::Decode(err)
+    }
+}
+
+impl From<std::io::Error> for AdminError {
+    fn from(err: std::io::Error) -> Self {
+        AdminError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for AdminError {
+    fn from(err: serde_json::Error) -> Self {
+        AdminError::Json(err)
+    }
+}
+
+impl From<AllocationError> for AdminError {
+    fn from(err: AllocationError) -> Self {
+        AdminError::Allocation(err)
+    }
+}
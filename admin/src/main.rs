@@ -0,0 +1,523 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use airdrop_admin::*;
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_spl::associated_token::get_associated_token_address;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use early_adopter_airdrop::{client, instruction, Config, Proposal, ProposalAction, VoteMode, VotingPower};
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+
+#[derive(Parser)]
+#[command(name = "airdrop-admin", version, about = "Administers the early adopter airdrop program")]
+struct Cli {
+    /// RPC endpoint; defaults to a local test validator.
+    #[arg(long, short = 'u', global = true, default_value = "http://127.0.0.1:8899")]
+    url: String,
+    /// Keypair that signs and pays; defaults to ~/.config/solana/id.json.
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<PathBuf>,
+    /// Simulate transactions and print their compute units instead of sending them.
+    #[arg(long, global = true)]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the reward token mint, with the keypair as mint and freeze
+    /// authority.
+    InitMint {
+        #[arg(long, default_value_t = 9)]
+        decimals: u8,
+        /// Keypair for the mint address; a new one is generated by default.
+        #[arg(long)]
+        mint_keypair: Option<PathBuf>,
+    },
+    /// Create the program config, with the keypair, which must be the program's
+    /// upgrade authority, as admin.
+    InitConfig {
+        #[arg(long, value_parser = parse_tier_thresholds, default_value = "100,1000,10000,100000")]
+        tier_thresholds: [u64; 4],
+    },
+    /// Create a user account for a wallet.
+    RegisterUser { wallet: Pubkey },
+    /// Mint rewards to the `wallet,user_account,amount` rows of a CSV file.
+    Airdrop {
+        #[arg(long)]
+        mint: Pubkey,
+        csv: PathBuf,
+        /// Recipients per transaction.
+        #[arg(long, default_value_t = 5)]
+        batch_size: usize,
+    },
+    /// Compute an airdrop from a JSON activity snapshot and formula, writing the
+    /// allocations as an airdrop CSV and printing their Merkle root.
+    Allocate {
+        #[arg(long)]
+        snapshot: PathBuf,
+        #[arg(long)]
+        formula: PathBuf,
+        /// Where to write the CSV; printed to stdout by default.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Credit loyalty points to a user, optionally counting them towards a
+    /// season. The keypair must be the config admin.
+    AwardPoints {
+        user_account: Pubkey,
+        points: u64,
+        #[arg(long)]
+        season: Option<u32>,
+    },
+    /// Create a proposal from the keypair's user account.
+    CreateProposal(CreateProposalArgs),
+    /// Tally a proposal whose voting period has ended, settling its deposit.
+    FinalizeProposal { proposal: Pubkey },
+    /// Execute a passed proposal once its timelock has elapsed.
+    ExecuteProposal { proposal: Pubkey },
+    /// Update admin-controlled config parameters. Unset values keep their current value.
+    SetParams(SetParamsArgs),
+    /// Stop all minting instructions.
+    Pause,
+    /// Resume minting instructions.
+    Unpause,
+    /// Print an account as JSON.
+    Inspect {
+        #[command(subcommand)]
+        target: InspectTarget,
+    },
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("action").args(["redemption_rate", "tier_thresholds", "emission_budget", "new_admin"])))]
+struct CreateProposalArgs {
+    #[arg(long)]
+    proposer_account: Pubkey,
+    #[arg(long)]
+    title: String,
+    #[arg(long, default_value = "")]
+    uri: String,
+    /// File holding the full proposal text; its SHA-256 is stored on-chain.
+    #[arg(long)]
+    content: Option<PathBuf>,
+    /// tokens, loyalty-points, tier, quadratic, or blend:TOKEN_BPS,POINTS_BPS,TIER_BPS.
+    #[arg(long, value_parser = parse_voting_power, default_value = "tokens")]
+    voting_power: VotingPower,
+    /// Option labels, making the proposal a poll.
+    #[arg(long, value_delimiter = ',')]
+    options: Vec<String>,
+    #[arg(long, value_enum, default_value_t = ModeArg::SingleChoice)]
+    vote_mode: ModeArg,
+    /// Update the reward parameters when the proposal passes. A proposal
+    /// carries at most one action.
+    #[arg(long, requires = "referral_reward")]
+    redemption_rate: Option<u64>,
+    #[arg(long, requires = "redemption_rate")]
+    referral_reward: Option<u64>,
+    /// Set the four tier thresholds when the proposal passes.
+    #[arg(long, value_parser = parse_tier_thresholds)]
+    tier_thresholds: Option<[u64; 4]>,
+    /// Set the emission budget when the proposal passes.
+    #[arg(long)]
+    emission_budget: Option<u64>,
+    /// Hand the admin role to another wallet when the proposal passes.
+    #[arg(long)]
+    new_admin: Option<Pubkey>,
+}
+
+#[derive(Args)]
+struct SetParamsArgs {
+    #[arg(long)]
+    voting_period: Option<i64>,
+    #[arg(long)]
+    timelock_delay: Option<i64>,
+    #[arg(long)]
+    quorum: Option<u64>,
+    /// How votes on proposals with an action or instructions are weighed,
+    /// in the same form as `create-proposal --voting-power`.
+    #[arg(long, value_parser = parse_voting_power)]
+    executive_voting_power: Option<VotingPower>,
+    #[arg(long)]
+    redemption_rate: Option<u64>,
+    #[arg(long)]
+    referral_reward: Option<u64>,
+    #[arg(long)]
+    redeem_window: Option<i64>,
+    #[arg(long)]
+    global_redeem_limit: Option<u64>,
+    #[arg(long)]
+    user_redeem_limit: Option<u64>,
+    #[arg(long)]
+    guardian: Option<Pubkey>,
+    #[arg(long)]
+    governance_mint: Option<Pubkey>,
+    #[arg(long)]
+    proposal_min_tokens: Option<u64>,
+    #[arg(long)]
+    proposal_min_tier: Option<u8>,
+    #[arg(long)]
+    proposal_deposit: Option<u64>,
+    #[arg(long)]
+    max_active_proposals: Option<u8>,
+}
+
+#[derive(Subcommand)]
+enum InspectTarget {
+    Config,
+    /// A user account together with its loyalty state.
+    User { user_account: Pubkey },
+    Proposal { proposal: Pubkey },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    SingleChoice,
+    Approval,
+    WeightedSplit,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            if let AdminError::Transaction { logs, .. } = &err {
+                for log in logs {
+                    eprintln!("  {log}");
+                }
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Four comma-separated point thresholds, one per tier above the first.
+fn parse_tier_thresholds(value: &str) -> Result<[u64; 4], String> {
+    let thresholds = value
+        .split(',')
+        .map(|threshold| threshold.trim().parse::<u64>().map_err(|err| format!("{threshold}: {err}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    thresholds
+        .try_into()
+        .map_err(|thresholds: Vec<u64>| format!("expected 4 thresholds, found {}", thresholds.len()))
+}
+
+fn parse_voting_power(value: &str) -> Result<VotingPower, String> {
+    match value.split_once(':') {
+        None => match value {
+            "tokens" => Ok(VotingPower::Tokens),
+            "loyalty-points" => Ok(VotingPower::LoyaltyPoints),
+            "tier" => Ok(VotingPower::Tier),
+            "quadratic" => Ok(VotingPower::Quadratic),
+            _ => Err(format!("unknown voting power {value}")),
+        },
+        Some(("blend", weights)) => {
+            let weights = weights
+                .split(',')
+                .map(|weight| weight.trim().parse::<u16>().map_err(|err| format!("{weight}: {err}")))
+                .collect::<Result<Vec<_>, _>>()?;
+            match weights[..] {
+                [token_bps, points_bps, tier_bps] => Ok(VotingPower::Blend {
+                    token_bps,
+                    points_bps,
+                    tier_bps,
+                }),
+                _ => Err(format!("expected 3 blend weights, found {}", weights.len())),
+            }
+        }
+        Some(_) => Err(format!("unknown voting power {value}")),
+    }
+}
+
+fn read_keypair(path: &Path) -> Result<Keypair, AdminError> {
+    read_keypair_file(path).map_err(|err| AdminError::Keypair(format!("{}: {err}", path.display())))
+}
+
+fn default_keypair_path() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_default();
+    Path::new(&home).join(".config/solana/id.json")
+}
+
+fn report(outcome: Outcome) {
+    match outcome {
+        Outcome::Sent(signature) => println!("signature: {signature}"),
+        Outcome::Simulated { units_consumed, logs } => {
+            match units_consumed {
+                Some(units) => println!("simulated: {units} compute units"),
+                None => println!("simulated"),
+            }
+            for log in logs {
+                println!("  {log}");
+            }
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), AdminError> {
+    // Inspecting only reads accounts, so it doesn't need the keypair.
+    if let Command::Inspect { target } = &cli.command {
+        let runner = Runner::new(&cli.url, Keypair::new(), false);
+        let json = inspect(&runner, target)?;
+        println!("{}", serde_json::to_string_pretty(&json).expect("JSON values serialize"));
+        return Ok(());
+    }
+    // Allocating works offline from files.
+    if let Command::Allocate { snapshot, formula, out } = &cli.command {
+        return allocate(snapshot, formula, out.as_deref());
+    }
+    let payer = read_keypair(&cli.keypair.unwrap_or_else(default_keypair_path))?;
+    let runner = Runner::new(&cli.url, payer, cli.dry_run);
+    let payer = runner.payer();
+
+    match cli.command {
+        Command::InitMint { decimals, mint_keypair } => {
+            let mint = match mint_keypair {
+                Some(path) => read_keypair(&path)?,
+                None => Keypair::new(),
+            };
+            println!("mint: {}", mint.pubkey());
+            report(runner.execute(&[client::initialize(&mint.pubkey(), &payer, decimals)], &[&mint])?);
+        }
+        Command::InitConfig { tier_thresholds } => {
+            println!("config: {}", client::config_address());
+            report(runner.execute(&[client::initialize_config(&payer, tier_thresholds)], &[])?);
+        }
+        Command::RegisterUser { wallet } => {
+            let user_account = Keypair::new();
+            println!("user account: {}", user_account.pubkey());
+            let instruction = client::initialize_user(&user_account.pubkey(), &payer, &wallet);
+            report(runner.execute(&[instruction], &[&user_account])?);
+        }
+        Command::Airdrop { mint, csv, batch_size } => {
+            let entries = parse_airdrop_csv(&std::fs::read_to_string(csv)?)?;
+            let batches = airdrop_batches(&mint, &payer, &entries, batch_size);
+            for (index, instruction) in batches.iter().enumerate() {
+                println!("batch {} of {}", index + 1, batches.len());
+                report(runner.execute(std::slice::from_ref(instruction), &[])?);
+            }
+        }
+        Command::AwardPoints {
+            user_account,
+            points,
+            season,
+        } => {
+            let user = runner.fetch_loyalty_state(&user_account)?.user;
+            let season = season.map(client::season_address);
+            let instruction = client::track_loyalty(&payer, &user_account, &user, season.as_ref(), points);
+            report(runner.execute(&[instruction], &[])?);
+        }
+        Command::CreateProposal(args) => create_proposal(&runner, args)?,
+        Command::FinalizeProposal { proposal } => {
+            let config: Config = runner.fetch(&client::config_address())?;
+            let proposal_info = runner.fetch_proposal(&proposal)?;
+            // A deposit is refunded to the creator or slashed to the treasury,
+            // in the governance mint.
+            let (deposit_vault, creator_tokens, treasury_tokens) = if proposal_info.deposit != 0 {
+                (
+                    Some(config.deposit_vault),
+                    Some(get_associated_token_address(&proposal_info.creator, &config.governance_mint)),
+                    Some(get_associated_token_address(&client::treasury_address(), &config.governance_mint)),
+                )
+            } else {
+                (None, None, None)
+            };
+            let instruction = client::finalize_proposal(
+                &proposal,
+                &proposal_info.creator_account,
+                deposit_vault.as_ref(),
+                creator_tokens.as_ref(),
+                treasury_tokens.as_ref(),
+            );
+            report(runner.execute(&[instruction], &[])?);
+        }
+        Command::ExecuteProposal { proposal } => {
+            let proposal_info = runner.fetch_proposal(&proposal)?;
+            let instruction = client::execute_proposal(&proposal, proposal_accounts(&proposal_info));
+            report(runner.execute(&[instruction], &[])?);
+        }
+        Command::SetParams(args) => {
+            let config: Config = runner.fetch(&client::config_address())?;
+            let instructions = set_params(&payer, &config, args);
+            if instructions.is_empty() {
+                println!("nothing to update");
+                return Ok(());
+            }
+            report(runner.execute(&instructions, &[])?);
+        }
+        Command::Pause => report(runner.execute(&[client::set_paused(&payer, true)], &[])?),
+        Command::Unpause => report(runner.execute(&[client::set_paused(&payer, false)], &[])?),
+        Command::Inspect { .. } | Command::Allocate { .. } => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+fn create_proposal(runner: &Runner, args: CreateProposalArgs) -> Result<(), AdminError> {
+    let payer = runner.payer();
+    let content_hash = match &args.content {
+        Some(path) => solana_sdk::hash::hash(&std::fs::read(path)?).to_bytes(),
+        None => [0; 32],
+    };
+    // The "action" group lets at most one of these through.
+    let action = args
+        .redemption_rate
+        .zip(args.referral_reward)
+        .map(|(redemption_rate, referral_reward)| ProposalAction::UpdateRewardParams {
+            redemption_rate,
+            referral_reward,
+        })
+        .or(args.tier_thresholds.map(|tier_thresholds| ProposalAction::SetTierThresholds { tier_thresholds }))
+        .or(args.emission_budget.map(|emission_budget| ProposalAction::SetEmissionBudget { emission_budget }))
+        .or(args.new_admin.map(|new_admin| ProposalAction::RotateAdmin { new_admin }));
+    let config: Config = runner.fetch(&client::config_address())?;
+
+    // The token account shows the proposer's balance and pays the deposit.
+    let proposer_tokens = (config.proposal_min_tokens != 0 || config.proposal_deposit != 0)
+        .then(|| get_associated_token_address(&payer, &config.governance_mint));
+    let deposit_vault = (config.proposal_deposit != 0).then_some(config.deposit_vault);
+    let proposal = Keypair::new();
+    let instruction = client::create_proposal(
+        &proposal.pubkey(),
+        &payer,
+        &args.proposer_account,
+        proposer_tokens.as_ref(),
+        deposit_vault.as_ref(),
+        instruction::CreateProposal {
+            title: args.title,
+            uri: args.uri,
+            content_hash,
+            action,
+            instructions: vec![],
+            vote_mode: match args.vote_mode {
+                ModeArg::SingleChoice => VoteMode::SingleChoice,
+                ModeArg::Approval => VoteMode::Approval,
+                ModeArg::WeightedSplit => VoteMode::WeightedSplit,
+            },
+            option_labels: args.options,
+            voting_power: args.voting_power,
+        },
+    );
+    println!("proposal: {}", proposal.pubkey());
+    report(runner.execute(&[instruction], &[&proposal])?);
+    Ok(())
+}
+
+// One instruction per group of parameters that has a value set, filling the
+// group's other parameters from the current config.
+fn set_params(admin: &Pubkey, config: &Config, args: SetParamsArgs) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    if args.voting_period.is_some()
+        || args.timelock_delay.is_some()
+        || args.quorum.is_some()
+        || args.executive_voting_power.is_some()
+    {
+        instructions.push(client::set_governance_params(
+            admin,
+            args.voting_period.unwrap_or(config.voting_period),
+            args.timelock_delay.unwrap_or(config.timelock_delay),
+            args.quorum.unwrap_or(config.quorum),
+            args.executive_voting_power.unwrap_or(config.executive_voting_power),
+        ));
+    }
+    if args.redemption_rate.is_some() || args.referral_reward.is_some() {
+        instructions.push(client::set_reward_params(
+            admin,
+            args.redemption_rate.unwrap_or(config.redemption_rate),
+            args.referral_reward.unwrap_or(config.referral_reward),
+        ));
+    }
+    if args.redeem_window.is_some() || args.global_redeem_limit.is_some() || args.user_redeem_limit.is_some() {
+        instructions.push(client::set_redemption_limits(
+            admin,
+            args.redeem_window.unwrap_or(config.redeem_window),
+            args.global_redeem_limit.unwrap_or(config.global_redeem_limit),
+            args.user_redeem_limit.unwrap_or(config.user_redeem_limit),
+        ));
+    }
+    if let Some(guardian) = args.guardian {
+        instructions.push(client::set_guardian(admin, &guardian));
+    }
+    if args.governance_mint.is_some()
+        || args.proposal_min_tokens.is_some()
+        || args.proposal_min_tier.is_some()
+        || args.proposal_deposit.is_some()
+        || args.max_active_proposals.is_some()
+    {
+        instructions.push(client::set_proposal_requirements(
+            admin,
+            &args.governance_mint.unwrap_or(config.governance_mint),
+            args.proposal_min_tokens.unwrap_or(config.proposal_min_tokens),
+            args.proposal_min_tier.unwrap_or(config.proposal_min_tier),
+            args.proposal_deposit.unwrap_or(config.proposal_deposit),
+            args.max_active_proposals.unwrap_or(config.max_active_proposals),
+        ));
+    }
+    instructions
+}
+
+// The stored instructions' programs and accounts. The treasury is passed by
+// `execute_proposal` itself and signs through the program, so it's left out.
+fn proposal_accounts(proposal: &Proposal) -> Vec<AccountMeta> {
+    let treasury = client::treasury_address();
+    let mut metas: Vec<AccountMeta> = Vec::new();
+    let mut add = |pubkey: Pubkey, is_writable: bool| {
+        if pubkey == treasury {
+            return;
+        }
+        match metas.iter_mut().find(|meta| meta.pubkey == pubkey) {
+            Some(meta) => meta.is_writable |= is_writable,
+            None => metas.push(AccountMeta {
+                pubkey,
+                is_signer: false,
+                is_writable,
+            }),
+        }
+    };
+    for instruction in &proposal.instructions {
+        add(instruction.program_id, false);
+        for meta in &instruction.accounts {
+            add(meta.pubkey, meta.is_writable);
+        }
+    }
+    metas
+}
+
+fn inspect(runner: &Runner, target: &InspectTarget) -> Result<serde_json::Value, AdminError> {
+    match target {
+        InspectTarget::Config => {
+            let address = client::config_address();
+            Ok(config_json(&address, &runner.fetch(&address)?))
+        }
+        InspectTarget::User { user_account } => {
+            let user_info = runner.fetch_user_account(user_account)?;
+            let loyalty_state = runner.fetch_loyalty_state(user_account)?;
+            Ok(serde_json::json!({
+                "user_account": user_account_json(user_account, &user_info),
+                "loyalty_state": loyalty_state_json(&client::loyalty_address(user_account), &loyalty_state),
+            }))
+        }
+        InspectTarget::Proposal { proposal } => Ok(proposal_json(proposal, &runner.fetch_proposal(proposal)?)),
+    }
+}
+
+fn allocate(snapshot: &Path, formula: &Path, out: Option<&Path>) -> Result<(), AdminError> {
+    let snapshot: Snapshot = serde_json::from_str(&std::fs::read_to_string(snapshot)?)?;
+    let formula: AllocationFormula = serde_json::from_str(&std::fs::read_to_string(formula)?)?;
+    let result = formula.allocate(&snapshot)?;
+    let csv = result.to_csv();
+    match out {
+        Some(path) => std::fs::write(path, csv)?,
+        None => print!("{csv}"),
+    }
+    let root: String = result.merkle_root().iter().map(|byte| format!("{byte:02x}")).collect();
+    eprintln!("recipients: {}", result.allocations.len());
+    eprintln!("total: {}", result.total);
+    if result.unallocated != 0 {
+        eprintln!("unallocated: {}", result.unallocated);
+    }
+    eprintln!("merkle root: {root}");
+    Ok(())
+}
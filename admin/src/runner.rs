@@ -0,0 +1,114 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::AccountDeserialize;
+use early_adopter_airdrop::{client, LoyaltyState, Proposal, UserAccount};
+use solana_client::client_error::ClientErrorKind;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+use crate::AdminError;
+
+pub enum Outcome {
+    Sent(Signature),
+    Simulated { units_consumed: Option<u64>, logs: Vec<String> },
+}
+
+// Sends transactions paid for and signed by `payer`, or only simulates them
+// in dry-run mode.
+pub struct Runner {
+    pub rpc: RpcClient,
+    pub payer: Keypair,
+    pub dry_run: bool,
+}
+
+impl Runner {
+    pub fn new(url: &str, payer: Keypair, dry_run: bool) -> Self {
+        Self {
+            rpc: RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed()),
+            payer,
+            dry_run,
+        }
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+
+    // `signers` are any keypairs the instructions need besides the payer.
+    pub fn execute(&self, instructions: &[Instruction], signers: &[&Keypair]) -> Result<Outcome, AdminError> {
+        let blockhash = self.rpc.get_latest_blockhash()?;
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend(signers);
+        let tx = Transaction::new_signed_with_payer(instructions, Some(&self.payer.pubkey()), &all_signers, blockhash);
+        if self.dry_run {
+            let config = RpcSimulateTransactionConfig {
+                commitment: Some(self.rpc.commitment()),
+                ..RpcSimulateTransactionConfig::default()
+            };
+            let simulation = self.rpc.simulate_transaction_with_config(&tx, config)?.value;
+            let logs = simulation.logs.unwrap_or_default();
+            if let Some(error) = simulation.err {
+                return Err(transaction_error(error, logs));
+            }
+            return Ok(Outcome::Simulated {
+                units_consumed: simulation.units_consumed,
+                logs,
+            });
+        }
+        match self.rpc.send_and_confirm_transaction(&tx) {
+            Ok(signature) => Ok(Outcome::Sent(signature)),
+            Err(err) => match err.get_transaction_error() {
+                Some(error) => Err(transaction_error(error, preflight_logs(&err.kind))),
+                None => Err(err.into()),
+            },
+        }
+    }
+
+    pub fn fetch<T: AccountDeserialize>(&self, address: &Pubkey) -> Result<T, AdminError> {
+        Ok(client::decode_account(&self.account_data(address)?)?)
+    }
+
+    pub fn fetch_user_account(&self, user_account: &Pubkey) -> Result<UserAccount, AdminError> {
+        Ok(client::decode_user_account(&self.account_data(user_account)?)?)
+    }
+
+    pub fn fetch_proposal(&self, proposal: &Pubkey) -> Result<Proposal, AdminError> {
+        Ok(client::decode_proposal(&self.account_data(proposal)?)?)
+    }
+
+    pub fn fetch_loyalty_state(&self, user_account: &Pubkey) -> Result<LoyaltyState, AdminError> {
+        let data = self.account_data(&client::loyalty_address(user_account))?;
+        Ok(client::decode_loyalty_state(&data)?)
+    }
+
+    fn account_data(&self, address: &Pubkey) -> Result<Vec<u8>, AdminError> {
+        self.rpc
+            .get_account_with_commitment(address, self.rpc.commitment())?
+            .value
+            .map(|account| account.data)
+            .ok_or(AdminError::AccountNotFound(*address))
+    }
+}
+
+fn transaction_error(error: TransactionError, logs: Vec<String>) -> AdminError {
+    AdminError::Transaction {
+        program_error: client::transaction_program_error(&error),
+        error,
+        logs,
+    }
+}
+
+// A transaction rejected in preflight carries the simulation's logs.
+fn preflight_logs(kind: &ClientErrorKind) -> Vec<String> {
+    match kind {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+            ..
+        }) => result.logs.clone().unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,365 @@
+use airdrop_admin::*;
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::AnchorDeserialize;
+use anchor_spl::associated_token::get_associated_token_address;
+use early_adopter_airdrop::*;
+use serde_json::json;
+
+fn key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+#[test]
+fn test_parse_airdrop_csv() {
+    let csv = format!(
+        "wallet,user_account,amount\n\
+         {},{},1000\n\
+         \n\
+         # second wave\n\
+         {} , {} , 250\n",
+        key(1),
+        key(2),
+        key(3),
+        key(4)
+    );
+    let entries = parse_airdrop_csv(&csv).unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            AirdropEntry {
+                wallet: key(1),
+                user_account: key(2),
+                amount: 1000,
+            },
+            AirdropEntry {
+                wallet: key(3),
+                user_account: key(4),
+                amount: 250,
+            },
+        ]
+    );
+
+    // Errors point at the offending line
+    let err = parse_airdrop_csv(&format!("{},{},1000\n{},{},-5\n", key(1), key(2), key(3), key(4))).unwrap_err();
+    assert!(matches!(err, AdminError::Csv { line: 2, .. }));
+    assert_eq!(err.to_string(), "line 2: invalid amount -5");
+    let err = parse_airdrop_csv(&format!("{},1000\n", key(1))).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: expected 3 fields, found 2");
+    let err = parse_airdrop_csv("not-a-key,also-not,1\n").unwrap_err();
+    assert_eq!(err.to_string(), "line 1: invalid wallet not-a-key");
+}
+
+#[test]
+fn test_airdrop_batches() {
+    let (mint, authority) = (key(50), key(51));
+    let entries: Vec<_> = (0..7u8)
+        .map(|index| AirdropEntry {
+            wallet: key(index * 2 + 1),
+            user_account: key(index * 2 + 2),
+            amount: 100 * (index as u64 + 1),
+        })
+        .collect();
+
+    let batches = airdrop_batches(&mint, &authority, &entries, 3);
+    assert_eq!(batches.len(), 3);
+    let amounts: Vec<Vec<u64>> = batches
+        .iter()
+        .map(|batch| {
+            instruction::BatchRewardEarlyAdopters::deserialize(&mut &batch.data[8..])
+                .unwrap()
+                .amounts
+        })
+        .collect();
+    assert_eq!(amounts, vec![vec![100, 200, 300], vec![400, 500, 600], vec![700]]);
+
+    // Six fixed accounts, then four per recipient
+    let last = &batches[2];
+    assert_eq!(last.accounts.len(), 6 + 4);
+    assert_eq!(last.accounts[6].pubkey, entries[6].user_account);
+    assert_eq!(last.accounts[7].pubkey, entries[6].wallet);
+    assert_eq!(last.accounts[8].pubkey, get_associated_token_address(&entries[6].wallet, &mint));
+    assert_eq!(last.accounts[9].pubkey, client::early_adopter_reward_address(&entries[6].wallet));
+    assert!(last.accounts.iter().any(|meta| meta.pubkey == authority && meta.is_signer));
+}
+
+#[test]
+fn test_proposal_json() {
+    let proposal = Proposal {
+        version: 1,
+        title: "Raise referral reward".to_string(),
+        uri: "https://example.com/proposals/1".to_string(),
+        content_hash: [0xab; 32],
+        votes_for: 90,
+        votes_against: 10,
+        turnout: 100,
+        vote_mode: VoteMode::SingleChoice,
+        options: vec![],
+        winning_option: None,
+        voting_power: VotingPower::Blend {
+            token_bps: 5000,
+            points_bps: 5000,
+            tier_bps: 0,
+        },
+        creator: key(1),
+        creator_account: key(2),
+        deposit: 0,
+        created_at: 1_760_000_000,
+        voting_ends_at: 1_760_259_200,
+        status: ProposalStatus::Succeeded,
+        executable_at: 1_760_345_600,
+        reserved: [0; ACCOUNT_RESERVED_LEN],
+        action: Some(ProposalAction::UpdateRewardParams {
+            redemption_rate: 100,
+            referral_reward: 150,
+        }),
+        instructions: vec![],
+    };
+    let json = proposal_json(&key(5), &proposal);
+    assert_eq!(json["address"], key(5).to_string());
+    assert_eq!(json["status"], "succeeded");
+    assert_eq!(json["content_hash"], "ab".repeat(32));
+    assert_eq!(json["voting_power"], json!({ "blend": { "token_bps": 5000, "points_bps": 5000, "tier_bps": 0 } }));
+    assert_eq!(
+        json["action"],
+        json!({ "update_reward_params": { "redemption_rate": 100, "referral_reward": 150 } })
+    );
+    assert_eq!(json["winning_option"], json!(null));
+    assert_eq!((json["votes_for"].as_u64(), json["votes_against"].as_u64()), (Some(90), Some(10)));
+}
+
+#[test]
+fn test_user_account_json() {
+    let user_account = UserAccount {
+        version: USER_ACCOUNT_VERSION,
+        user: key(1),
+        loyalty_tier: 2,
+        tokens_held: 0,
+        badges: 0b101,
+        badges_minted: 0,
+        redemptions: 1,
+        referrals: 0,
+        votes_cast: 3,
+        active_proposals: 0,
+        redemption_window: RateWindow::default(),
+        username: Pubkey::default(),
+        early_adopter_rewarded: true,
+        reserved: [0; ACCOUNT_RESERVED_LEN - 1],
+        name: "Alice".to_string(),
+        bio: String::new(),
+        avatar_uri: None,
+        socials: vec![SocialHandle {
+            platform: SocialPlatform::Github,
+            handle: "alice".to_string(),
+        }],
+    };
+    let json = user_account_json(&key(2), &user_account);
+    assert_eq!(json["user"], key(1).to_string());
+    assert_eq!(json["username"], json!(null));
+    assert_eq!(json["early_adopter_rewarded"], true);
+    assert_eq!(json["socials"], json!([{ "platform": "github", "handle": "alice" }]));
+}
+
+fn snapshot_user(byte: u8, loyalty_points: u64, loyalty_tier: u8, registered_at: i64, referrals: u32) -> SnapshotUser {
+    SnapshotUser {
+        wallet: key(byte),
+        user_account: key(byte + 100),
+        loyalty_points,
+        loyalty_tier,
+        registered_at,
+        referrals,
+    }
+}
+
+fn amounts(result: &AllocationResult) -> Vec<(Pubkey, u64)> {
+    result
+        .allocations
+        .iter()
+        .map(|allocation| (allocation.wallet, allocation.amount))
+        .collect()
+}
+
+#[test]
+fn test_allocation_formula() {
+    const DAY: i64 = 86_400;
+    let taken_at = 1_760_000_000;
+    let snapshot: Snapshot = serde_json::from_value(json!({
+        "taken_at": taken_at,
+        "users": [
+            { "wallet": key(1).to_string(), "user_account": key(101).to_string(), "loyalty_points": 500,
+              "loyalty_tier": 2, "registered_at": taken_at - 10 * DAY - 100, "referrals": 1 },
+            { "wallet": key(2).to_string(), "user_account": key(102).to_string(), "loyalty_points": 50,
+              "loyalty_tier": 0, "registered_at": taken_at - DAY, "referrals": 0 },
+            { "wallet": key(3).to_string(), "user_account": key(103).to_string(), "loyalty_points": 2000,
+              "loyalty_tier": 3, "registered_at": taken_at - 40 * DAY, "referrals": 4 },
+            { "wallet": key(4).to_string(), "user_account": key(104).to_string(), "loyalty_points": 100,
+              "loyalty_tier": 0, "registered_at": taken_at, "referrals": 0 },
+        ],
+    }))
+    .unwrap();
+    assert_eq!(snapshot.users[0], snapshot_user(1, 500, 2, taken_at - 10 * DAY - 100, 1));
+
+    // Omitted fields default to zero or no limit
+    let formula: AllocationFormula = serde_json::from_str(
+        r#"{
+            "weights": { "points": 1, "tier": 100, "tenure_day": 10, "referral": 50 },
+            "floor": 150,
+            "cap": 2000,
+            "min_points": 100
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(formula.score(&snapshot.users[0], taken_at), Ok(500 + 200 + 100 + 50));
+    assert!(!formula.is_eligible(&snapshot.users[1], taken_at));
+
+    // Scores are clamped between the floor and the cap
+    let result = formula.allocate(&snapshot).unwrap();
+    assert_eq!(amounts(&result), vec![(key(1), 850), (key(3), 2000), (key(4), 150)]);
+    assert_eq!((result.total, result.unallocated), (3000, 0));
+
+    // Snapshot order doesn't matter
+    let mut reversed = snapshot.clone();
+    reversed.users.reverse();
+    assert_eq!(formula.allocate(&reversed).unwrap(), result);
+
+    let veterans = AllocationFormula {
+        min_tenure_days: 10,
+        ..formula.clone()
+    };
+    assert_eq!(amounts(&veterans.allocate(&snapshot).unwrap()), vec![(key(1), 850), (key(3), 2000)]);
+
+    let mut duplicated = snapshot.clone();
+    duplicated.users.push(snapshot.users[2].clone());
+    assert_eq!(formula.allocate(&duplicated), Err(AllocationError::DuplicateUser(key(103))));
+
+    // A wallet gets one allocation, so a second eligible user account for it
+    // is rejected, while an ineligible one is ignored
+    let mut second_account = snapshot.clone();
+    second_account.users.push(SnapshotUser {
+        user_account: key(105),
+        ..snapshot.users[2].clone()
+    });
+    assert_eq!(formula.allocate(&second_account), Err(AllocationError::DuplicateWallet(key(3))));
+    second_account.users[4].loyalty_points = 0;
+    assert_eq!(formula.allocate(&second_account).unwrap(), result);
+
+    // Typos in a formula are rejected rather than ignored
+    assert!(serde_json::from_str::<AllocationFormula>(r#"{ "min_point": 100 }"#).is_err());
+}
+
+#[test]
+fn test_allocation_pool() {
+    let points_only = AllocationFormula {
+        weights: Weights {
+            points: 1,
+            ..Weights::default()
+        },
+        ..AllocationFormula::default()
+    };
+    let snapshot = |points: &[u64]| Snapshot {
+        taken_at: 0,
+        users: points
+            .iter()
+            .enumerate()
+            .map(|(index, &points)| snapshot_user(index as u8 + 1, points, 0, 0, 0))
+            .collect(),
+    };
+
+    // What the capped user can't take goes to the others
+    let capped = AllocationFormula {
+        pool: Some(1000),
+        cap: Some(500),
+        ..points_only.clone()
+    };
+    let result = capped.allocate(&snapshot(&[800, 100, 100])).unwrap();
+    assert_eq!(amounts(&result), vec![(key(1), 500), (key(2), 250), (key(3), 250)]);
+    assert_eq!((result.total, result.unallocated), (1000, 0));
+
+    // Rounding leftovers go to the earliest user on a tie
+    let even = AllocationFormula {
+        pool: Some(100),
+        ..points_only.clone()
+    };
+    let result = even.allocate(&snapshot(&[1, 1, 1])).unwrap();
+    assert_eq!(amounts(&result), vec![(key(1), 34), (key(2), 33), (key(3), 33)]);
+
+    // Floors come out of the pool first, even for users without a score
+    let floored = AllocationFormula {
+        pool: Some(100),
+        floor: 10,
+        ..points_only.clone()
+    };
+    let result = floored.allocate(&snapshot(&[0, 1, 3])).unwrap();
+    assert_eq!(amounts(&result), vec![(key(1), 10), (key(2), 28), (key(3), 62)]);
+    assert_eq!(result.total, 100);
+
+    let result = AllocationFormula {
+        pool: Some(1000),
+        cap: Some(100),
+        ..points_only.clone()
+    }
+    .allocate(&snapshot(&[5, 7]))
+    .unwrap();
+    assert_eq!((result.total, result.unallocated), (200, 800));
+
+    assert_eq!(
+        AllocationFormula {
+            pool: Some(20),
+            ..floored
+        }
+        .allocate(&snapshot(&[1, 2, 3])),
+        Err(AllocationError::PoolBelowFloors { required: 30, pool: 20 })
+    );
+}
+
+#[test]
+fn test_allocation_merkle_tree() {
+    let result = |count: u8| {
+        let allocations: Vec<_> = (1..=count)
+            .map(|byte| Allocation {
+                wallet: key(byte),
+                user_account: key(byte + 100),
+                amount: byte as u64 * 1000,
+            })
+            .collect();
+        AllocationResult {
+            total: allocations.iter().map(|allocation| allocation.amount).sum(),
+            allocations,
+            unallocated: 0,
+        }
+    };
+    assert_eq!(result(0).merkle_root(), [0; 32]);
+    assert_eq!(result(1).merkle_root(), allocation_leaf(&result(1).allocations[0]));
+
+    let pair = result(2);
+    let mut leaves = [allocation_leaf(&pair.allocations[0]), allocation_leaf(&pair.allocations[1])];
+    leaves.sort();
+    let expected = anchor_lang::solana_program::keccak::hashv(&[&[1], &leaves[0], &leaves[1]]).to_bytes();
+    assert_eq!(pair.merkle_root(), expected);
+
+    for count in 1..=7 {
+        let result = result(count);
+        let root = result.merkle_root();
+        for (index, allocation) in result.allocations.iter().enumerate() {
+            let proof = result.merkle_proof(index);
+            assert!(verify_allocation_proof(allocation, &proof, &root));
+            let inflated = Allocation {
+                amount: allocation.amount + 1,
+                ..*allocation
+            };
+            assert!(!verify_allocation_proof(&inflated, &proof, &root));
+        }
+    }
+
+    // The CSV is what the airdrop command reads
+    let result = result(3);
+    let entries = parse_airdrop_csv(&result.to_csv()).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(
+        entries[2],
+        AirdropEntry {
+            wallet: key(3),
+            user_account: key(103),
+            amount: 3000,
+        }
+    );
+}
@@ -0,0 +1,1122 @@
+//! Instruction builders, PDA helpers, account decoding and error lookup for
+//! off-chain callers, so services don't assemble account lists by hand.
+//!
+//! Builders take the wallets and accounts an instruction needs and derive
+//! every PDA. Signers are marked from the program's account structs; the
+//! caller only has to sign with them.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{Instruction, InstructionError};
+use anchor_lang::solana_program::{bpf_loader_upgradeable, system_program, sysvar};
+use anchor_lang::{Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token;
+use solana_sdk::transaction::TransactionError;
+
+use crate::{
+    accounts, instruction, BadgeKind, CompressedUser, LoyaltyEntry, LoyaltyProgramError, LoyaltyState, Proposal,
+    SocialHandle, UserAccount, VotingPower, LEADERBOARD_SIZE,
+};
+
+fn address(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &crate::ID).0
+}
+
+pub fn config_address() -> Pubkey {
+    address(&[b"config"])
+}
+
+// The upgradeable loader's program data account, which records the upgrade
+// authority.
+pub fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[crate::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+pub fn treasury_address() -> Pubkey {
+    address(&[b"treasury"])
+}
+
+pub fn deposit_vault_address(governance_mint: &Pubkey) -> Pubkey {
+    address(&[b"deposit_vault", governance_mint.as_ref()])
+}
+
+pub fn loyalty_address(user_account: &Pubkey) -> Pubkey {
+    address(&[b"loyalty", user_account.as_ref()])
+}
+
+pub fn badge_address(badge_id: u8) -> Pubkey {
+    address(&[b"badge", badge_id.to_le_bytes().as_ref()])
+}
+
+pub fn badge_mint_address(badge_id: u8) -> Pubkey {
+    address(&[b"badge_mint", badge_id.to_le_bytes().as_ref()])
+}
+
+pub fn season_address(season_id: u32) -> Pubkey {
+    address(&[b"season", season_id.to_le_bytes().as_ref()])
+}
+
+// Season stats are keyed by the user's wallet, not their user account.
+pub fn season_stats_address(season: &Pubkey, user: &Pubkey) -> Pubkey {
+    address(&[b"season_stats", season.as_ref(), user.as_ref()])
+}
+
+// Votes are recorded per wallet, not per user account.
+pub fn vote_record_address(proposal: &Pubkey, voter: &Pubkey) -> Pubkey {
+    address(&[b"vote", proposal.as_ref(), voter.as_ref()])
+}
+
+pub fn token_lock_address(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    address(&[b"token_lock", owner.as_ref(), mint.as_ref()])
+}
+
+pub fn token_lock_vault_address(token_lock: &Pubkey) -> Pubkey {
+    address(&[b"token_lock_vault", token_lock.as_ref()])
+}
+
+pub fn early_adopter_reward_address(wallet: &Pubkey) -> Pubkey {
+    address(&[b"rewarded", wallet.as_ref()])
+}
+
+pub fn referral_address(referrer: &Pubkey, referred: &Pubkey) -> Pubkey {
+    address(&[b"referral", referrer.as_ref(), referred.as_ref()])
+}
+
+pub fn delegation_address(delegator_account: &Pubkey) -> Pubkey {
+    address(&[b"delegation", delegator_account.as_ref()])
+}
+
+pub fn catalog_address() -> Pubkey {
+    address(&[b"catalog"])
+}
+
+pub fn catalog_item_address(item_id: u32) -> Pubkey {
+    address(&[b"catalog_item", item_id.to_le_bytes().as_ref()])
+}
+
+pub fn item_claims_address(item: &Pubkey, owner: &Pubkey) -> Pubkey {
+    address(&[b"item_claims", item.as_ref(), owner.as_ref()])
+}
+
+// `claim_count` is the owner's `ItemClaims::count` before the redemption.
+pub fn redemption_address(item: &Pubkey, owner: &Pubkey, claim_count: u32) -> Pubkey {
+    address(&[b"redemption", item.as_ref(), owner.as_ref(), claim_count.to_le_bytes().as_ref()])
+}
+
+// Usernames are case-insensitive, so the address uses the lowercase name.
+pub fn username_address(name: &str) -> Pubkey {
+    address(&[b"username", crate::username_seed(name).as_ref()])
+}
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: crate::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+fn admin_only(admin: &Pubkey) -> accounts::AdminOnly {
+    accounts::AdminOnly {
+        config: config_address(),
+        admin: *admin,
+    }
+}
+
+// Passes badge definitions to an instruction that unlocks badges
+// (`update_tier`, `redeem_loyalty`, `redeem_item`, `refer_user` and votes),
+// so the badges whose milestones it reaches are unlocked.
+pub fn with_badges(mut instruction: Instruction, badge_ids: &[u8]) -> Instruction {
+    instruction.accounts.extend(
+        badge_ids
+            .iter()
+            .map(|&badge_id| AccountMeta::new_readonly(badge_address(badge_id), false)),
+    );
+    instruction
+}
+
+// Lets a delegate's vote also count for each delegator, given as a (wallet,
+// user account) pair. Delegators' tokens are read from their locks for
+// `governance_mint`. Add delegators before any badges.
+pub fn with_delegators(
+    mut instruction: Instruction,
+    proposal: &Pubkey,
+    governance_mint: &Pubkey,
+    delegators: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    for (delegator, delegator_account) in delegators {
+        instruction.accounts.extend([
+            AccountMeta::new_readonly(delegation_address(delegator_account), false),
+            AccountMeta::new_readonly(*delegator_account, false),
+            AccountMeta::new_readonly(loyalty_address(delegator_account), false),
+            AccountMeta::new(token_lock_address(delegator, governance_mint), false),
+            AccountMeta::new(vote_record_address(proposal, delegator), false),
+        ]);
+    }
+    instruction
+}
+
+// `mint` is a new keypair that signs alongside `authority`.
+pub fn initialize(mint: &Pubkey, authority: &Pubkey, decimals: u8) -> Instruction {
+    build(
+        accounts::Initialize {
+            mint: *mint,
+            authority: *authority,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::Initialize { decimals },
+    )
+}
+
+pub fn initialize_config(admin: &Pubkey, tier_thresholds: [u64; 4]) -> Instruction {
+    build(
+        accounts::InitializeConfig {
+            config: config_address(),
+            program_data: program_data_address(),
+            admin: *admin,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeConfig { tier_thresholds },
+    )
+}
+
+pub fn set_proposal_requirements(
+    admin: &Pubkey,
+    governance_mint: &Pubkey,
+    proposal_min_tokens: u64,
+    proposal_min_tier: u8,
+    proposal_deposit: u64,
+    max_active_proposals: u8,
+) -> Instruction {
+    build(
+        accounts::SetProposalRequirements {
+            config: config_address(),
+            governance_mint: *governance_mint,
+            deposit_vault: deposit_vault_address(governance_mint),
+            admin: *admin,
+            rent: sysvar::rent::ID,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::SetProposalRequirements {
+            proposal_min_tokens,
+            proposal_min_tier,
+            proposal_deposit,
+            max_active_proposals,
+        },
+    )
+}
+
+pub fn set_governance_params(
+    admin: &Pubkey,
+    voting_period: i64,
+    timelock_delay: i64,
+    quorum: u64,
+    executive_voting_power: VotingPower,
+) -> Instruction {
+    build(
+        admin_only(admin),
+        instruction::SetGovernanceParams {
+            voting_period,
+            timelock_delay,
+            quorum,
+            executive_voting_power,
+        },
+    )
+}
+
+pub fn set_guardian(admin: &Pubkey, guardian: &Pubkey) -> Instruction {
+    build(admin_only(admin), instruction::SetGuardian { guardian: *guardian })
+}
+
+pub fn set_redemption_limits(
+    admin: &Pubkey,
+    redeem_window: i64,
+    global_redeem_limit: u64,
+    user_redeem_limit: u64,
+) -> Instruction {
+    build(
+        admin_only(admin),
+        instruction::SetRedemptionLimits {
+            redeem_window,
+            global_redeem_limit,
+            user_redeem_limit,
+        },
+    )
+}
+
+pub fn set_reward_params(admin: &Pubkey, redemption_rate: u64, referral_reward: u64) -> Instruction {
+    build(
+        admin_only(admin),
+        instruction::SetRewardParams {
+            redemption_rate,
+            referral_reward,
+        },
+    )
+}
+
+// `authority` is the admin or the guardian.
+pub fn set_paused(authority: &Pubkey, paused: bool) -> Instruction {
+    build(
+        accounts::SetPaused {
+            config: config_address(),
+            authority: *authority,
+        },
+        instruction::SetPaused { paused },
+    )
+}
+
+pub fn register_badge(admin: &Pubkey, badge_id: u8, kind: BadgeKind, threshold: u64) -> Instruction {
+    build(
+        accounts::RegisterBadge {
+            config: config_address(),
+            badge: badge_address(badge_id),
+            badge_mint: badge_mint_address(badge_id),
+            admin: *admin,
+            rent: sysvar::rent::ID,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::RegisterBadge {
+            badge_id,
+            kind,
+            threshold,
+        },
+    )
+}
+
+// `recipient` is the owner's token account for the badge mint.
+pub fn claim_badge(owner: &Pubkey, user_account: &Pubkey, recipient: &Pubkey, badge_id: u8) -> Instruction {
+    build(
+        accounts::ClaimBadge {
+            config: config_address(),
+            user_account: *user_account,
+            badge_mint: badge_mint_address(badge_id),
+            recipient: *recipient,
+            owner: *owner,
+            token_program: token::ID,
+        },
+        instruction::ClaimBadge { badge_id },
+    )
+}
+
+pub fn update_tier(user_account: &Pubkey) -> Instruction {
+    build(
+        accounts::UpdateTier {
+            config: config_address(),
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+        },
+        instruction::UpdateTier {},
+    )
+}
+
+// `user_account` is a new keypair that signs alongside `authority`, who pays.
+pub fn initialize_user(user_account: &Pubkey, authority: &Pubkey, user: &Pubkey) -> Instruction {
+    build(
+        accounts::InitializeUser {
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+            authority: *authority,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeUser { user: *user },
+    )
+}
+
+// `wallet` is the wallet `user_account` belongs to.
+pub fn reward_early_adopter(
+    user_account: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    build(
+        accounts::RewardEarlyAdopter {
+            config: config_address(),
+            user_account: *user_account,
+            reward_record: early_adopter_reward_address(wallet),
+            mint: *mint,
+            recipient: *recipient,
+            authority: *authority,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::RewardEarlyAdopter { amount },
+    )
+}
+
+// Each recipient is a `(user_account, wallet, amount)`; tokens go to the
+// wallet's associated token account, which is created if missing.
+pub fn batch_reward_early_adopters(mint: &Pubkey, authority: &Pubkey, recipients: &[(Pubkey, Pubkey, u64)]) -> Instruction {
+    let mut instruction = build(
+        accounts::BatchRewardEarlyAdopters {
+            config: config_address(),
+            mint: *mint,
+            authority: *authority,
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::BatchRewardEarlyAdopters {
+            amounts: recipients.iter().map(|&(_, _, amount)| amount).collect(),
+        },
+    );
+    for (user_account, wallet, _) in recipients {
+        instruction.accounts.extend([
+            AccountMeta::new(*user_account, false),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new(get_associated_token_address(wallet, mint), false),
+            AccountMeta::new(early_adopter_reward_address(wallet), false),
+        ]);
+    }
+    instruction
+}
+
+// With a season, the points also count towards the user's season tally.
+// `authority` is the config admin.
+pub fn track_loyalty(
+    authority: &Pubkey,
+    user_account: &Pubkey,
+    user: &Pubkey,
+    season: Option<&Pubkey>,
+    points: u64,
+) -> Instruction {
+    build(
+        accounts::TrackLoyalty {
+            config: config_address(),
+            authority: *authority,
+            loyalty_state: loyalty_address(user_account),
+            season: season.copied(),
+            season_stats: season.map(|season| season_stats_address(season, user)),
+        },
+        instruction::TrackLoyalty { points },
+    )
+}
+
+// Each entry is a `(user_account, points)`. A user listed more than once
+// shares one account slot.
+pub fn batch_track_loyalty(authority: &Pubkey, entries: &[(Pubkey, u64)]) -> Instruction {
+    let mut loyalty_accounts: Vec<Pubkey> = Vec::new();
+    let mut indexed = Vec::with_capacity(entries.len());
+    for (user_account, points) in entries {
+        let loyalty_state = loyalty_address(user_account);
+        let index = match loyalty_accounts.iter().position(|address| *address == loyalty_state) {
+            Some(index) => index,
+            None => {
+                loyalty_accounts.push(loyalty_state);
+                loyalty_accounts.len() - 1
+            }
+        };
+        indexed.push(LoyaltyEntry {
+            index: index as u8,
+            points: *points,
+        });
+    }
+    let mut instruction = build(
+        accounts::BatchTrackLoyalty {
+            config: config_address(),
+            authority: *authority,
+        },
+        instruction::BatchTrackLoyalty { entries: indexed },
+    );
+    instruction
+        .accounts
+        .extend(loyalty_accounts.into_iter().map(|address| AccountMeta::new(address, false)));
+    instruction
+}
+
+pub fn create_season(
+    admin: &Pubkey,
+    season_id: u32,
+    start_time: i64,
+    end_time: i64,
+    reward_pool: u64,
+    rank_shares_bps: [u16; LEADERBOARD_SIZE],
+) -> Instruction {
+    build(
+        accounts::CreateSeason {
+            config: config_address(),
+            season: season_address(season_id),
+            admin: *admin,
+            system_program: system_program::ID,
+        },
+        instruction::CreateSeason {
+            season_id,
+            start_time,
+            end_time,
+            reward_pool,
+            rank_shares_bps,
+        },
+    )
+}
+
+pub fn join_season(season: &Pubkey, user_account: &Pubkey, user: &Pubkey, payer: &Pubkey) -> Instruction {
+    build(
+        accounts::JoinSeason {
+            season: *season,
+            user_account: *user_account,
+            season_stats: season_stats_address(season, user),
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        instruction::JoinSeason {},
+    )
+}
+
+// `recipient` is the ranked user's token account for the reward mint.
+pub fn distribute_season_reward(
+    season: &Pubkey,
+    user: &Pubkey,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    authority: &Pubkey,
+) -> Instruction {
+    build(
+        accounts::DistributeSeasonReward {
+            config: config_address(),
+            season: *season,
+            season_stats: season_stats_address(season, user),
+            mint: *mint,
+            recipient: *recipient,
+            authority: *authority,
+            token_program: token::ID,
+        },
+        instruction::DistributeSeasonReward {},
+    )
+}
+
+// `proposal` is a new keypair that signs alongside `authority`. The token
+// account is needed when proposals require a token balance, and it and the
+// config's deposit vault when they take a deposit.
+pub fn create_proposal(
+    proposal: &Pubkey,
+    authority: &Pubkey,
+    proposer_account: &Pubkey,
+    proposer_token_account: Option<&Pubkey>,
+    deposit_vault: Option<&Pubkey>,
+    args: instruction::CreateProposal,
+) -> Instruction {
+    build(
+        accounts::CreateProposal {
+            config: config_address(),
+            proposal: *proposal,
+            treasury: treasury_address(),
+            proposer_account: *proposer_account,
+            proposer_token_account: proposer_token_account.copied(),
+            deposit_vault: deposit_vault.copied(),
+            authority: *authority,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        args,
+    )
+}
+
+pub fn update_proposal_metadata(
+    proposal: &Pubkey,
+    creator: &Pubkey,
+    title: &str,
+    uri: &str,
+    content_hash: [u8; 32],
+) -> Instruction {
+    build(
+        accounts::UpdateProposalMetadata {
+            proposal: *proposal,
+            creator: *creator,
+            system_program: system_program::ID,
+        },
+        instruction::UpdateProposalMetadata {
+            title: title.to_string(),
+            uri: uri.to_string(),
+            content_hash,
+        },
+    )
+}
+
+fn vote_accounts(
+    proposal: &Pubkey,
+    voter: &Pubkey,
+    voter_account: &Pubkey,
+    voter_token_lock: Option<&Pubkey>,
+) -> accounts::Vote {
+    accounts::Vote {
+        config: config_address(),
+        proposal: *proposal,
+        voter: *voter,
+        voter_account: *voter_account,
+        voter_loyalty: loyalty_address(voter_account),
+        voter_token_lock: voter_token_lock.copied(),
+        vote_record: vote_record_address(proposal, voter),
+        system_program: system_program::ID,
+    }
+}
+
+// `voter_token_lock` holds the voter's locked governance tokens, if they
+// have any; see `token_lock_address`.
+pub fn vote(
+    proposal: &Pubkey,
+    voter: &Pubkey,
+    voter_account: &Pubkey,
+    voter_token_lock: Option<&Pubkey>,
+    in_favor: bool,
+) -> Instruction {
+    build(
+        vote_accounts(proposal, voter, voter_account, voter_token_lock),
+        instruction::Vote { in_favor },
+    )
+}
+
+pub fn vote_options(
+    proposal: &Pubkey,
+    voter: &Pubkey,
+    voter_account: &Pubkey,
+    voter_token_lock: Option<&Pubkey>,
+    choices: Vec<u16>,
+) -> Instruction {
+    build(
+        vote_accounts(proposal, voter, voter_account, voter_token_lock),
+        instruction::VoteOptions { choices },
+    )
+}
+
+// Moves `amount` of `mint`, the governance mint, from the owner's token
+// account into their lock.
+pub fn lock_tokens(owner: &Pubkey, owner_token_account: &Pubkey, mint: &Pubkey, amount: u64) -> Instruction {
+    let token_lock = token_lock_address(owner, mint);
+    build(
+        accounts::LockTokens {
+            config: config_address(),
+            mint: *mint,
+            token_lock,
+            vault: token_lock_vault_address(&token_lock),
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::LockTokens { amount },
+    )
+}
+
+// Returns locked tokens of `mint` to `owner_token_account` once no vote
+// holds them.
+pub fn unlock_tokens(owner: &Pubkey, owner_token_account: &Pubkey, mint: &Pubkey, amount: u64) -> Instruction {
+    let token_lock = token_lock_address(owner, mint);
+    build(
+        accounts::UnlockTokens {
+            token_lock,
+            vault: token_lock_vault_address(&token_lock),
+            owner_token_account: *owner_token_account,
+            owner: *owner,
+            token_program: token::ID,
+        },
+        instruction::UnlockTokens { amount },
+    )
+}
+
+pub fn delegate_votes(delegator: &Pubkey, delegator_account: &Pubkey, delegate: &Pubkey) -> Instruction {
+    build(
+        accounts::DelegateVotes {
+            delegator_account: *delegator_account,
+            delegation: delegation_address(delegator_account),
+            delegator: *delegator,
+            system_program: system_program::ID,
+        },
+        instruction::DelegateVotes { delegate: *delegate },
+    )
+}
+
+pub fn undelegate(delegator: &Pubkey, delegator_account: &Pubkey) -> Instruction {
+    build(
+        accounts::Undelegate {
+            delegation: delegation_address(delegator_account),
+            delegator: *delegator,
+        },
+        instruction::Undelegate {},
+    )
+}
+
+// The token accounts settle a deposit: the config's deposit vault, the
+// creator's account for a refund and the treasury's for a slash.
+pub fn finalize_proposal(
+    proposal: &Pubkey,
+    creator_account: &Pubkey,
+    deposit_vault: Option<&Pubkey>,
+    creator_token_account: Option<&Pubkey>,
+    treasury_token_account: Option<&Pubkey>,
+) -> Instruction {
+    build(
+        accounts::FinalizeProposal {
+            config: config_address(),
+            proposal: *proposal,
+            creator_account: *creator_account,
+            deposit_vault: deposit_vault.copied(),
+            creator_token_account: creator_token_account.copied(),
+            treasury_token_account: treasury_token_account.copied(),
+            treasury: treasury_address(),
+            token_program: token::ID,
+        },
+        instruction::FinalizeProposal {},
+    )
+}
+
+// Every account the proposal's stored instructions touch goes in the
+// remaining accounts, target programs included.
+pub fn execute_proposal(proposal: &Pubkey, remaining_accounts: Vec<AccountMeta>) -> Instruction {
+    let mut instruction = build(
+        accounts::ExecuteProposal {
+            config: config_address(),
+            proposal: *proposal,
+            treasury: treasury_address(),
+        },
+        instruction::ExecuteProposal {},
+    );
+    instruction.accounts.extend(remaining_accounts);
+    instruction
+}
+
+pub fn close_proposal(proposal: &Pubkey, creator: &Pubkey) -> Instruction {
+    build(
+        accounts::CloseProposal {
+            proposal: *proposal,
+            creator: *creator,
+        },
+        instruction::CloseProposal {},
+    )
+}
+
+// `payer` is whoever paid for the vote record.
+pub fn close_vote_record(proposal: &Pubkey, voter: &Pubkey, payer: &Pubkey) -> Instruction {
+    build(
+        accounts::CloseVoteRecord {
+            vote_record: vote_record_address(proposal, voter),
+            proposal: *proposal,
+            payer: *payer,
+        },
+        instruction::CloseVoteRecord {},
+    )
+}
+
+pub fn redeem_loyalty(user_account: &Pubkey, mint: &Pubkey, recipient: &Pubkey, authority: &Pubkey, points: u64) -> Instruction {
+    build(
+        accounts::RedeemLoyalty {
+            config: config_address(),
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+            mint: *mint,
+            recipient: *recipient,
+            authority: *authority,
+            token_program: token::ID,
+        },
+        instruction::RedeemLoyalty { points },
+    )
+}
+
+pub fn initialize_catalog(admin: &Pubkey, operator: &Pubkey) -> Instruction {
+    build(
+        accounts::InitializeCatalog {
+            config: config_address(),
+            catalog: catalog_address(),
+            admin: *admin,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeCatalog { operator: *operator },
+    )
+}
+
+// `item_id` is the catalog's current `item_count`; token rewards need the
+// vault they are paid from.
+pub fn list_item(admin: &Pubkey, item_id: u32, vault: Option<&Pubkey>, args: instruction::ListItem) -> Instruction {
+    build(
+        accounts::ListItem {
+            config: config_address(),
+            catalog: catalog_address(),
+            item: catalog_item_address(item_id),
+            vault: vault.copied(),
+            admin: *admin,
+            system_program: system_program::ID,
+        },
+        args,
+    )
+}
+
+pub fn set_item_active(admin: &Pubkey, item_id: u32, active: bool) -> Instruction {
+    build(
+        accounts::SetItemActive {
+            config: config_address(),
+            item: catalog_item_address(item_id),
+            admin: *admin,
+        },
+        instruction::SetItemActive { item_id, active },
+    )
+}
+
+// `claim_count` is the owner's `ItemClaims::count` for the item, zero before
+// their first claim. Token rewards need the item's vault and the owner's
+// token account.
+pub fn redeem_item(
+    owner: &Pubkey,
+    user_account: &Pubkey,
+    item_id: u32,
+    claim_count: u32,
+    vault: Option<&Pubkey>,
+    recipient: Option<&Pubkey>,
+) -> Instruction {
+    let item = catalog_item_address(item_id);
+    build(
+        accounts::RedeemItem {
+            config: config_address(),
+            catalog: catalog_address(),
+            item,
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+            item_claims: item_claims_address(&item, owner),
+            redemption: redemption_address(&item, owner, claim_count),
+            vault: vault.copied(),
+            recipient: recipient.copied(),
+            owner: *owner,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::RedeemItem { item_id },
+    )
+}
+
+// `user` is the redemption's owner, who gets its rent back.
+pub fn close_redemption(operator: &Pubkey, redemption: &Pubkey, user: &Pubkey) -> Instruction {
+    build(
+        accounts::CloseRedemption {
+            catalog: catalog_address(),
+            redemption: *redemption,
+            user: *user,
+            operator: *operator,
+        },
+        instruction::CloseRedemption {},
+    )
+}
+
+pub fn burn_tokens(mint: &Pubkey, token_account: &Pubkey, authority: &Pubkey, amount: u64) -> Instruction {
+    build(
+        accounts::BurnTokens {
+            mint: *mint,
+            token_account: *token_account,
+            authority: *authority,
+            token_program: token::ID,
+        },
+        instruction::BurnTokens { amount },
+    )
+}
+
+pub fn time_based_rewards(user_account: &Pubkey) -> Instruction {
+    build(
+        accounts::TimeBasedRewards {
+            loyalty_state: loyalty_address(user_account),
+        },
+        instruction::TimeBasedRewards {},
+    )
+}
+
+// `user` is the newly referred wallet and signs.
+// `referrer` is the wallet `referrer_account` belongs to.
+pub fn refer_user(authority: &Pubkey, referrer: &Pubkey, referrer_account: &Pubkey, user: &Pubkey) -> Instruction {
+    build(
+        accounts::ReferUser {
+            config: config_address(),
+            authority: *authority,
+            referrer_account: *referrer_account,
+            referrer_loyalty: loyalty_address(referrer_account),
+            referral: referral_address(referrer, user),
+            user: *user,
+            system_program: system_program::ID,
+        },
+        instruction::ReferUser {},
+    )
+}
+
+pub fn apply_inactivity_penalty(user_account: &Pubkey) -> Instruction {
+    build(
+        accounts::ApplyInactivityPenalty {
+            loyalty_state: loyalty_address(user_account),
+        },
+        instruction::ApplyInactivityPenalty {},
+    )
+}
+
+pub fn expire_points(user_account: &Pubkey) -> Instruction {
+    build(
+        accounts::ExpirePoints {
+            loyalty_state: loyalty_address(user_account),
+        },
+        instruction::ExpirePoints {},
+    )
+}
+
+// `loyalty_tree` must already be allocated, program-owned and zeroed, with
+// `LoyaltyTree::SPACE` bytes.
+pub fn create_loyalty_tree(loyalty_tree: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        accounts::CreateLoyaltyTree {
+            config: config_address(),
+            loyalty_tree: *loyalty_tree,
+            admin: *admin,
+        },
+        instruction::CreateLoyaltyTree {},
+    )
+}
+
+pub fn append_compressed_user(loyalty_tree: &Pubkey, authority: &Pubkey, user: &Pubkey) -> Instruction {
+    build(
+        accounts::AppendCompressedUser {
+            loyalty_tree: *loyalty_tree,
+            authority: *authority,
+        },
+        instruction::AppendCompressedUser { user: *user },
+    )
+}
+
+// `root`, `leaf` and `proof` come from a replica of the tree.
+pub fn track_compressed_loyalty(
+    loyalty_tree: &Pubkey,
+    authority: &Pubkey,
+    root: [u8; 32],
+    leaf: CompressedUser,
+    proof: Vec<[u8; 32]>,
+    points: u64,
+) -> Instruction {
+    build(
+        accounts::TrackCompressedLoyalty {
+            loyalty_tree: *loyalty_tree,
+            authority: *authority,
+        },
+        instruction::TrackCompressedLoyalty {
+            root,
+            leaf,
+            proof,
+            points,
+        },
+    )
+}
+
+pub fn redeem_compressed_loyalty(
+    loyalty_tree: &Pubkey,
+    mint: &Pubkey,
+    recipient: &Pubkey,
+    owner: &Pubkey,
+    authority: &Pubkey,
+    args: instruction::RedeemCompressedLoyalty,
+) -> Instruction {
+    build(
+        accounts::RedeemCompressedLoyalty {
+            config: config_address(),
+            loyalty_tree: *loyalty_tree,
+            mint: *mint,
+            recipient: *recipient,
+            owner: *owner,
+            authority: *authority,
+            token_program: token::ID,
+        },
+        args,
+    )
+}
+
+pub fn close_user_account(user_account: &Pubkey, owner: &Pubkey) -> Instruction {
+    build(
+        accounts::CloseUserAccount {
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+            owner: *owner,
+        },
+        instruction::CloseUserAccount {},
+    )
+}
+
+pub fn migrate_user_account(user_account: &Pubkey, payer: &Pubkey) -> Instruction {
+    build(
+        accounts::MigrateUserAccount {
+            user_account: *user_account,
+            loyalty_state: loyalty_address(user_account),
+            payer: *payer,
+            system_program: system_program::ID,
+        },
+        instruction::MigrateUserAccount {},
+    )
+}
+
+pub fn claim_username(owner: &Pubkey, user_account: &Pubkey, name: &str) -> Instruction {
+    build(
+        accounts::ClaimUsername {
+            user_account: *user_account,
+            username: username_address(name),
+            owner: *owner,
+            system_program: system_program::ID,
+        },
+        instruction::ClaimUsername { name: name.to_string() },
+    )
+}
+
+pub fn release_username(owner: &Pubkey, user_account: &Pubkey, name: &str) -> Instruction {
+    build(
+        accounts::ReleaseUsername {
+            user_account: *user_account,
+            username: username_address(name),
+            owner: *owner,
+        },
+        instruction::ReleaseUsername {},
+    )
+}
+
+// Both owners sign: the current one gives the name up and the new one accepts it.
+pub fn transfer_username(
+    name: &str,
+    owner: &Pubkey,
+    user_account: &Pubkey,
+    new_owner: &Pubkey,
+    new_user_account: &Pubkey,
+) -> Instruction {
+    build(
+        accounts::TransferUsername {
+            user_account: *user_account,
+            username: username_address(name),
+            owner: *owner,
+            new_user_account: *new_user_account,
+            new_owner: *new_owner,
+        },
+        instruction::TransferUsername {},
+    )
+}
+
+pub fn update_profile(
+    owner: &Pubkey,
+    user_account: &Pubkey,
+    name: &str,
+    bio: &str,
+    avatar_uri: Option<String>,
+    socials: Vec<SocialHandle>,
+) -> Instruction {
+    build(
+        accounts::UpdateProfile {
+            user_account: *user_account,
+            owner: *owner,
+            system_program: system_program::ID,
+        },
+        instruction::UpdateProfile {
+            name: name.to_string(),
+            bio: bio.to_string(),
+            avatar_uri,
+            socials,
+        },
+    )
+}
+
+// The decoders take raw account data so callers can read accounts from any
+// source, e.g. an RPC node or a `BanksClient`.
+pub fn decode_account<T: AccountDeserialize>(data: &[u8]) -> Result<T> {
+    T::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_user_account(data: &[u8]) -> Result<UserAccount> {
+    decode_account(data)
+}
+
+pub fn decode_proposal(data: &[u8]) -> Result<Proposal> {
+    decode_account(data)
+}
+
+// Zero-copy accounts are read straight from their bytes after the discriminator.
+pub fn decode_loyalty_state(data: &[u8]) -> Result<LoyaltyState> {
+    if data.len() < 8 + std::mem::size_of::<LoyaltyState>() {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+    if data[..8] != LoyaltyState::DISCRIMINATOR {
+        return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+    Ok(bytemuck::pod_read_unaligned(&data[8..8 + std::mem::size_of::<LoyaltyState>()]))
+}
+
+// Lists every error so codes can be mapped back. The exhaustive match makes
+// adding a variant to `LoyaltyProgramError` without listing it here a
+// compile error.
+macro_rules! program_errors {
+    ($($error:ident),* $(,)?) => {
+        pub const PROGRAM_ERRORS: &[LoyaltyProgramError] = &[$(LoyaltyProgramError::$error,)*];
+
+        #[allow(dead_code)]
+        fn listed(error: LoyaltyProgramError) {
+            match error {
+                $(LoyaltyProgramError::$error => {})*
+            }
+        }
+    };
+}
+
+program_errors!(
+    InsufficientFunds,
+    Unauthorized,
+    AccountAlreadyInitialized,
+    AccountNotInitialized,
+    InvalidBadgeId,
+    InvalidBadgeAccount,
+    BadgeNotEarned,
+    BadgeAlreadyMinted,
+    InvalidSeasonWindow,
+    InvalidRankShares,
+    SeasonNotActive,
+    SeasonNotEnded,
+    SeasonMismatch,
+    SeasonRewardClaimed,
+    NotRanked,
+    InvalidAvailabilityWindow,
+    InvalidItemVault,
+    ItemNotAvailable,
+    ItemOutOfStock,
+    ItemUserLimitReached,
+    ProgramPaused,
+    RedemptionRateLimited,
+    EmissionBudgetExceeded,
+    ProposalNotActive,
+    VotingClosed,
+    VotingStillOpen,
+    ProposalNotSucceeded,
+    TimelockNotElapsed,
+    NoProposalAction,
+    InvalidProposalInstruction,
+    ProposerBelowThreshold,
+    ActiveProposalLimitReached,
+    InvalidDepositAccount,
+    InvalidProposalOptions,
+    InvalidBallot,
+    InvalidDelegate,
+    InvalidDelegation,
+    AlreadyVoted,
+    InvalidProposalTitle,
+    ProposalUriTooLong,
+    ProposalMetadataLocked,
+    ProposalNotSettled,
+    UserAccountInUse,
+    ProfileFieldTooLong,
+    InvalidProfileCharacters,
+    InvalidUsername,
+    UsernameAlreadySet,
+    AccountAlreadyMigrated,
+    InvalidBatchEntry,
+    InvalidLeafProof,
+    LoyaltyTreeFull,
+    TokensLocked,
+);
+
+// Maps a custom error code back to the program error, or `None` for codes
+// from Anchor or other programs.
+pub fn program_error(code: u32) -> Option<LoyaltyProgramError> {
+    PROGRAM_ERRORS.iter().copied().find(|error| u32::from(*error) == code)
+}
+
+pub fn transaction_program_error(error: &TransactionError) -> Option<LoyaltyProgramError> {
+    match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => program_error(*code),
+        _ => None,
+    }
+}
@@ -1,5 +1,19 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::keccak;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::associated_token::{self, get_associated_token_address, AssociatedToken};
 use anchor_spl::token::{self, Mint, TokenAccount, Token};
+use spl_concurrent_merkle_tree::concurrent_merkle_tree::ConcurrentMerkleTree;
+
+#[cfg(not(target_os = "solana"))]
+pub mod tree_replica;
+
+#[cfg(not(target_os = "solana"))]
+pub mod client;
 
 declare_id!("MmZW8AQAHZuSAeh69QCgnu5nLxBXnTX3RPCtgBh7qBX");
 
@@ -7,26 +21,254 @@ declare_id!("MmZW8AQAHZuSAeh69QCgnu5nLxBXnTX3RPCtgBh7qBX");
 mod early_adopter_airdrop {
     use super::*;
 
+    // The mint is created and initialized by its account constraints.
     pub fn initialize(ctx: Context<Initialize>, decimals: u8) -> Result<()> {
-        let cpi_accounts = token::InitializeMint {
-            mint: ctx.accounts.mint.to_account_info(),
-            rent: ctx.accounts.rent.to_account_info(),
-        };
+        emit!(MintInitialized {
+            version: EVENT_VERSION,
+            mint: ctx.accounts.mint.key(),
+            authority: ctx.accounts.authority.key(),
+            decimals,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>, tier_thresholds: [u64; 4]) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.bump = ctx.bumps.config;
+        config.tier_thresholds = tier_thresholds;
+        config.guardian = ctx.accounts.admin.key();
+        config.paused = false;
+        config.redeem_window = 0;
+        config.global_redeem_limit = 0;
+        config.user_redeem_limit = 0;
+        config.global_redemptions = RateWindow::default();
+        config.redemption_rate = 100;
+        config.referral_reward = 100;
+        config.emission_budget = 0;
+        config.emitted = 0;
+        config.voting_period = DEFAULT_VOTING_PERIOD;
+        config.timelock_delay = DEFAULT_TIMELOCK_DELAY;
+        config.quorum = 0;
+        config.governance_mint = Pubkey::default();
+        config.deposit_vault = Pubkey::default();
+        config.proposal_min_tokens = 0;
+        config.proposal_min_tier = 0;
+        config.proposal_deposit = 0;
+        config.max_active_proposals = 0;
+        config.executive_voting_power = VotingPower::Tokens;
+        emit_config_updated(config, config.admin)
+    }
+
+    pub fn set_proposal_requirements(
+        ctx: Context<SetProposalRequirements>,
+        proposal_min_tokens: u64,
+        proposal_min_tier: u8,
+        proposal_deposit: u64,
+        max_active_proposals: u8,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.governance_mint = ctx.accounts.governance_mint.key();
+        config.deposit_vault = ctx.accounts.deposit_vault.key();
+        config.proposal_min_tokens = proposal_min_tokens;
+        config.proposal_min_tier = proposal_min_tier;
+        config.proposal_deposit = proposal_deposit;
+        config.max_active_proposals = max_active_proposals;
+        emit_config_updated(config, ctx.accounts.admin.key())
+    }
+
+    pub fn set_governance_params(
+        ctx: Context<AdminOnly>,
+        voting_period: i64,
+        timelock_delay: i64,
+        quorum: u64,
+        executive_voting_power: VotingPower,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.voting_period = voting_period;
+        config.timelock_delay = timelock_delay;
+        config.quorum = quorum;
+        config.executive_voting_power = executive_voting_power;
+        emit_config_updated(config, ctx.accounts.admin.key())
+    }
+
+    pub fn set_guardian(ctx: Context<AdminOnly>, guardian: Pubkey) -> Result<()> {
+        ctx.accounts.config.guardian = guardian;
+        emit_config_updated(&ctx.accounts.config, ctx.accounts.admin.key())
+    }
+
+    pub fn set_redemption_limits(
+        ctx: Context<AdminOnly>,
+        redeem_window: i64,
+        global_redeem_limit: u64,
+        user_redeem_limit: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.redeem_window = redeem_window;
+        config.global_redeem_limit = global_redeem_limit;
+        config.user_redeem_limit = user_redeem_limit;
+        config.global_redemptions = RateWindow::default();
+        emit_config_updated(config, ctx.accounts.admin.key())
+    }
+
+    pub fn set_reward_params(ctx: Context<AdminOnly>, redemption_rate: u64, referral_reward: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.redemption_rate = redemption_rate;
+        config.referral_reward = referral_reward;
+        emit_config_updated(config, ctx.accounts.admin.key())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let authority = ctx.accounts.authority.key();
+        if authority != config.admin && authority != config.guardian {
+            return Err(LoyaltyProgramError::Unauthorized.into());
+        }
+        config.paused = paused;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        if paused {
+            emit!(ProgramPaused {
+                version: EVENT_VERSION,
+                authority,
+                timestamp,
+            });
+        } else {
+            emit!(ProgramUnpaused {
+                version: EVENT_VERSION,
+                authority,
+                timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn register_badge(ctx: Context<RegisterBadge>, badge_id: u8, kind: BadgeKind, threshold: u64) -> Result<()> {
+        if badge_id >= MAX_BADGES {
+            return Err(LoyaltyProgramError::InvalidBadgeId.into());
+        }
+        let badge = &mut ctx.accounts.badge;
+        badge.badge_id = badge_id;
+        badge.kind = kind;
+        badge.threshold = threshold;
+        badge.mint = ctx.accounts.badge_mint.key();
+        emit!(BadgeRegistered {
+            version: EVENT_VERSION,
+            badge_id,
+            kind,
+            threshold,
+            mint: badge.mint,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn claim_badge(ctx: Context<ClaimBadge>, badge_id: u8) -> Result<()> {
+        let bit = 1u64 << badge_id;
+        let user_account = &mut ctx.accounts.user_account;
+        if user_account.badges & bit == 0 {
+            return Err(LoyaltyProgramError::BadgeNotEarned.into());
+        }
+        if user_account.badges_minted & bit != 0 {
+            return Err(LoyaltyProgramError::BadgeAlreadyMinted.into());
+        }
+        user_account.badges_minted |= bit;
+
+        // The config PDA is mint and freeze authority, so the badge is frozen
+        // in the holder's account right after minting and cannot be moved.
+        let signer_seeds: &[&[&[u8]]] = &[&[b"config", &[ctx.accounts.config.bump]]];
         let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::initialize_mint(cpi_ctx, decimals, &ctx.accounts.authority.key(), Some(&ctx.accounts.authority.key()))
+        let cpi_accounts = token::MintTo {
+            mint: ctx.accounts.badge_mint.to_account_info(),
+            to: ctx.accounts.recipient.to_account_info(),
+            authority: ctx.accounts.config.to_account_info(),
+        };
+        token::mint_to(CpiContext::new_with_signer(cpi_program.clone(), cpi_accounts, signer_seeds), 1)?;
+        let cpi_accounts = token::FreezeAccount {
+            account: ctx.accounts.recipient.to_account_info(),
+            mint: ctx.accounts.badge_mint.to_account_info(),
+            authority: ctx.accounts.config.to_account_info(),
+        };
+        token::freeze_account(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds))?;
+
+        emit!(BadgeMinted {
+            version: EVENT_VERSION,
+            user: ctx.accounts.user_account.user,
+            user_account: ctx.accounts.user_account.key(),
+            badge_id,
+            mint: ctx.accounts.badge_mint.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn update_tier(ctx: Context<UpdateTier>) -> Result<()> {
+        let loyalty_points = ctx.accounts.loyalty_state.load()?.loyalty_points;
+        let user_account = &mut ctx.accounts.user_account;
+        let previous_tier = user_account.loyalty_tier;
+        let new_tier = tier_for_points(&ctx.accounts.config.tier_thresholds, loyalty_points);
+        if new_tier != previous_tier {
+            user_account.loyalty_tier = new_tier;
+            emit!(TierChanged {
+                version: EVENT_VERSION,
+                user: user_account.user,
+                user_account: user_account.key(),
+                previous_tier,
+                new_tier,
+                timestamp: Clock::get()?.unix_timestamp,
+            });
+        }
+        unlock_badges(user_account, ctx.remaining_accounts)
     }
 
     pub fn initialize_user(ctx: Context<InitializeUser>, user: Pubkey) -> Result<()> {
         let user_account = &mut ctx.accounts.user_account;
+        user_account.version = USER_ACCOUNT_VERSION;
         user_account.user = user;
-        user_account.loyalty_points = 0;
-        user_account.last_activity = Clock::get()?.unix_timestamp;
         user_account.loyalty_tier = 1;
+        user_account.badges = 0;
+        user_account.badges_minted = 0;
+        user_account.redemptions = 0;
+        user_account.referrals = 0;
+        user_account.votes_cast = 0;
+        user_account.active_proposals = 0;
+        user_account.redemption_window = RateWindow::default();
+        user_account.username = Pubkey::default();
+        user_account.early_adopter_rewarded = false;
+        user_account.reserved = [0; ACCOUNT_RESERVED_LEN - 1];
+        user_account.name = String::new();
+        user_account.bio = String::new();
+        user_account.avatar_uri = None;
+        user_account.socials = Vec::new();
+
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_init()?;
+        loyalty_state.version = LOYALTY_STATE_VERSION;
+        loyalty_state.user = user;
+        loyalty_state.user_account = user_account.key();
+        loyalty_state.last_activity = Clock::get()?.unix_timestamp;
+        emit!(UserInitialized {
+            version: EVENT_VERSION,
+            user,
+            user_account: user_account.key(),
+            loyalty_state: ctx.accounts.loyalty_state.key(),
+            timestamp: loyalty_state.last_activity,
+        });
         Ok(())
     }
 
+    // Each wallet is rewarded once, whether here or by a batch, however many
+    // user accounts it registers.
     pub fn reward_early_adopter(ctx: Context<RewardEarlyAdopter>, amount: u64) -> Result<()> {
+        require_not_paused(&ctx.accounts.config)?;
+        ctx.accounts.user_account.early_adopter_rewarded = true;
+        ctx.accounts.reward_record.set_inner(EarlyAdopterReward {
+            wallet: ctx.accounts.user_account.user,
+            user_account: ctx.accounts.user_account.key(),
+            amount,
+            rewarded_at: Clock::get()?.unix_timestamp,
+        });
+        record_emission(&mut ctx.accounts.config, amount)?;
         let cpi_accounts = token::MintTo {
             mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.recipient.to_account_info(),
@@ -35,57 +277,714 @@ mod early_adopter_airdrop {
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
         token::mint_to(cpi_ctx, amount)?;
+        let previous_balance = ctx.accounts.recipient.amount;
         emit!(EarlyAdopterRewarded {
-            user: ctx.accounts.recipient.key(),
+            version: EVENT_VERSION,
+            user: ctx.accounts.recipient.owner,
+            token_account: ctx.accounts.recipient.key(),
             amount,
+            previous_balance,
+            new_balance: previous_balance.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?,
+            timestamp: Clock::get()?.unix_timestamp,
         });
         Ok(())
     }
 
-    pub fn track_loyalty(ctx: Context<TrackLoyalty>, user: Pubkey, points: u64) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
-        user_account.loyalty_points += points;
-        user_account.last_activity = Clock::get()?.unix_timestamp;
-        emit!(LoyaltyPointsTracked { user, points });
+    // Remaining accounts come in groups of user account, wallet, the wallet's
+    // associated token account and the wallet's reward record, one per
+    // amount. Missing token accounts and reward records are created at the
+    // authority's expense, and wallets already rewarded are skipped.
+    pub fn batch_reward_early_adopters<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchRewardEarlyAdopters<'info>>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        require_not_paused(&ctx.accounts.config)?;
+        if ctx.remaining_accounts.len() != amounts.len() * 4 {
+            return Err(LoyaltyProgramError::InvalidBatchEntry.into());
+        }
+        let mint_key = ctx.accounts.mint.key();
+        let current_time = Clock::get()?.unix_timestamp;
+        let (mut skipped, mut accounts_created, mut total_amount) = (0u32, 0u32, 0u64);
+        let mut recipients = Vec::with_capacity(amounts.len());
+        for (accounts, amount) in ctx.remaining_accounts.chunks(4).zip(amounts) {
+            let (user_info, wallet_info, recipient_info, record_info) =
+                (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+            let mut user_account = Account::<UserAccount>::try_from(user_info)?;
+            let (record_address, bump) =
+                Pubkey::find_program_address(&[b"rewarded", wallet_info.key.as_ref()], &crate::ID);
+            if user_account.user != wallet_info.key()
+                || recipient_info.key() != get_associated_token_address(wallet_info.key, &mint_key)
+                || record_info.key() != record_address
+            {
+                return Err(LoyaltyProgramError::InvalidBatchEntry.into());
+            }
+            if !record_info.data_is_empty() {
+                skipped += 1;
+                continue;
+            }
+
+            let space = EarlyAdopterReward::SPACE;
+            let cpi_accounts = system_program::CreateAccount {
+                from: ctx.accounts.authority.to_account_info(),
+                to: record_info.clone(),
+            };
+            let signer_seeds: &[&[&[u8]]] = &[&[b"rewarded", wallet_info.key.as_ref(), &[bump]]];
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::create_account(
+                CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds),
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                &crate::ID,
+            )?;
+            let reward_record = EarlyAdopterReward {
+                wallet: wallet_info.key(),
+                user_account: user_info.key(),
+                amount,
+                rewarded_at: current_time,
+            };
+            reward_record.try_serialize(&mut &mut record_info.try_borrow_mut_data()?[..])?;
+
+            let previous_balance = if recipient_info.data_is_empty() {
+                let cpi_accounts = associated_token::Create {
+                    payer: ctx.accounts.authority.to_account_info(),
+                    associated_token: recipient_info.clone(),
+                    authority: wallet_info.clone(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.associated_token_program.to_account_info();
+                associated_token::create(CpiContext::new(cpi_program, cpi_accounts))?;
+                accounts_created += 1;
+                0
+            } else {
+                Account::<TokenAccount>::try_from(recipient_info)?.amount
+            };
+            let cpi_accounts = token::MintTo {
+                mint: ctx.accounts.mint.to_account_info(),
+                to: recipient_info.clone(),
+                authority: ctx.accounts.authority.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::mint_to(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+
+            user_account.early_adopter_rewarded = true;
+            user_account.exit(&crate::ID)?;
+            total_amount = total_amount.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+            recipients.push(RewardedUser {
+                user: wallet_info.key(),
+                user_account: user_info.key(),
+                token_account: recipient_info.key(),
+                amount,
+                previous_balance,
+                new_balance: previous_balance.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?,
+            });
+        }
+        record_emission(&mut ctx.accounts.config, total_amount)?;
+
+        emit!(EarlyAdoptersBatchRewarded {
+            version: EVENT_VERSION,
+            rewarded: recipients.len() as u32,
+            skipped,
+            accounts_created,
+            total_amount,
+            recipients,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    pub fn track_loyalty(ctx: Context<TrackLoyalty>, points: u64) -> Result<()> {
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_mut()?;
+        let current_time = Clock::get()?.unix_timestamp;
+        credit_points(&mut loyalty_state, points, current_time)?;
+        loyalty_state.last_activity = current_time;
+
+        // Seasonal points accumulate separately; lifetime points above are unaffected.
+        match (&mut ctx.accounts.season, &mut ctx.accounts.season_stats) {
+            (Some(season), Some(season_stats)) => {
+                if season_stats.season != season.key() || season_stats.user != loyalty_state.user {
+                    return Err(LoyaltyProgramError::SeasonMismatch.into());
+                }
+                record_season_points(season, season_stats, points, current_time)?;
+                emit!(SeasonPointsTracked {
+                    version: EVENT_VERSION,
+                    season_id: season.season_id,
+                    user: season_stats.user,
+                    points,
+                    season_points: season_stats.points,
+                    timestamp: current_time,
+                });
+            }
+            (None, None) => {}
+            _ => return Err(LoyaltyProgramError::SeasonMismatch.into()),
+        }
+
+        emit!(LoyaltyPointsTracked {
+            version: EVENT_VERSION,
+            user: loyalty_state.user,
+            user_account: loyalty_state.user_account,
+            points,
+            previous_points: loyalty_state.loyalty_points - points,
+            new_points: loyalty_state.loyalty_points,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    // Credits each entry's points to the `LoyaltyState` at its index in the
+    // remaining accounts. Seasonal points aren't recorded here; use
+    // `track_loyalty` for users in a season.
+    pub fn batch_track_loyalty<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchTrackLoyalty<'info>>,
+        entries: Vec<LoyaltyEntry>,
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let mut tracked = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let info = ctx
+                .remaining_accounts
+                .get(entry.index as usize)
+                .ok_or(LoyaltyProgramError::InvalidBatchEntry)?;
+            let loader = AccountLoader::<LoyaltyState>::try_from(info)?;
+            let mut loyalty_state = loader.load_mut()?;
+            credit_points(&mut loyalty_state, entry.points, current_time)?;
+            loyalty_state.last_activity = current_time;
+            tracked.push(TrackedPoints {
+                user: loyalty_state.user,
+                user_account: loyalty_state.user_account,
+                points: entry.points,
+                previous_points: loyalty_state.loyalty_points - entry.points,
+                new_points: loyalty_state.loyalty_points,
+            });
+        }
+        emit!(LoyaltyPointsBatchTracked {
+            version: EVENT_VERSION,
+            entries: tracked,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    pub fn create_season(
+        ctx: Context<CreateSeason>,
+        season_id: u32,
+        start_time: i64,
+        end_time: i64,
+        reward_pool: u64,
+        rank_shares_bps: [u16; LEADERBOARD_SIZE],
+    ) -> Result<()> {
+        if end_time <= start_time {
+            return Err(LoyaltyProgramError::InvalidSeasonWindow.into());
+        }
+        if rank_shares_bps.iter().map(|share| *share as u64).sum::<u64>() > 10_000 {
+            return Err(LoyaltyProgramError::InvalidRankShares.into());
+        }
+        let season = &mut ctx.accounts.season;
+        season.season_id = season_id;
+        season.start_time = start_time;
+        season.end_time = end_time;
+        season.reward_pool = reward_pool;
+        season.rank_shares_bps = rank_shares_bps;
+        season.leaderboard = Vec::new();
+        emit!(SeasonCreated {
+            version: EVENT_VERSION,
+            season_id,
+            start_time,
+            end_time,
+            reward_pool,
+            rank_shares_bps,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn join_season(ctx: Context<JoinSeason>) -> Result<()> {
+        let season_stats = &mut ctx.accounts.season_stats;
+        season_stats.season = ctx.accounts.season.key();
+        season_stats.user = ctx.accounts.user_account.user;
+        season_stats.points = 0;
+        season_stats.reward_claimed = false;
+        emit!(SeasonJoined {
+            version: EVENT_VERSION,
+            season_id: ctx.accounts.season.season_id,
+            user: season_stats.user,
+            user_account: ctx.accounts.user_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn distribute_season_reward(ctx: Context<DistributeSeasonReward>) -> Result<()> {
+        require_not_paused(&ctx.accounts.config)?;
+        let season = &ctx.accounts.season;
+        let season_stats = &mut ctx.accounts.season_stats;
+        if Clock::get()?.unix_timestamp < season.end_time {
+            return Err(LoyaltyProgramError::SeasonNotEnded.into());
+        }
+        if season_stats.reward_claimed {
+            return Err(LoyaltyProgramError::SeasonRewardClaimed.into());
+        }
+        let rank = season
+            .leaderboard
+            .iter()
+            .position(|entry| entry.user == season_stats.user)
+            .ok_or(LoyaltyProgramError::NotRanked)?;
+        let amount = (season.reward_pool as u128 * season.rank_shares_bps[rank] as u128 / 10_000) as u64;
+        season_stats.reward_claimed = true;
+        record_emission(&mut ctx.accounts.config, amount)?;
+
+        let cpi_accounts = token::MintTo {
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.recipient.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::mint_to(cpi_ctx, amount)?;
+
+        emit!(SeasonRewardDistributed {
+            version: EVENT_VERSION,
+            season_id: season.season_id,
+            user: season_stats.user,
+            rank: rank as u8,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn create_proposal(ctx: Context<CreateProposal>, description: String) -> Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_proposal(
+        ctx: Context<CreateProposal>,
+        title: String,
+        uri: String,
+        content_hash: [u8; 32],
+        action: Option<ProposalAction>,
+        instructions: Vec<ProposalInstruction>,
+        vote_mode: VoteMode,
+        option_labels: Vec<String>,
+        voting_power: VotingPower,
+    ) -> Result<()> {
+        validate_proposal_metadata(&title, &uri)?;
+        // Proposals that change config or move treasury funds are weighed the
+        // way the config says, not the way their proposer picks.
+        let voting_power = if action.is_some() || !instructions.is_empty() {
+            ctx.accounts.config.executive_voting_power
+        } else {
+            voting_power
+        };
+        // Polls have no outcome to execute, so they carry no action or instructions.
+        if !option_labels.is_empty()
+            && (option_labels.len() < 2
+                || option_labels.len() > MAX_PROPOSAL_OPTIONS
+                || option_labels.iter().any(|label| label.len() > MAX_OPTION_LABEL_LEN)
+                || action.is_some()
+                || !instructions.is_empty())
+        {
+            return Err(LoyaltyProgramError::InvalidProposalOptions.into());
+        }
+
+        // Treasury instructions run under the treasury PDA's signature, which is
+        // the only signer they may require, and may not re-enter this program.
+        let treasury = ctx.accounts.treasury.key();
+        for instruction in &instructions {
+            if instruction.program_id == crate::ID
+                || instruction.accounts.iter().any(|meta| meta.is_signer && meta.pubkey != treasury)
+            {
+                return Err(LoyaltyProgramError::InvalidProposalInstruction.into());
+            }
+        }
+
+        let config = &ctx.accounts.config;
+        let proposer_account = &mut ctx.accounts.proposer_account;
+        if config.max_active_proposals != 0 && proposer_account.active_proposals >= config.max_active_proposals {
+            return Err(LoyaltyProgramError::ActiveProposalLimitReached.into());
+        }
+
+        // Proposers qualify through either their token balance or their loyalty tier.
+        let tokens_held = ctx.accounts.proposer_token_account.as_ref().map_or(0, |account| account.amount);
+        let meets_token_threshold = config.proposal_min_tokens != 0 && tokens_held >= config.proposal_min_tokens;
+        let meets_tier_threshold = config.proposal_min_tier != 0 && proposer_account.loyalty_tier >= config.proposal_min_tier;
+        if (config.proposal_min_tokens != 0 || config.proposal_min_tier != 0) && !meets_token_threshold && !meets_tier_threshold {
+            return Err(LoyaltyProgramError::ProposerBelowThreshold.into());
+        }
+
+        if config.proposal_deposit != 0 {
+            let (from, to) = match (&ctx.accounts.proposer_token_account, &ctx.accounts.deposit_vault) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Err(LoyaltyProgramError::InvalidDepositAccount.into()),
+            };
+            let cpi_accounts = token::Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), config.proposal_deposit)?;
+        }
+        proposer_account.active_proposals += 1;
+
+        let current_time = Clock::get()?.unix_timestamp;
         let proposal = &mut ctx.accounts.proposal;
-        proposal.description = description;
+        proposal.version = PROPOSAL_VERSION;
+        proposal.title = title;
+        proposal.uri = uri;
+        proposal.content_hash = content_hash;
         proposal.votes_for = 0;
         proposal.votes_against = 0;
+        proposal.turnout = 0;
+        proposal.vote_mode = vote_mode;
+        proposal.options = option_labels
+            .into_iter()
+            .map(|label| ProposalOption { label, votes: 0 })
+            .collect();
+        proposal.winning_option = None;
+        proposal.voting_power = voting_power;
+        proposal.creator = ctx.accounts.authority.key();
+        proposal.creator_account = proposer_account.key();
+        proposal.deposit = config.proposal_deposit;
+        proposal.created_at = current_time;
+        proposal.voting_ends_at = current_time + ctx.accounts.config.voting_period;
+        proposal.status = ProposalStatus::Active;
+        proposal.executable_at = 0;
+        proposal.reserved = [0; ACCOUNT_RESERVED_LEN];
+        proposal.action = action;
+        proposal.instructions = instructions;
+        emit!(ProposalCreated {
+            version: EVENT_VERSION,
+            proposal: proposal.key(),
+            creator: proposal.creator,
+            creator_account: proposal.creator_account,
+            title: proposal.title.clone(),
+            uri: proposal.uri.clone(),
+            content_hash,
+            vote_mode,
+            voting_power,
+            options: proposal.options.iter().map(|option| option.label.clone()).collect(),
+            action: proposal.action,
+            instruction_count: proposal.instructions.len() as u32,
+            deposit: proposal.deposit,
+            voting_ends_at: proposal.voting_ends_at,
+            timestamp: current_time,
+        });
         Ok(())
     }
 
-    pub fn vote(ctx: Context<Vote>, in_favor: bool) -> Result<()> {
+    // The account is resized to fit the new title and URI, with the creator
+    // paying for any growth and receiving any freed rent.
+    pub fn update_proposal_metadata(
+        ctx: Context<UpdateProposalMetadata>,
+        title: String,
+        uri: String,
+        content_hash: [u8; 32],
+    ) -> Result<()> {
+        validate_proposal_metadata(&title, &uri)?;
         let proposal = &mut ctx.accounts.proposal;
-        let voter_account = &ctx.accounts.voter_account;
+        if proposal.status != ProposalStatus::Active || proposal.turnout != 0 {
+            return Err(LoyaltyProgramError::ProposalMetadataLocked.into());
+        }
+        proposal.title = title;
+        proposal.uri = uri;
+        proposal.content_hash = content_hash;
+        emit!(ProposalMetadataUpdated {
+            version: EVENT_VERSION,
+            proposal: proposal.key(),
+            title: proposal.title.clone(),
+            uri: proposal.uri.clone(),
+            content_hash,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
 
-        let voting_power = voter_account.tokens_held;
+    pub fn vote<'info>(ctx: Context<'_, '_, 'info, 'info, Vote<'info>>, in_favor: bool) -> Result<()> {
+        cast_vote(ctx, in_favor, Vec::new())
+    }
 
-        if in_favor {
-            proposal.votes_for += voting_power;
+    // `choices` has one entry per option: nonzero marks a selected option, and
+    // in weighted-split mode the entries are basis points summing to 10000.
+    pub fn vote_options<'info>(ctx: Context<'_, '_, 'info, 'info, Vote<'info>>, choices: Vec<u16>) -> Result<()> {
+        cast_vote(ctx, false, choices)
+    }
+
+    pub fn delegate_votes(ctx: Context<DelegateVotes>, delegate: Pubkey) -> Result<()> {
+        let delegator = ctx.accounts.delegator.key();
+        if delegate == delegator {
+            return Err(LoyaltyProgramError::InvalidDelegate.into());
+        }
+        let delegation = &mut ctx.accounts.delegation;
+        delegation.delegator = delegator;
+        delegation.delegator_account = ctx.accounts.delegator_account.key();
+        delegation.delegate = delegate;
+        delegation.delegated_at = Clock::get()?.unix_timestamp;
+        emit!(VotesDelegated {
+            version: EVENT_VERSION,
+            delegator,
+            delegator_account: delegation.delegator_account,
+            delegate,
+            timestamp: delegation.delegated_at,
+        });
+        Ok(())
+    }
+
+    pub fn undelegate(ctx: Context<Undelegate>) -> Result<()> {
+        emit!(VotesUndelegated {
+            version: EVENT_VERSION,
+            delegator: ctx.accounts.delegator.key(),
+            delegator_account: ctx.accounts.delegation.delegator_account,
+            delegate: ctx.accounts.delegation.delegate,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Token-weighted votes count only locked tokens. A vote that counts them
+    // keeps them locked until voting on that proposal ends, so they can't be
+    // moved to another wallet and counted again.
+    pub fn lock_tokens(ctx: Context<LockTokens>, amount: u64) -> Result<()> {
+        let cpi_accounts = token::Transfer {
+            from: ctx.accounts.owner_token_account.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+
+        let token_lock = &mut ctx.accounts.token_lock;
+        token_lock.owner = ctx.accounts.owner.key();
+        token_lock.mint = ctx.accounts.mint.key();
+        token_lock.bump = ctx.bumps.token_lock;
+        let previous_amount = token_lock.amount;
+        token_lock.amount = previous_amount.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+        emit!(TokensLocked {
+            version: EVENT_VERSION,
+            owner: token_lock.owner,
+            mint: token_lock.mint,
+            amount,
+            previous_amount,
+            new_amount: token_lock.amount,
+            locked_until: token_lock.locked_until,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn unlock_tokens(ctx: Context<UnlockTokens>, amount: u64) -> Result<()> {
+        let token_lock = &ctx.accounts.token_lock;
+        let current_time = Clock::get()?.unix_timestamp;
+        if current_time < token_lock.locked_until {
+            return Err(LoyaltyProgramError::TokensLocked.into());
+        }
+        if token_lock.amount < amount {
+            return Err(LoyaltyProgramError::InsufficientFunds.into());
+        }
+        let cpi_accounts = token::Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.owner_token_account.to_account_info(),
+            authority: token_lock.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let signer_seeds: &[&[&[u8]]] =
+            &[&[b"token_lock", token_lock.owner.as_ref(), token_lock.mint.as_ref(), &[token_lock.bump]]];
+        token::transfer(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds), amount)?;
+
+        let token_lock = &mut ctx.accounts.token_lock;
+        let previous_amount = token_lock.amount;
+        token_lock.amount -= amount;
+        emit!(TokensUnlocked {
+            version: EVENT_VERSION,
+            owner: token_lock.owner,
+            mint: token_lock.mint,
+            amount,
+            previous_amount,
+            new_amount: token_lock.amount,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    pub fn finalize_proposal(ctx: Context<FinalizeProposal>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let proposal = &mut ctx.accounts.proposal;
+        let current_time = Clock::get()?.unix_timestamp;
+        if proposal.status != ProposalStatus::Active {
+            return Err(LoyaltyProgramError::ProposalNotActive.into());
+        }
+        if current_time < proposal.voting_ends_at {
+            return Err(LoyaltyProgramError::VotingStillOpen.into());
+        }
+
+        let reached_quorum = proposal.turnout >= config.quorum;
+        proposal.winning_option = winning_option(&proposal.options);
+        let passed = if proposal.options.is_empty() {
+            proposal.votes_for > proposal.votes_against
+        } else {
+            proposal.winning_option.is_some()
+        };
+        proposal.status = if reached_quorum && passed {
+            ProposalStatus::Succeeded
         } else {
-            proposal.votes_against += voting_power;
+            ProposalStatus::Defeated
+        };
+        proposal.executable_at = current_time + config.timelock_delay;
+        ctx.accounts.creator_account.active_proposals -= 1;
+
+        // The deposit is returned if the proposal drew enough votes to count,
+        // whatever the outcome, and goes to the treasury otherwise.
+        if proposal.deposit != 0 {
+            let destination = if reached_quorum {
+                &ctx.accounts.creator_token_account
+            } else {
+                &ctx.accounts.treasury_token_account
+            };
+            let (deposit_vault, destination) = match (&ctx.accounts.deposit_vault, destination) {
+                (Some(deposit_vault), Some(destination)) => (deposit_vault, destination),
+                _ => return Err(LoyaltyProgramError::InvalidDepositAccount.into()),
+            };
+            let cpi_accounts = token::Transfer {
+                from: deposit_vault.to_account_info(),
+                to: destination.to_account_info(),
+                authority: config.to_account_info(),
+            };
+            let signer_seeds: &[&[&[u8]]] = &[&[b"config", &[config.bump]]];
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds), proposal.deposit)?;
+            emit!(ProposalDepositSettled {
+                version: EVENT_VERSION,
+                proposal: proposal.key(),
+                refunded: reached_quorum,
+                amount: proposal.deposit,
+                timestamp: current_time,
+            });
+        }
+
+        emit!(ProposalFinalized {
+            version: EVENT_VERSION,
+            proposal: proposal.key(),
+            succeeded: proposal.status == ProposalStatus::Succeeded,
+            votes_for: proposal.votes_for,
+            votes_against: proposal.votes_against,
+            winning_option: proposal.winning_option,
+            executable_at: proposal.executable_at,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    pub fn execute_proposal<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteProposal<'info>>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let proposal = &mut ctx.accounts.proposal;
+        if proposal.status != ProposalStatus::Succeeded {
+            return Err(LoyaltyProgramError::ProposalNotSucceeded.into());
+        }
+        let current_time = Clock::get()?.unix_timestamp;
+        if current_time < proposal.executable_at {
+            return Err(LoyaltyProgramError::TimelockNotElapsed.into());
+        }
+        if proposal.action.is_none() && proposal.instructions.is_empty() {
+            return Err(LoyaltyProgramError::NoProposalAction.into());
+        }
+        proposal.status = ProposalStatus::Executed;
+
+        match proposal.action {
+            Some(ProposalAction::UpdateRewardParams {
+                redemption_rate,
+                referral_reward,
+            }) => {
+                config.redemption_rate = redemption_rate;
+                config.referral_reward = referral_reward;
+            }
+            Some(ProposalAction::SetTierThresholds { tier_thresholds }) => {
+                config.tier_thresholds = tier_thresholds;
+            }
+            Some(ProposalAction::SetEmissionBudget { emission_budget }) => {
+                config.emission_budget = emission_budget;
+            }
+            Some(ProposalAction::RotateAdmin { new_admin }) => {
+                config.admin = new_admin;
+            }
+            None => {}
+        }
+        if proposal.action.is_some() {
+            emit_config_updated(config, proposal.key())?;
+        }
+
+        // Every account the stored instructions touch, including the target
+        // programs, must be passed as remaining accounts.
+        let mut account_infos = vec![ctx.accounts.treasury.to_account_info()];
+        account_infos.extend(ctx.remaining_accounts.iter().cloned());
+        let signer_seeds: &[&[&[u8]]] = &[&[b"treasury", &[ctx.bumps.treasury]]];
+        for instruction in &proposal.instructions {
+            invoke_signed(&Instruction::from(instruction), &account_infos, signer_seeds)?;
+        }
+
+        emit!(ProposalExecuted {
+            version: EVENT_VERSION,
+            proposal: proposal.key(),
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    pub fn close_proposal(ctx: Context<CloseProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+        if !proposal.is_settled() {
+            return Err(LoyaltyProgramError::ProposalNotSettled.into());
         }
-        emit!(GovernanceVoted {
+        emit!(ProposalClosed {
+            version: EVENT_VERSION,
             proposal: proposal.key(),
-            user: ctx.accounts.voter.key(),
-            in_favor,
+            creator: proposal.creator,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Records can be closed once their proposal is finalized, or closed itself.
+    pub fn close_vote_record(ctx: Context<CloseVoteRecord>) -> Result<()> {
+        let proposal_info = &ctx.accounts.proposal;
+        if proposal_info.owner == &crate::ID && !proposal_info.data_is_empty() {
+            let proposal = Proposal::try_deserialize(&mut &proposal_info.try_borrow_data()?[..])?;
+            if proposal.status == ProposalStatus::Active {
+                return Err(LoyaltyProgramError::VotingStillOpen.into());
+            }
+        }
+        emit!(VoteRecordClosed {
+            version: EVENT_VERSION,
+            vote_record: ctx.accounts.vote_record.key(),
+            proposal: proposal_info.key(),
+            voter: ctx.accounts.vote_record.voter,
+            timestamp: Clock::get()?.unix_timestamp,
         });
         Ok(())
     }
 
     pub fn redeem_loyalty(ctx: Context<RedeemLoyalty>, points: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_not_paused(config)?;
         let user_account = &mut ctx.accounts.user_account;
-        if user_account.loyalty_points < points {
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_mut()?;
+        let current_time = Clock::get()?.unix_timestamp;
+        expire_point_lots(&mut loyalty_state, current_time);
+        if loyalty_state.loyalty_points < points {
             return Err(LoyaltyProgramError::InsufficientFunds.into());
         }
-        user_account.loyalty_points -= points;
-        user_account.last_activity = Clock::get()?.unix_timestamp;
 
-        let amount = points * 100; // Example conversion rate
+        let amount = points.checked_mul(config.redemption_rate).ok_or(ProgramError::ArithmeticOverflow)?;
+        record_emission(config, amount)?;
+        let (redeem_window, global_limit, user_limit) =
+            (config.redeem_window, config.global_redeem_limit, config.user_redeem_limit);
+        consume_rate_limit(&mut config.global_redemptions, global_limit, redeem_window, amount, current_time)?;
+        consume_rate_limit(&mut user_account.redemption_window, user_limit, redeem_window, amount, current_time)?;
+
+        let previous_points = loyalty_state.loyalty_points;
+        debit_points(&mut loyalty_state, points);
+        loyalty_state.last_activity = current_time;
+        user_account.redemptions += 1;
+        let new_points = loyalty_state.loyalty_points;
+        drop(loyalty_state);
+
         let cpi_accounts = token::MintTo {
             mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.recipient.to_account_info(),
@@ -96,8 +995,171 @@ mod early_adopter_airdrop {
         token::mint_to(cpi_ctx, amount)?;
 
         emit!(LoyaltyPointsRedeemed {
+            version: EVENT_VERSION,
             user: ctx.accounts.user_account.user,
+            user_account: ctx.accounts.user_account.key(),
             points,
+            amount,
+            previous_points,
+            new_points,
+            timestamp: current_time,
+        });
+        unlock_badges(&mut ctx.accounts.user_account, ctx.remaining_accounts)
+    }
+
+    pub fn initialize_catalog(ctx: Context<InitializeCatalog>, operator: Pubkey) -> Result<()> {
+        let catalog = &mut ctx.accounts.catalog;
+        catalog.operator = operator;
+        catalog.item_count = 0;
+        catalog.bump = ctx.bumps.catalog;
+        emit!(CatalogInitialized {
+            version: EVENT_VERSION,
+            catalog: catalog.key(),
+            operator,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn list_item(
+        ctx: Context<ListItem>,
+        kind: RewardKind,
+        point_cost: u64,
+        amount: u64,
+        stock_limit: u64,
+        per_user_limit: u32,
+        available_from: i64,
+        available_until: i64,
+    ) -> Result<()> {
+        if available_until != 0 && available_until <= available_from {
+            return Err(LoyaltyProgramError::InvalidAvailabilityWindow.into());
+        }
+        // Token, partner token and NFT items pay out of a vault owned by the catalog PDA.
+        let vault = match (kind, &ctx.accounts.vault) {
+            (RewardKind::Voucher, _) => Pubkey::default(),
+            (_, Some(vault)) if vault.owner == ctx.accounts.catalog.key() => vault.key(),
+            _ => return Err(LoyaltyProgramError::InvalidItemVault.into()),
+        };
+
+        let catalog = &mut ctx.accounts.catalog;
+        let item = &mut ctx.accounts.item;
+        item.item_id = catalog.item_count;
+        item.kind = kind;
+        item.point_cost = point_cost;
+        item.amount = if kind == RewardKind::Nft { 1 } else { amount };
+        item.vault = vault;
+        item.stock_limit = stock_limit;
+        item.redeemed = 0;
+        item.per_user_limit = per_user_limit;
+        item.available_from = available_from;
+        item.available_until = available_until;
+        item.active = true;
+        catalog.item_count += 1;
+
+        emit!(ItemListed {
+            version: EVENT_VERSION,
+            item_id: item.item_id,
+            kind,
+            point_cost,
+            amount: item.amount,
+            vault,
+            stock_limit,
+            per_user_limit,
+            available_from,
+            available_until,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_item_active(ctx: Context<SetItemActive>, item_id: u32, active: bool) -> Result<()> {
+        ctx.accounts.item.active = active;
+        emit!(ItemActiveChanged {
+            version: EVENT_VERSION,
+            item_id,
+            active,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn redeem_item(ctx: Context<RedeemItem>, item_id: u32) -> Result<()> {
+        require_not_paused(&ctx.accounts.config)?;
+        let current_time = Clock::get()?.unix_timestamp;
+        let item = &mut ctx.accounts.item;
+        if !item.active
+            || current_time < item.available_from
+            || (item.available_until != 0 && current_time >= item.available_until)
+        {
+            return Err(LoyaltyProgramError::ItemNotAvailable.into());
+        }
+        if item.stock_limit != 0 && item.redeemed >= item.stock_limit {
+            return Err(LoyaltyProgramError::ItemOutOfStock.into());
+        }
+        let item_claims = &mut ctx.accounts.item_claims;
+        if item.per_user_limit != 0 && item_claims.count >= item.per_user_limit {
+            return Err(LoyaltyProgramError::ItemUserLimitReached.into());
+        }
+
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_mut()?;
+        expire_point_lots(&mut loyalty_state, current_time);
+        if loyalty_state.loyalty_points < item.point_cost {
+            return Err(LoyaltyProgramError::InsufficientFunds.into());
+        }
+        let previous_points = loyalty_state.loyalty_points;
+        debit_points(&mut loyalty_state, item.point_cost);
+        loyalty_state.last_activity = current_time;
+        let new_points = loyalty_state.loyalty_points;
+        drop(loyalty_state);
+        ctx.accounts.user_account.redemptions += 1;
+        item.redeemed += 1;
+        item_claims.count += 1;
+
+        if item.kind != RewardKind::Voucher {
+            let (vault, recipient) = match (&ctx.accounts.vault, &ctx.accounts.recipient) {
+                (Some(vault), Some(recipient)) if vault.key() == item.vault => (vault, recipient),
+                _ => return Err(LoyaltyProgramError::InvalidItemVault.into()),
+            };
+            let cpi_accounts = token::Transfer {
+                from: vault.to_account_info(),
+                to: recipient.to_account_info(),
+                authority: ctx.accounts.catalog.to_account_info(),
+            };
+            let signer_seeds: &[&[&[u8]]] = &[&[b"catalog", &[ctx.accounts.catalog.bump]]];
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+            token::transfer(cpi_ctx, item.amount)?;
+        }
+
+        let redemption = &mut ctx.accounts.redemption;
+        redemption.item = item.key();
+        redemption.user = ctx.accounts.owner.key();
+        redemption.points_spent = item.point_cost;
+        redemption.redeemed_at = current_time;
+        // Only vouchers wait on off-chain delivery.
+        redemption.fulfilled = item.kind != RewardKind::Voucher;
+
+        emit!(ItemRedeemed {
+            version: EVENT_VERSION,
+            item_id,
+            user: redemption.user,
+            user_account: ctx.accounts.user_account.key(),
+            redemption: redemption.key(),
+            points: item.point_cost,
+            previous_points,
+            new_points,
+            timestamp: current_time,
+        });
+        unlock_badges(&mut ctx.accounts.user_account, ctx.remaining_accounts)
+    }
+
+    pub fn close_redemption(ctx: Context<CloseRedemption>) -> Result<()> {
+        emit!(RedemptionFulfilled {
+            version: EVENT_VERSION,
+            redemption: ctx.accounts.redemption.key(),
+            user: ctx.accounts.redemption.user,
+            timestamp: Clock::get()?.unix_timestamp,
         });
         Ok(())
     }
@@ -111,73 +1173,1155 @@ mod early_adopter_airdrop {
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
         token::burn(cpi_ctx, amount)?;
+        let previous_balance = ctx.accounts.token_account.amount;
+        emit!(TokensBurned {
+            version: EVENT_VERSION,
+            owner: ctx.accounts.token_account.owner,
+            token_account: ctx.accounts.token_account.key(),
+            mint: ctx.accounts.mint.key(),
+            amount,
+            previous_balance,
+            new_balance: previous_balance - amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn time_based_rewards(ctx: Context<TimeBasedRewards>) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_mut()?;
         let current_time = Clock::get()?.unix_timestamp;
-        let time_held = current_time - user_account.last_activity;
+        let time_held = current_time - loyalty_state.last_activity;
 
         // Example: reward 1 point for every day held
         let points = (time_held / 86400) as u64;
-        user_account.loyalty_points += points;
-        user_account.last_activity = current_time;
+        credit_points(&mut loyalty_state, points, current_time)?;
+        loyalty_state.last_activity = current_time;
 
         emit!(LoyaltyPointsTracked {
-            user: user_account.user,
+            version: EVENT_VERSION,
+            user: loyalty_state.user,
+            user_account: loyalty_state.user_account,
             points,
+            previous_points: loyalty_state.loyalty_points - points,
+            new_points: loyalty_state.loyalty_points,
+            timestamp: current_time,
         });
 
         Ok(())
     }
 
-    pub fn refer_user(ctx: Context<ReferUser>, referrer: Pubkey) -> Result<()> {
+    pub fn refer_user(ctx: Context<ReferUser>) -> Result<()> {
+        let points = ctx.accounts.config.referral_reward;
+        let current_time = Clock::get()?.unix_timestamp;
+        let new_points = {
+            let mut referrer_loyalty = ctx.accounts.referrer_loyalty.load_mut()?;
+            credit_points(&mut referrer_loyalty, points, current_time)?;
+            referrer_loyalty.loyalty_points
+        };
+        ctx.accounts.referral.set_inner(Referral {
+            referrer: ctx.accounts.referrer_account.user,
+            referred: ctx.accounts.user.key(),
+            referred_at: current_time,
+        });
         let referrer_account = &mut ctx.accounts.referrer_account;
-        referrer_account.loyalty_points += 100; // Example referral reward
-        emit!(UserReferred { referrer, referred: ctx.accounts.user.key() });
-        Ok(())
+        referrer_account.referrals += 1;
+        emit!(UserReferred {
+            version: EVENT_VERSION,
+            referrer: referrer_account.user,
+            referrer_account: referrer_account.key(),
+            referred: ctx.accounts.user.key(),
+            points,
+            previous_points: new_points - points,
+            new_points,
+            timestamp: current_time,
+        });
+        unlock_badges(referrer_account, ctx.remaining_accounts)
     }
 
     pub fn apply_inactivity_penalty(ctx: Context<ApplyInactivityPenalty>) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_mut()?;
         let current_time = Clock::get()?.unix_timestamp;
-        let inactivity_period = current_time - user_account.last_activity;
+        let inactivity_period = current_time - loyalty_state.last_activity;
 
         let penalty_points = (inactivity_period / 604800) as u64 * 10;
-        if user_account.loyalty_points >= penalty_points {
-            user_account.loyalty_points -= penalty_points;
-        } else {
-            user_account.loyalty_points = 0;
-        }
-        user_account.last_activity = current_time;
+        expire_point_lots(&mut loyalty_state, current_time);
+        let previous_points = loyalty_state.loyalty_points;
+        let points = debit_points(&mut loyalty_state, penalty_points);
+        loyalty_state.last_activity = current_time;
 
+        emit!(InactivityPenaltyApplied {
+            version: EVENT_VERSION,
+            user: loyalty_state.user,
+            user_account: loyalty_state.user_account,
+            points,
+            previous_points,
+            new_points: loyalty_state.loyalty_points,
+            timestamp: current_time,
+        });
         Ok(())
     }
 
-    pub fn update_profile(ctx: Context<UpdateProfile>, name: String, bio: String) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
-        user_account.name = name;
-        user_account.bio = bio;
+    pub fn expire_points(ctx: Context<ExpirePoints>) -> Result<()> {
+        expire_point_lots(&mut *ctx.accounts.loyalty_state.load_mut()?, Clock::get()?.unix_timestamp);
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(init, payer = authority, mint::decimals = 9, mint::authority = authority)]
-    pub mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    // The tree account is too large to create here, so the admin allocates it
+    // beforehand with `LoyaltyTree::SPACE` bytes owned by this program.
+    pub fn create_loyalty_tree(ctx: Context<CreateLoyaltyTree>) -> Result<()> {
+        let mut loyalty_tree = ctx.accounts.loyalty_tree.load_init()?;
+        loyalty_tree.authority = ctx.accounts.admin.key();
+        loyalty_tree
+            .tree
+            .initialize()
+            .map_err(|_| LoyaltyProgramError::InvalidLeafProof)?;
+        emit!(LoyaltyTreeCreated {
+            version: EVENT_VERSION,
+            tree: ctx.accounts.loyalty_tree.key(),
+            authority: loyalty_tree.authority,
+            max_depth: LOYALTY_TREE_DEPTH as u32,
+            max_buffer_size: LOYALTY_TREE_BUFFER_SIZE as u32,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Compressed users hold a plain balance; point lots, expiry, badges and
+    // the other `UserAccount` features need a full account.
+    pub fn append_compressed_user(ctx: Context<AppendCompressedUser>, user: Pubkey) -> Result<()> {
+        let mut loyalty_tree = ctx.accounts.loyalty_tree.load_mut()?;
+        let current_time = Clock::get()?.unix_timestamp;
+        let leaf = CompressedUser {
+            user,
+            index: loyalty_tree.tree.rightmost_proof.index,
+            loyalty_points: 0,
+            last_activity: current_time,
+            redemption_window: RateWindow::default(),
+        };
+        loyalty_tree
+            .tree
+            .append(leaf.hash())
+            .map_err(|_| LoyaltyProgramError::LoyaltyTreeFull)?;
+        emit!(CompressedUserChanged {
+            version: EVENT_VERSION,
+            tree: ctx.accounts.loyalty_tree.key(),
+            leaf,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    // `root` is the root `proof` was made against; proofs stay valid while
+    // that root is still in the tree's change log.
+    pub fn track_compressed_loyalty(
+        ctx: Context<TrackCompressedLoyalty>,
+        root: [u8; 32],
+        leaf: CompressedUser,
+        proof: Vec<[u8; 32]>,
+        points: u64,
+    ) -> Result<()> {
+        let updated = CompressedUser {
+            loyalty_points: leaf.loyalty_points.checked_add(points).ok_or(ProgramError::ArithmeticOverflow)?,
+            last_activity: Clock::get()?.unix_timestamp,
+            ..leaf
+        };
+        let tree = ctx.accounts.loyalty_tree.key();
+        set_compressed_user(&mut *ctx.accounts.loyalty_tree.load_mut()?, root, &leaf, &updated, &proof)?;
+        emit!(CompressedUserChanged {
+            version: EVENT_VERSION,
+            tree,
+            leaf: updated,
+            timestamp: updated.last_activity,
+        });
+        emit!(LoyaltyPointsTracked {
+            version: EVENT_VERSION,
+            user: leaf.user,
+            user_account: tree,
+            points,
+            previous_points: leaf.loyalty_points,
+            new_points: updated.loyalty_points,
+            timestamp: updated.last_activity,
+        });
+        Ok(())
+    }
+
+    pub fn redeem_compressed_loyalty(
+        ctx: Context<RedeemCompressedLoyalty>,
+        root: [u8; 32],
+        leaf: CompressedUser,
+        proof: Vec<[u8; 32]>,
+        points: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require_not_paused(config)?;
+        if leaf.user != ctx.accounts.owner.key() {
+            return Err(LoyaltyProgramError::Unauthorized.into());
+        }
+        if leaf.loyalty_points < points {
+            return Err(LoyaltyProgramError::InsufficientFunds.into());
+        }
+        let current_time = Clock::get()?.unix_timestamp;
+        let amount = points.checked_mul(config.redemption_rate).ok_or(ProgramError::ArithmeticOverflow)?;
+        record_emission(config, amount)?;
+        let (redeem_window, global_limit, user_limit) =
+            (config.redeem_window, config.global_redeem_limit, config.user_redeem_limit);
+        consume_rate_limit(&mut config.global_redemptions, global_limit, redeem_window, amount, current_time)?;
+
+        let mut updated = CompressedUser {
+            loyalty_points: leaf.loyalty_points - points,
+            last_activity: current_time,
+            ..leaf
+        };
+        consume_rate_limit(&mut updated.redemption_window, user_limit, redeem_window, amount, current_time)?;
+        let tree = ctx.accounts.loyalty_tree.key();
+        set_compressed_user(&mut *ctx.accounts.loyalty_tree.load_mut()?, root, &leaf, &updated, &proof)?;
+
+        let cpi_accounts = token::MintTo {
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.recipient.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::mint_to(CpiContext::new(cpi_program, cpi_accounts), amount)?;
+
+        emit!(CompressedUserChanged {
+            version: EVENT_VERSION,
+            tree,
+            leaf: updated,
+            timestamp: current_time,
+        });
+        emit!(LoyaltyPointsRedeemed {
+            version: EVENT_VERSION,
+            user: leaf.user,
+            user_account: tree,
+            points,
+            amount,
+            previous_points: leaf.loyalty_points,
+            new_points: updated.loyalty_points,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    // Remaining points are forfeited. Accounts with proposals still open can't
+    // be closed, since finalizing those updates the creator's account, nor can
+    // accounts whose username hasn't been released.
+    pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+        let user_account = &ctx.accounts.user_account;
+        if user_account.active_proposals != 0 || user_account.username != Pubkey::default() {
+            return Err(LoyaltyProgramError::UserAccountInUse.into());
+        }
+        emit!(UserAccountClosed {
+            version: EVENT_VERSION,
+            user: user_account.user,
+            user_account: user_account.key(),
+            forfeited_points: ctx.accounts.loyalty_state.load()?.loyalty_points,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Rewrites a user account from the original, unversioned layout in the
+    // current one and moves its points into a new `LoyaltyState`. Anyone may
+    // run it; the payer covers any extra rent, and rent freed by a smaller
+    // layout stays in the account until it is closed.
+    pub fn migrate_user_account(ctx: Context<MigrateUserAccount>) -> Result<()> {
+        let info = ctx.accounts.user_account.to_account_info();
+        let legacy = {
+            let data = info.try_borrow_data()?;
+            if data.len() < 8 || data[..8] != UserAccount::DISCRIMINATOR {
+                return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+            }
+            // Both layouts are sized exactly, which tells them apart even if
+            // a key happens to start with a version byte.
+            if UserAccount::deserialize(&mut &data[8..]).is_ok_and(|account| {
+                account.version == USER_ACCOUNT_VERSION
+                    && data.len() == UserAccount::space(&account.name, &account.bio, &account.avatar_uri, &account.socials)
+            }) {
+                return Err(LoyaltyProgramError::AccountAlreadyMigrated.into());
+            }
+            if data.len() != UserAccountV1::SPACE {
+                return Err(ErrorCode::AccountDidNotDeserialize.into());
+            }
+            UserAccountV1::deserialize(&mut &data[8..]).map_err(|_| ErrorCode::AccountDidNotDeserialize)?
+        };
+        let (migrated, loyalty_points, last_activity, point_lots) = legacy.upgrade(Clock::get()?.unix_timestamp);
+
+        let space = UserAccount::space(&migrated.name, &migrated.bio, &migrated.avatar_uri, &migrated.socials);
+        let rent = Rent::get()?.minimum_balance(space);
+        if rent > info.lamports() {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                rent - info.lamports(),
+            )?;
+        }
+        info.realloc(space, false)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        let mut loyalty_state = ctx.accounts.loyalty_state.load_init()?;
+        loyalty_state.version = LOYALTY_STATE_VERSION;
+        loyalty_state.user = migrated.user;
+        loyalty_state.user_account = info.key();
+        loyalty_state.loyalty_points = loyalty_points;
+        loyalty_state.last_activity = last_activity;
+        for (slot, lot) in loyalty_state.point_lots.iter_mut().zip(&point_lots) {
+            *slot = LotSlot {
+                points: lot.points,
+                expires_at: lot.expires_at,
+                period: lot.period,
+                padding: [0; 4],
+            };
+        }
+        loyalty_state.lot_count = point_lots.len().min(MAX_POINT_LOTS) as u8;
+        emit!(UserAccountMigrated {
+            version: EVENT_VERSION,
+            user: migrated.user,
+            user_account: info.key(),
+            loyalty_state: ctx.accounts.loyalty_state.key(),
+            account_version: USER_ACCOUNT_VERSION,
+            loyalty_points,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Handles are stored and looked up in lowercase, so `Alice` and `alice`
+    // are the same name. Each user account holds at most one.
+    pub fn claim_username(ctx: Context<ClaimUsername>, name: String) -> Result<()> {
+        let normalized = normalize_username(&name)?;
+        let user_account = &mut ctx.accounts.user_account;
+        if user_account.username != Pubkey::default() {
+            return Err(LoyaltyProgramError::UsernameAlreadySet.into());
+        }
+        let username = &mut ctx.accounts.username;
+        username.name = normalized;
+        username.owner = ctx.accounts.owner.key();
+        username.user_account = user_account.key();
+        username.claimed_at = Clock::get()?.unix_timestamp;
+        user_account.username = username.key();
+        emit!(UsernameClaimed {
+            version: EVENT_VERSION,
+            name: username.name.clone(),
+            owner: username.owner,
+            user_account: username.user_account,
+            timestamp: username.claimed_at,
+        });
+        Ok(())
+    }
+
+    pub fn release_username(ctx: Context<ReleaseUsername>) -> Result<()> {
+        ctx.accounts.user_account.username = Pubkey::default();
+        emit!(UsernameReleased {
+            version: EVENT_VERSION,
+            name: ctx.accounts.username.name.clone(),
+            owner: ctx.accounts.owner.key(),
+            user_account: ctx.accounts.user_account.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Both owners sign, so nobody is handed a name they didn't ask for.
+    pub fn transfer_username(ctx: Context<TransferUsername>) -> Result<()> {
+        let new_user_account = &mut ctx.accounts.new_user_account;
+        if new_user_account.username != Pubkey::default() {
+            return Err(LoyaltyProgramError::UsernameAlreadySet.into());
+        }
+        let username = &mut ctx.accounts.username;
+        username.owner = ctx.accounts.new_owner.key();
+        username.user_account = new_user_account.key();
+        username.claimed_at = Clock::get()?.unix_timestamp;
+        new_user_account.username = username.key();
+        ctx.accounts.user_account.username = Pubkey::default();
+        emit!(UsernameTransferred {
+            version: EVENT_VERSION,
+            name: username.name.clone(),
+            from: ctx.accounts.owner.key(),
+            from_account: ctx.accounts.user_account.key(),
+            to: username.owner,
+            to_account: username.user_account,
+            timestamp: username.claimed_at,
+        });
+        Ok(())
+    }
+
+    // The account is resized to fit the new profile, with the owner paying
+    // for any growth and receiving any freed rent.
+    pub fn update_profile(
+        ctx: Context<UpdateProfile>,
+        name: String,
+        bio: String,
+        avatar_uri: Option<String>,
+        socials: Vec<SocialHandle>,
+    ) -> Result<()> {
+        validate_text(&name, MAX_NAME_LEN)?;
+        validate_text(&bio, MAX_BIO_LEN)?;
+        if let Some(avatar_uri) = &avatar_uri {
+            validate_token(avatar_uri, MAX_AVATAR_URI_LEN, |c| c.is_ascii_graphic())?;
+        }
+        if socials.len() > MAX_SOCIAL_HANDLES {
+            return Err(LoyaltyProgramError::ProfileFieldTooLong.into());
+        }
+        for social in &socials {
+            validate_token(&social.handle, MAX_HANDLE_LEN, |c| c.is_ascii_alphanumeric() || "_.-".contains(c))?;
+        }
+
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.name = name;
+        user_account.bio = bio;
+        user_account.avatar_uri = avatar_uri;
+        user_account.socials = socials;
+        emit!(ProfileUpdated {
+            version: EVENT_VERSION,
+            user: user_account.user,
+            user_account: user_account.key(),
+            name: user_account.name.clone(),
+            bio: user_account.bio.clone(),
+            avatar_uri: user_account.avatar_uri.clone(),
+            socials: user_account.socials.clone(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+pub const MAX_BADGES: u8 = 64;
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_BIO_LEN: usize = 160;
+pub const MAX_AVATAR_URI_LEN: usize = 200;
+pub const MAX_SOCIAL_HANDLES: usize = 4;
+pub const MAX_HANDLE_LEN: usize = 32;
+pub const USER_ACCOUNT_VERSION: u8 = 3;
+// Carried as the first field of every event, and bumped whenever an event's
+// fields change so indexers can tell the layouts apart.
+pub const EVENT_VERSION: u8 = 1;
+pub const LOYALTY_STATE_VERSION: u8 = 1;
+pub const PROPOSAL_VERSION: u8 = 1;
+// Zeroed space on versioned accounts that later fixed-size fields can take
+// without moving anything after them.
+pub const ACCOUNT_RESERVED_LEN: usize = 64;
+pub const MIN_USERNAME_LEN: usize = 3;
+pub const MAX_USERNAME_LEN: usize = 20;
+pub const DEFAULT_VOTING_PERIOD: i64 = 3 * 86400;
+pub const DEFAULT_TIMELOCK_DELAY: i64 = 86400;
+pub const LEADERBOARD_SIZE: usize = 10;
+pub const MAX_TITLE_LEN: usize = 80;
+pub const MAX_URI_LEN: usize = 200;
+pub const MAX_PROPOSAL_OPTIONS: usize = 8;
+pub const MAX_OPTION_LABEL_LEN: usize = 32;
+pub const POINT_LOT_DURATION: i64 = 30 * 86400;
+pub const POINT_EXPIRY_WINDOW: i64 = 12 * POINT_LOT_DURATION;
+// Lots older than the expiry window are dropped before new ones are added,
+// so at most one lot per period in the window plus the current one is live.
+pub const MAX_POINT_LOTS: usize = (POINT_EXPIRY_WINDOW / POINT_LOT_DURATION) as usize + 1;
+// Room for about a million compressed users, with proofs valid for 64
+// updates after the root they were made against.
+pub const LOYALTY_TREE_DEPTH: usize = 20;
+pub const LOYALTY_TREE_BUFFER_SIZE: usize = 64;
+
+// Every change to the config's settings emits the full set, so its current
+// state is always the latest event. `authority` is the admin, or the
+// proposal whose action made the change.
+fn emit_config_updated(config: &Config, authority: Pubkey) -> Result<()> {
+    emit!(ConfigUpdated {
+        version: EVENT_VERSION,
+        authority,
+        admin: config.admin,
+        guardian: config.guardian,
+        tier_thresholds: config.tier_thresholds,
+        redemption_rate: config.redemption_rate,
+        referral_reward: config.referral_reward,
+        emission_budget: config.emission_budget,
+        voting_period: config.voting_period,
+        timelock_delay: config.timelock_delay,
+        quorum: config.quorum,
+        governance_mint: config.governance_mint,
+        deposit_vault: config.deposit_vault,
+        proposal_min_tokens: config.proposal_min_tokens,
+        proposal_min_tier: config.proposal_min_tier,
+        proposal_deposit: config.proposal_deposit,
+        max_active_proposals: config.max_active_proposals,
+        executive_voting_power: config.executive_voting_power,
+        redeem_window: config.redeem_window,
+        global_redeem_limit: config.global_redeem_limit,
+        user_redeem_limit: config.user_redeem_limit,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+fn require_not_paused(config: &Config) -> Result<()> {
+    if config.paused {
+        return Err(LoyaltyProgramError::ProgramPaused.into());
+    }
+    Ok(())
+}
+
+// Free text may hold any characters but control characters. Lengths are in bytes.
+fn validate_text(text: &str, max_len: usize) -> Result<()> {
+    if text.len() > max_len {
+        return Err(LoyaltyProgramError::ProfileFieldTooLong.into());
+    }
+    if text.chars().any(char::is_control) {
+        return Err(LoyaltyProgramError::InvalidProfileCharacters.into());
+    }
+    Ok(())
+}
+
+// URIs and handles are non-empty and limited to the characters `allowed` accepts.
+fn validate_token(token: &str, max_len: usize, allowed: impl Fn(char) -> bool) -> Result<()> {
+    if token.len() > max_len {
+        return Err(LoyaltyProgramError::ProfileFieldTooLong.into());
+    }
+    if token.is_empty() || !token.chars().all(allowed) {
+        return Err(LoyaltyProgramError::InvalidProfileCharacters.into());
+    }
+    Ok(())
+}
+
+// Username addresses are seeded with a hash of the lowercase name, since
+// names too long for a seed must still reach `normalize_username` to be
+// rejected.
+pub fn username_seed(name: &str) -> [u8; 32] {
+    keccak::hash(name.to_ascii_lowercase().as_bytes()).to_bytes()
+}
+
+fn normalize_username(name: &str) -> Result<String> {
+    let normalized = name.to_ascii_lowercase();
+    if normalized.len() < MIN_USERNAME_LEN
+        || normalized.len() > MAX_USERNAME_LEN
+        || !normalized.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(LoyaltyProgramError::InvalidUsername.into());
+    }
+    Ok(normalized)
+}
+
+fn validate_proposal_metadata(title: &str, uri: &str) -> Result<()> {
+    if title.is_empty() || title.len() > MAX_TITLE_LEN {
+        return Err(LoyaltyProgramError::InvalidProposalTitle.into());
+    }
+    if uri.len() > MAX_URI_LEN {
+        return Err(LoyaltyProgramError::ProposalUriTooLong.into());
+    }
+    Ok(())
+}
+
+fn require_voting_open(proposal: &Proposal) -> Result<()> {
+    if proposal.status != ProposalStatus::Active {
+        return Err(LoyaltyProgramError::ProposalNotActive.into());
+    }
+    if Clock::get()?.unix_timestamp >= proposal.voting_ends_at {
+        return Err(LoyaltyProgramError::VotingClosed.into());
+    }
+    Ok(())
+}
+
+// Every vote, direct or delegated, has a record at an address derived from
+// the proposal and the voting wallet, so a wallet votes once per proposal
+// however many user accounts it registers. Remaining accounts start with a
+// (delegation, delegator account, delegator loyalty state, delegator token
+// lock, delegator vote record) group per delegator the voter votes for,
+// followed by badge definitions.
+fn cast_vote<'info>(ctx: Context<'_, '_, 'info, 'info, Vote<'info>>, in_favor: bool, choices: Vec<u16>) -> Result<()> {
+    let accounts = ctx.accounts;
+    require_voting_open(&accounts.proposal)?;
+    check_ballot(&accounts.proposal, &choices)?;
+
+    // A record that already exists was either a direct vote, which can't be
+    // repeated, or a delegate's vote, which a direct vote overrides.
+    let vote_record = &mut accounts.vote_record;
+    if vote_record.proposal != Pubkey::default() {
+        if vote_record.delegate.is_none() {
+            return Err(LoyaltyProgramError::AlreadyVoted.into());
+        }
+        let (previous_in_favor, previous_weight) = (vote_record.in_favor, vote_record.weight);
+        tally_ballot(&mut accounts.proposal, previous_in_favor, &vote_record.choices, previous_weight, false);
+    } else {
+        vote_record.payer = accounts.voter.key();
+    }
+
+    let loyalty_points = accounts.voter_loyalty.load()?.loyalty_points;
+    let tokens = match accounts.voter_token_lock.as_mut() {
+        Some(token_lock) => hold_token_lock(token_lock, &accounts.proposal),
+        None => 0,
+    };
+    let voting_power = voting_weight(accounts.proposal.voting_power, &accounts.voter_account, loyalty_points, tokens);
+    tally_ballot(&mut accounts.proposal, in_favor, &choices, voting_power, true);
+    vote_record.proposal = accounts.proposal.key();
+    vote_record.voter = accounts.voter.key();
+    vote_record.in_favor = in_favor;
+    vote_record.weight = voting_power;
+    vote_record.choices = choices.clone();
+    vote_record.delegate = None;
+
+    let (delegations, badge_accounts) = split_delegations(ctx.remaining_accounts);
+    let mut delegated_weight = 0;
+    for delegation in delegations.chunks(DELEGATION_ACCOUNTS) {
+        delegated_weight += vote_for_delegator(accounts, delegation, in_favor, &choices)?;
+    }
+
+    emit!(GovernanceVoted {
+        version: EVENT_VERSION,
+        proposal: accounts.proposal.key(),
+        user: accounts.voter.key(),
+        voter_account: accounts.voter_account.key(),
+        in_favor,
+        choices,
+        weight: voting_power,
+        delegated_weight,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    accounts.voter_account.votes_cast += 1;
+    unlock_badges(&mut accounts.voter_account, badge_accounts)
+}
+
+const DELEGATION_ACCOUNTS: usize = 5;
+
+fn split_delegations<'info>(
+    accounts: &'info [AccountInfo<'info>],
+) -> (&'info [AccountInfo<'info>], &'info [AccountInfo<'info>]) {
+    let is_delegation = |info: &AccountInfo| {
+        info.owner == &crate::ID
+            && info
+                .try_borrow_data()
+                .is_ok_and(|data| data.starts_with(&Delegation::DISCRIMINATOR))
+    };
+    let mut end = 0;
+    while end + DELEGATION_ACCOUNTS <= accounts.len() && is_delegation(&accounts[end]) {
+        end += DELEGATION_ACCOUNTS;
+    }
+    accounts.split_at(end)
+}
+
+// Casts the delegator's weight as the delegate voted by creating the
+// delegator's vote record, and returns the weight counted. Delegators who
+// already voted, directly or through another delegate, are skipped.
+fn vote_for_delegator<'info>(
+    accounts: &mut Vote<'info>,
+    delegation_accounts: &'info [AccountInfo<'info>],
+    in_favor: bool,
+    choices: &[u16],
+) -> Result<u64> {
+    let (delegation_info, delegator_info, loyalty_info, lock_info, record_info) = (
+        &delegation_accounts[0],
+        &delegation_accounts[1],
+        &delegation_accounts[2],
+        &delegation_accounts[3],
+        &delegation_accounts[4],
+    );
+    let delegation = Delegation::try_deserialize(&mut &delegation_info.try_borrow_data()?[..])?;
+    if delegation.delegate != accounts.voter.key()
+        || delegation.delegator_account != delegator_info.key()
+        || delegator_info.owner != &crate::ID
+    {
+        return Err(LoyaltyProgramError::InvalidDelegation.into());
+    }
+    let delegator_account = UserAccount::try_deserialize(&mut &delegator_info.try_borrow_data()?[..])?;
+    if delegator_account.user != delegation.delegator {
+        return Err(LoyaltyProgramError::InvalidDelegation.into());
+    }
+    let loyalty_points = {
+        let loader = AccountLoader::<LoyaltyState>::try_from(loyalty_info)?;
+        let loyalty_state = loader.load()?;
+        if loyalty_state.user_account != delegator_info.key() {
+            return Err(LoyaltyProgramError::InvalidDelegation.into());
+        }
+        loyalty_state.loyalty_points
+    };
+    let (lock_address, _) = Pubkey::find_program_address(
+        &[b"token_lock", delegation.delegator.as_ref(), accounts.config.governance_mint.as_ref()],
+        &crate::ID,
+    );
+    if lock_info.key() != lock_address {
+        return Err(LoyaltyProgramError::InvalidDelegation.into());
+    }
+
+    let proposal_key = accounts.proposal.key();
+    let (address, bump) =
+        Pubkey::find_program_address(&[b"vote", proposal_key.as_ref(), delegation.delegator.as_ref()], &crate::ID);
+    if record_info.key() != address {
+        return Err(LoyaltyProgramError::InvalidDelegation.into());
+    }
+    if !record_info.data_is_empty() {
+        return Ok(0);
+    }
+
+    // A delegator who never locked tokens has none to count. Counted tokens
+    // stay locked until voting ends, as for a direct vote.
+    let tokens = if lock_info.data_is_empty() {
+        0
+    } else {
+        let mut token_lock = Account::<TokenLock>::try_from(lock_info)?;
+        let tokens = hold_token_lock(&mut token_lock, &accounts.proposal);
+        token_lock.exit(&crate::ID)?;
+        tokens
+    };
+
+    let space = VoteRecord::space(accounts.proposal.options.len());
+    let cpi_accounts = system_program::CreateAccount {
+        from: accounts.voter.to_account_info(),
+        to: record_info.clone(),
+    };
+    let signer_seeds: &[&[&[u8]]] = &[&[b"vote", proposal_key.as_ref(), delegation.delegator.as_ref(), &[bump]]];
+    let cpi_program = accounts.system_program.to_account_info();
+    system_program::create_account(
+        CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )?;
+
+    let weight = voting_weight(accounts.proposal.voting_power, &delegator_account, loyalty_points, tokens);
+    tally_ballot(&mut accounts.proposal, in_favor, choices, weight, true);
+    let vote_record = VoteRecord {
+        proposal: proposal_key,
+        voter: delegation.delegator,
+        in_favor,
+        weight,
+        choices: choices.to_vec(),
+        delegate: Some(delegation.delegate),
+        payer: accounts.voter.key(),
+    };
+    vote_record.try_serialize(&mut &mut record_info.try_borrow_mut_data()?[..])?;
+    emit!(DelegatedVoteCast {
+        version: EVENT_VERSION,
+        proposal: proposal_key,
+        delegate: delegation.delegate,
+        delegator: delegation.delegator,
+        delegator_account: delegator_info.key(),
+        weight,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    Ok(weight)
+}
+
+// Returns the locked amount a vote on `proposal` counts, keeping it locked
+// until voting ends when the proposal weighs tokens.
+fn hold_token_lock(token_lock: &mut TokenLock, proposal: &Proposal) -> u64 {
+    if proposal.voting_power.counts_tokens() {
+        token_lock.locked_until = token_lock.locked_until.max(proposal.voting_ends_at);
+    }
+    token_lock.amount
+}
+
+// `tokens` is the voter's locked governance token amount. Blend weights are
+// basis points applied to each of the other measures.
+fn voting_weight(voting_power: VotingPower, user_account: &UserAccount, points: u64, tokens: u64) -> u64 {
+    let tier = user_account.loyalty_tier as u64;
+    match voting_power {
+        VotingPower::Tokens => tokens,
+        VotingPower::LoyaltyPoints => points,
+        VotingPower::Tier => tier,
+        VotingPower::Quadratic => integer_sqrt(tokens),
+        VotingPower::Blend {
+            token_bps,
+            points_bps,
+            tier_bps,
+        } => {
+            let blended =
+                tokens as u128 * token_bps as u128 + points as u128 * points_bps as u128 + tier as u128 * tier_bps as u128;
+            (blended / 10_000) as u64
+        }
+    }
+}
+
+// Largest integer whose square doesn't exceed `value`, by Newton's method.
+fn integer_sqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    let mut root = value;
+    let mut next = (root + value / root) / 2;
+    while next < root {
+        root = next;
+        next = (root + value / root) / 2;
+    }
+    root
+}
+
+// For/against proposals take an empty `choices`. Poll ballots have one entry
+// per option: single-choice ballots select exactly one option and approval
+// ballots any number, while weighted-split entries must sum to 10000.
+fn check_ballot(proposal: &Proposal, choices: &[u16]) -> Result<()> {
+    let selected = choices.iter().filter(|choice| **choice != 0).count();
+    let valid = match proposal.vote_mode {
+        _ if proposal.options.is_empty() => choices.is_empty(),
+        _ if choices.len() != proposal.options.len() => false,
+        VoteMode::SingleChoice => selected == 1,
+        VoteMode::Approval => selected >= 1,
+        VoteMode::WeightedSplit => choices.iter().map(|choice| *choice as u64).sum::<u64>() == 10_000,
+    };
+    if !valid {
+        return Err(LoyaltyProgramError::InvalidBallot.into());
+    }
+    Ok(())
+}
+
+// Adds a checked ballot's weight to the tallies, or takes it back out when
+// `add` is false. Selected poll options receive the full weight, except in
+// weighted-split mode, which divides it by basis points.
+fn tally_ballot(proposal: &mut Proposal, in_favor: bool, choices: &[u16], weight: u64, add: bool) {
+    let apply = |tally: &mut u64, amount: u64| {
+        if add {
+            *tally += amount;
+        } else {
+            *tally -= amount;
+        }
+    };
+    apply(&mut proposal.turnout, weight);
+    if proposal.options.is_empty() {
+        if in_favor {
+            apply(&mut proposal.votes_for, weight);
+        } else {
+            apply(&mut proposal.votes_against, weight);
+        }
+        return;
+    }
+    for (option, choice) in proposal.options.iter_mut().zip(choices) {
+        let amount = match (proposal.vote_mode, *choice) {
+            (_, 0) => 0,
+            (VoteMode::WeightedSplit, bps) => (weight as u128 * bps as u128 / 10_000) as u64,
+            _ => weight,
+        };
+        apply(&mut option.votes, amount);
+    }
+}
+
+// The option with the most votes wins; a tie for first, or no votes at all,
+// leaves the poll without a winner.
+fn winning_option(options: &[ProposalOption]) -> Option<u8> {
+    let (index, leader) = options.iter().enumerate().max_by_key(|(_, option)| option.votes)?;
+    let tied = options.iter().filter(|option| option.votes == leader.votes).count() > 1;
+    if leader.votes == 0 || tied {
+        return None;
+    }
+    Some(index as u8)
+}
+
+// An emission budget of zero means minting is uncapped.
+fn record_emission(config: &mut Config, amount: u64) -> Result<()> {
+    let emitted = config.emitted.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    if config.emission_budget != 0 && emitted > config.emission_budget {
+        return Err(LoyaltyProgramError::EmissionBudgetExceeded.into());
+    }
+    config.emitted = emitted;
+    Ok(())
+}
+
+// A limit of zero disables the check. The window restarts with the first
+// redemption made after the previous one has elapsed.
+fn consume_rate_limit(usage: &mut RateWindow, limit: u64, window: i64, amount: u64, now: i64) -> Result<()> {
+    if limit == 0 {
+        return Ok(());
+    }
+    if now >= usage.start + window {
+        usage.start = now;
+        usage.used = 0;
+    }
+    let total = usage.used.checked_add(amount).ok_or(ProgramError::ArithmeticOverflow)?;
+    if total > limit {
+        return Err(LoyaltyProgramError::RedemptionRateLimited.into());
+    }
+    usage.used = total;
+    Ok(())
+}
+
+fn set_compressed_user(
+    loyalty_tree: &mut LoyaltyTree,
+    root: [u8; 32],
+    previous: &CompressedUser,
+    updated: &CompressedUser,
+    proof: &[[u8; 32]],
+) -> Result<()> {
+    if proof.len() > LOYALTY_TREE_DEPTH {
+        return Err(LoyaltyProgramError::InvalidLeafProof.into());
+    }
+    loyalty_tree
+        .tree
+        .set_leaf(root, previous.hash(), updated.hash(), proof, previous.index)
+        .map_err(|_| LoyaltyProgramError::InvalidLeafProof)?;
+    Ok(())
+}
+
+fn tier_for_points(thresholds: &[u64; 4], points: u64) -> u8 {
+    1 + thresholds.iter().filter(|threshold| points >= **threshold).count() as u8
+}
+
+fn expire_point_lots(loyalty_state: &mut LoyaltyState, now: i64) {
+    let expired: u64 = loyalty_state
+        .lots()
+        .iter()
+        .filter(|lot| lot.expires_at <= now)
+        .map(|lot| lot.points)
+        .sum();
+    let expired_lots = loyalty_state.retain_lots(|lot| lot.expires_at > now);
+    if expired_lots == 0 {
+        return;
+    }
+    let previous_points = loyalty_state.loyalty_points;
+    loyalty_state.loyalty_points = previous_points.saturating_sub(expired);
+    emit!(PointsExpired {
+        version: EVENT_VERSION,
+        user: loyalty_state.user,
+        user_account: loyalty_state.user_account,
+        points: expired,
+        lots: expired_lots,
+        previous_points,
+        new_points: loyalty_state.loyalty_points,
+        timestamp: now,
+    });
+}
+
+// After expiry only the last POINT_EXPIRY_WINDOW worth of periods hold
+// lots, so there is always a free slot for the current one.
+fn credit_points(loyalty_state: &mut LoyaltyState, points: u64, now: i64) -> Result<()> {
+    if points == 0 {
+        return Ok(());
+    }
+    expire_point_lots(loyalty_state, now);
+    loyalty_state.loyalty_points = loyalty_state
+        .loyalty_points
+        .checked_add(points)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let period = (now / POINT_LOT_DURATION) as u32;
+    let lots = loyalty_state.lot_count as usize;
+    match loyalty_state.point_lots[..lots].last_mut() {
+        Some(lot) if lot.period == period => lot.points += points,
+        _ => {
+            loyalty_state.point_lots[lots] = LotSlot {
+                points,
+                expires_at: period as i64 * POINT_LOT_DURATION + POINT_EXPIRY_WINDOW,
+                period,
+                padding: [0; 4],
+            };
+            loyalty_state.lot_count += 1;
+        }
+    }
+    Ok(())
+}
+
+// Consumes the oldest lots first and returns how many points were removed,
+// which is less than requested if the balance runs out.
+fn debit_points(loyalty_state: &mut LoyaltyState, points: u64) -> u64 {
+    let mut remaining = points;
+    let lots = loyalty_state.lot_count as usize;
+    for lot in loyalty_state.point_lots[..lots].iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let taken = lot.points.min(remaining);
+        lot.points -= taken;
+        remaining -= taken;
+    }
+    loyalty_state.retain_lots(|lot| lot.points > 0);
+    let debited = points - remaining;
+    loyalty_state.loyalty_points = loyalty_state.loyalty_points.saturating_sub(debited);
+    debited
+}
+
+// Keeps the leaderboard sorted by points, highest first, holding at most
+// LEADERBOARD_SIZE entries.
+fn record_season_points(season: &mut Season, season_stats: &mut SeasonStats, points: u64, now: i64) -> Result<()> {
+    if now < season.start_time || now >= season.end_time {
+        return Err(LoyaltyProgramError::SeasonNotActive.into());
+    }
+    season_stats.points += points;
+
+    let entry = LeaderboardEntry {
+        user: season_stats.user,
+        points: season_stats.points,
+    };
+    if let Some(existing) = season.leaderboard.iter_mut().find(|existing| existing.user == entry.user) {
+        existing.points = entry.points;
+    } else if season.leaderboard.len() < LEADERBOARD_SIZE {
+        season.leaderboard.push(entry);
+    } else if let Some(lowest) = season.leaderboard.last_mut() {
+        if entry.points > lowest.points {
+            *lowest = entry;
+        }
+    }
+    season.leaderboard.sort_by_key(|entry| std::cmp::Reverse(entry.points));
+    Ok(())
+}
+
+// Badge definitions are passed as remaining accounts; any whose milestone the
+// user has reached gets its bit set in `badges`, to be minted via `claim_badge`.
+fn unlock_badges(user_account: &mut Account<UserAccount>, badge_accounts: &[AccountInfo]) -> Result<()> {
+    for info in badge_accounts {
+        if info.owner != &crate::ID {
+            return Err(LoyaltyProgramError::InvalidBadgeAccount.into());
+        }
+        let badge = BadgeDefinition::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        let bit = 1u64 << badge.badge_id;
+        if user_account.badges & bit != 0 {
+            continue;
+        }
+        let progress = match badge.kind {
+            BadgeKind::Redemptions => user_account.redemptions as u64,
+            BadgeKind::LoyaltyTier => user_account.loyalty_tier as u64,
+            BadgeKind::Referrals => user_account.referrals as u64,
+            BadgeKind::ProposalVotes => user_account.votes_cast as u64,
+        };
+        if progress >= badge.threshold {
+            user_account.badges |= bit;
+            emit!(BadgeUnlocked {
+                version: EVENT_VERSION,
+                user: user_account.user,
+                user_account: user_account.key(),
+                badge_id: badge.badge_id,
+                timestamp: Clock::get()?.unix_timestamp,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(decimals: u8)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        mint::decimals = decimals,
+        mint::authority = authority,
+        mint::freeze_authority = authority
+    )]
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + 32 + 1 + 8 * 4 + 32 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 32 + 32 + 8 + 1 + 8 + 1 + 7,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    // Only the program's upgrade authority can become admin, so no one can
+    // claim the config between deploy and setup.
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ LoyaltyProgramError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminOnly<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetProposalRequirements<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    pub governance_mint: Account<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"deposit_vault", governance_mint.key().as_ref()],
+        bump,
+        token::mint = governance_mint,
+        token::authority = config
+    )]
+    pub deposit_vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(badge_id: u8)]
+pub struct RegisterBadge<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(init, payer = admin, space = 8 + 1 + 1 + 8 + 32, seeds = [b"badge", badge_id.to_le_bytes().as_ref()], bump)]
+    pub badge: Account<'info, BadgeDefinition>,
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"badge_mint", badge_id.to_le_bytes().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = config,
+        mint::freeze_authority = config
+    )]
+    pub badge_mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
     pub rent: Sysvar<'info, Rent>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+#[instruction(badge_id: u8)]
+pub struct ClaimBadge<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, constraint = user_account.user == owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, seeds = [b"badge_mint", badge_id.to_le_bytes().as_ref()], bump)]
+    pub badge_mint: Account<'info, Mint>,
+    #[account(mut, token::mint = badge_mint, token::authority = owner)]
+    pub recipient: Account<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTier<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(has_one = user_account @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+}
+
 #[derive(Accounts)]
 pub struct InitializeUser<'info> {
-    #[account(init, payer = authority, space = 8 + 32 + 8 + 8 + 4 + 100 + 200)]
+    #[account(init, payer = authority, space = UserAccount::space("", "", &None, &[]))]
     pub user_account: Account<'info, UserAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = LoyaltyState::SPACE,
+        seeds = [b"loyalty", user_account.key().as_ref()],
+        bump
+    )]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
@@ -185,103 +2329,1189 @@ pub struct InitializeUser<'info> {
 
 #[derive(Accounts)]
 pub struct RewardEarlyAdopter<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
     #[account(mut)]
-    pub mint: Account<'info, Mint>,
+    pub user_account: Account<'info, UserAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = EarlyAdopterReward::SPACE,
+        seeds = [b"rewarded", user_account.user.as_ref()],
+        bump
+    )]
+    pub reward_record: Account<'info, EarlyAdopterReward>,
     #[account(mut)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut, constraint = recipient.owner == user_account.user @ LoyaltyProgramError::Unauthorized)]
     pub recipient: Account<'info, TokenAccount>,
+    #[account(mut)]
     pub authority: Signer<'info>,
     pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct TrackLoyalty<'info> {
+pub struct BatchRewardEarlyAdopters<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
     #[account(mut)]
-    pub user_account: Account<'info, UserAccount>,
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BatchTrackLoyalty<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ LoyaltyProgramError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateLoyaltyTree<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(zero)]
+    pub loyalty_tree: AccountLoader<'info, LoyaltyTree>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AppendCompressedUser<'info> {
+    #[account(mut, has_one = authority @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_tree: AccountLoader<'info, LoyaltyTree>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TrackCompressedLoyalty<'info> {
+    #[account(mut, has_one = authority @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_tree: AccountLoader<'info, LoyaltyTree>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemCompressedLoyalty<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub loyalty_tree: AccountLoader<'info, LoyaltyTree>,
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub recipient: Account<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TrackLoyalty<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ LoyaltyProgramError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+    #[account(mut)]
+    pub season: Option<Account<'info, Season>>,
+    #[account(mut)]
+    pub season_stats: Option<Account<'info, SeasonStats>>,
+}
+
+#[derive(Accounts)]
+#[instruction(season_id: u32)]
+pub struct CreateSeason<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + 4 + 8 + 8 + 8 + 2 * LEADERBOARD_SIZE + 4 + LEADERBOARD_SIZE * (32 + 8),
+        seeds = [b"season", season_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub season: Account<'info, Season>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct JoinSeason<'info> {
+    pub season: Account<'info, Season>,
+    pub user_account: Account<'info, UserAccount>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 32 + 8 + 1,
+        seeds = [b"season_stats", season.key().as_ref(), user_account.user.as_ref()],
+        bump
+    )]
+    pub season_stats: Account<'info, SeasonStats>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeSeasonReward<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub season: Account<'info, Season>,
+    #[account(mut, has_one = season @ LoyaltyProgramError::SeasonMismatch)]
+    pub season_stats: Account<'info, SeasonStats>,
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut, token::mint = mint, constraint = recipient.owner == season_stats.user @ LoyaltyProgramError::Unauthorized)]
+    pub recipient: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    title: String,
+    uri: String,
+    content_hash: [u8; 32],
+    action: Option<ProposalAction>,
+    instructions: Vec<ProposalInstruction>,
+    vote_mode: VoteMode,
+    option_labels: Vec<String>,
+    voting_power: VotingPower
+)]
+pub struct CreateProposal<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        payer = authority,
+        space = Proposal::space(&title, &uri, &action, &option_labels, &voting_power, &instructions)
+    )]
+    pub proposal: Account<'info, Proposal>,
+    /// CHECK: PDA used only as the expected signer of treasury instructions.
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(mut, constraint = proposer_account.user == authority.key() @ LoyaltyProgramError::Unauthorized)]
+    pub proposer_account: Account<'info, UserAccount>,
+    #[account(
+        mut,
+        token::mint = config.governance_mint,
+        token::authority = authority
+    )]
+    pub proposer_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(mut, address = config.deposit_vault @ LoyaltyProgramError::InvalidDepositAccount)]
+    pub deposit_vault: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(title: String, uri: String)]
+pub struct UpdateProposalMetadata<'info> {
+    #[account(
+        mut,
+        has_one = creator @ LoyaltyProgramError::Unauthorized,
+        realloc = proposal.to_account_info().data_len() + title.len() + uri.len() - proposal.title.len() - proposal.uri.len(),
+        realloc::payer = creator,
+        realloc::zero = false
+    )]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Vote<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub voter: Signer<'info>,
+    #[account(mut, constraint = voter_account.user == voter.key() @ LoyaltyProgramError::Unauthorized)]
+    pub voter_account: Account<'info, UserAccount>,
+    #[account(constraint = voter_loyalty.load()?.user_account == voter_account.key() @ LoyaltyProgramError::Unauthorized)]
+    pub voter_loyalty: AccountLoader<'info, LoyaltyState>,
+    // Token-weighted votes count the tokens locked here; voters without a
+    // lock count none.
+    #[account(
+        mut,
+        seeds = [b"token_lock", voter.key().as_ref(), config.governance_mint.as_ref()],
+        bump = voter_token_lock.bump
+    )]
+    pub voter_token_lock: Option<Account<'info, TokenLock>>,
+    #[account(
+        init_if_needed,
+        payer = voter,
+        space = VoteRecord::space(proposal.options.len()),
+        seeds = [b"vote", proposal.key().as_ref(), voter.key().as_ref()],
+        bump
+    )]
+    pub vote_record: Account<'info, VoteRecord>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DelegateVotes<'info> {
+    #[account(constraint = delegator_account.user == delegator.key() @ LoyaltyProgramError::Unauthorized)]
+    pub delegator_account: Account<'info, UserAccount>,
+    #[account(
+        init_if_needed,
+        payer = delegator,
+        space = 8 + 32 + 32 + 32 + 8,
+        seeds = [b"delegation", delegator_account.key().as_ref()],
+        bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+    #[account(mut)]
+    pub delegator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LockTokens<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(address = config.governance_mint)]
+    pub mint: Account<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + 32 + 32 + 8 + 8 + 1,
+        seeds = [b"token_lock", owner.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub token_lock: Account<'info, TokenLock>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = mint,
+        token::authority = token_lock,
+        seeds = [b"token_lock_vault", token_lock.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = mint, token::authority = owner)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+// Locks stay keyed by the mint they hold, so tokens locked before the
+// governance mint changes can still be withdrawn.
+#[derive(Accounts)]
+pub struct UnlockTokens<'info> {
+    #[account(
+        mut,
+        seeds = [b"token_lock", owner.key().as_ref(), token_lock.mint.as_ref()],
+        bump = token_lock.bump
+    )]
+    pub token_lock: Account<'info, TokenLock>,
+    #[account(mut, seeds = [b"token_lock_vault", token_lock.key().as_ref()], bump)]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut, token::mint = token_lock.mint)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Undelegate<'info> {
+    #[account(mut, close = delegator, has_one = delegator @ LoyaltyProgramError::Unauthorized)]
+    pub delegation: Account<'info, Delegation>,
+    #[account(mut)]
+    pub delegator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeProposal<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut, address = proposal.creator_account @ LoyaltyProgramError::Unauthorized)]
+    pub creator_account: Account<'info, UserAccount>,
+    #[account(mut, address = config.deposit_vault @ LoyaltyProgramError::InvalidDepositAccount)]
+    pub deposit_vault: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = config.governance_mint,
+        token::authority = proposal.creator
+    )]
+    pub creator_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = config.governance_mint,
+        token::authority = treasury
+    )]
+    pub treasury_token_account: Option<Account<'info, TokenAccount>>,
+    /// CHECK: only used to check the owner of `treasury_token_account`.
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    /// CHECK: system-owned PDA that signs treasury instructions and owns the treasury token accounts.
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseProposal<'info> {
+    #[account(mut, close = creator, has_one = creator @ LoyaltyProgramError::Unauthorized)]
+    pub proposal: Account<'info, Proposal>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVoteRecord<'info> {
+    #[account(mut, close = payer, has_one = payer @ LoyaltyProgramError::Unauthorized)]
+    pub vote_record: Account<'info, VoteRecord>,
+    /// CHECK: the record's proposal, which may already have been closed.
+    #[account(address = vote_record.proposal)]
+    pub proposal: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemLoyalty<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, has_one = user_account @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub recipient: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeCatalog<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(init, payer = admin, space = 8 + 32 + 4 + 1, seeds = [b"catalog"], bump)]
+    pub catalog: Account<'info, RewardCatalog>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ListItem<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(mut, seeds = [b"catalog"], bump = catalog.bump)]
+    pub catalog: Account<'info, RewardCatalog>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + 4 + 1 + 8 + 8 + 32 + 8 + 8 + 4 + 8 + 8 + 1,
+        seeds = [b"catalog_item", catalog.item_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub item: Account<'info, CatalogItem>,
+    pub vault: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(item_id: u32)]
+pub struct SetItemActive<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ LoyaltyProgramError::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(mut, seeds = [b"catalog_item", item_id.to_le_bytes().as_ref()], bump)]
+    pub item: Account<'info, CatalogItem>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(item_id: u32)]
+pub struct RedeemItem<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(seeds = [b"catalog"], bump = catalog.bump)]
+    pub catalog: Account<'info, RewardCatalog>,
+    #[account(mut, seeds = [b"catalog_item", item_id.to_le_bytes().as_ref()], bump)]
+    pub item: Account<'info, CatalogItem>,
+    #[account(mut, constraint = user_account.user == owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, has_one = user_account @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + 4,
+        seeds = [b"item_claims", item.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub item_claims: Account<'info, ItemClaims>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + 32 + 32 + 8 + 8 + 1,
+        seeds = [b"redemption", item.key().as_ref(), owner.key().as_ref(), item_claims.count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub redemption: Account<'info, Redemption>,
+    #[account(mut)]
+    pub vault: Option<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = recipient.owner == owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub recipient: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseRedemption<'info> {
+    #[account(seeds = [b"catalog"], bump = catalog.bump, has_one = operator @ LoyaltyProgramError::Unauthorized)]
+    pub catalog: Account<'info, RewardCatalog>,
+    #[account(mut, close = user, has_one = user @ LoyaltyProgramError::Unauthorized)]
+    pub redemption: Account<'info, Redemption>,
+    /// CHECK: receives the receipt's rent, checked against `redemption.user`.
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+    pub operator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BurnTokens<'info> {
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub token_account: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TimeBasedRewards<'info> {
+    #[account(mut)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+}
+
+// The admin vouches for each referral, and the referral record can only be
+// created once per referrer wallet and referred wallet.
+#[derive(Accounts)]
+pub struct ReferUser<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.admin == authority.key() @ LoyaltyProgramError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub referrer_account: Account<'info, UserAccount>,
+    #[account(
+        mut,
+        constraint = referrer_loyalty.load()?.user_account == referrer_account.key() @ LoyaltyProgramError::Unauthorized
+    )]
+    pub referrer_loyalty: AccountLoader<'info, LoyaltyState>,
+    #[account(
+        init,
+        payer = user,
+        space = 8 + 32 + 32 + 8,
+        seeds = [b"referral", referrer_account.user.as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub referral: Account<'info, Referral>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyInactivityPenalty<'info> {
+    #[account(mut)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+}
+
+#[derive(Accounts)]
+pub struct ExpirePoints<'info> {
+    #[account(mut)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserAccount<'info> {
+    #[account(mut, close = owner, constraint = user_account.user == owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, close = owner, has_one = user_account @ LoyaltyProgramError::Unauthorized)]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserAccount<'info> {
+    /// CHECK: decoded by hand, since older layouts don't fit `UserAccount`.
+    #[account(mut, owner = crate::ID)]
+    pub user_account: UncheckedAccount<'info>,
+    // `init_if_needed` so a second migration reaches the version check.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = LoyaltyState::SPACE,
+        seeds = [b"loyalty", user_account.key().as_ref()],
+        bump
+    )]
+    pub loyalty_state: AccountLoader<'info, LoyaltyState>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct ClaimUsername<'info> {
+    #[account(mut, constraint = user_account.user == owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + 4 + MAX_USERNAME_LEN + 32 + 32 + 8,
+        seeds = [b"username", username_seed(&name).as_ref()],
+        bump
+    )]
+    pub username: Account<'info, Username>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseUsername<'info> {
+    #[account(mut, address = username.user_account @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, close = owner, has_one = owner @ LoyaltyProgramError::Unauthorized)]
+    pub username: Account<'info, Username>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferUsername<'info> {
+    #[account(mut, address = username.user_account @ LoyaltyProgramError::Unauthorized)]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut, has_one = owner @ LoyaltyProgramError::Unauthorized)]
+    pub username: Account<'info, Username>,
+    pub owner: Signer<'info>,
+    #[account(mut, constraint = new_user_account.user == new_owner.key() @ LoyaltyProgramError::Unauthorized)]
+    pub new_user_account: Account<'info, UserAccount>,
+    pub new_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(name: String, bio: String, avatar_uri: Option<String>, socials: Vec<SocialHandle>)]
+pub struct UpdateProfile<'info> {
+    #[account(
+        mut,
+        constraint = user_account.user == owner.key() @ LoyaltyProgramError::Unauthorized,
+        realloc = UserAccount::space(&name, &bio, &avatar_uri, &socials),
+        realloc::payer = owner,
+        realloc::zero = false
+    )]
+    pub user_account: Account<'info, UserAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct UserAccount {
+    pub version: u8,
+    pub user: Pubkey,
+    pub loyalty_tier: u8,
+    // Kept for the layout only; token-weighted votes read the voter's
+    // governance token account.
+    pub tokens_held: u64,
+    pub badges: u64,
+    pub badges_minted: u64,
+    pub redemptions: u32,
+    pub referrals: u32,
+    pub votes_cast: u32,
+    pub active_proposals: u8,
+    pub redemption_window: RateWindow,
+    // The claimed `Username` account, or the default key if there is none.
+    pub username: Pubkey,
+    // Set by `batch_reward_early_adopters`; taken from the reserved space.
+    pub early_adopter_rewarded: bool,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN - 1],
+    pub name: String,
+    pub bio: String,
+    pub avatar_uri: Option<String>,
+    pub socials: Vec<SocialHandle>,
+}
+
+impl UserAccount {
+    pub fn space(name: &str, bio: &str, avatar_uri: &Option<String>, socials: &[SocialHandle]) -> usize {
+        8 + 1 + 32 + 1 + 8 + 8 + 8 + 4 + 4 + 4 + 1 + 8 + 8 + 32 + ACCOUNT_RESERVED_LEN
+            + profile_len(name, bio, avatar_uri, socials)
+    }
+}
+
+fn profile_len(name: &str, bio: &str, avatar_uri: &Option<String>, socials: &[SocialHandle]) -> usize {
+    4 + name.len() + 4 + bio.len()
+        + 1 + avatar_uri.as_ref().map_or(0, |uri| 4 + uri.len())
+        + 4 + socials.iter().map(|social| 1 + 4 + social.handle.len()).sum::<usize>()
+}
+
+// Points and activity change on every update, so they live in a fixed-size
+// account read in place rather than alongside the Borsh-encoded profile.
+#[account(zero_copy)]
+pub struct LoyaltyState {
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub loyalty_points: u64,
+    pub last_activity: i64,
+    pub point_lots: [LotSlot; MAX_POINT_LOTS],
+    pub version: u8,
+    pub lot_count: u8,
+    pub padding: [u8; 6],
+    pub reserved: [u64; ACCOUNT_RESERVED_LEN / 8],
+}
+
+impl LoyaltyState {
+    pub const SPACE: usize = 8 + std::mem::size_of::<LoyaltyState>();
+
+    pub fn lots(&self) -> &[LotSlot] {
+        &self.point_lots[..self.lot_count as usize]
+    }
+
+    // Keeps the lots `keep` accepts, in order, and returns how many were dropped.
+    fn retain_lots(&mut self, keep: impl Fn(&LotSlot) -> bool) -> u8 {
+        let mut kept = 0;
+        for index in 0..self.lot_count as usize {
+            let lot = self.point_lots[index];
+            if keep(&lot) {
+                self.point_lots[kept] = lot;
+                kept += 1;
+            }
+        }
+        for lot in self.point_lots[kept..].iter_mut() {
+            *lot = LotSlot::default();
+        }
+        let dropped = self.lot_count - kept as u8;
+        self.lot_count = kept as u8;
+        dropped
+    }
+}
+
+// Loyalty state for users kept as leaves of a `LoyaltyTree` instead of in
+// accounts of their own.
+#[account(zero_copy)]
+pub struct LoyaltyTree {
+    pub authority: Pubkey,
+    pub tree: ConcurrentMerkleTree<LOYALTY_TREE_DEPTH, LOYALTY_TREE_BUFFER_SIZE>,
+}
+
+impl LoyaltyTree {
+    pub const SPACE: usize = 8 + std::mem::size_of::<LoyaltyTree>();
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CompressedUser {
+    pub user: Pubkey,
+    pub index: u32,
+    pub loyalty_points: u64,
+    pub last_activity: i64,
+    pub redemption_window: RateWindow,
+}
+
+impl CompressedUser {
+    // The tree stores the Keccak hash of the Borsh-encoded leaf.
+    pub fn hash(&self) -> [u8; 32] {
+        keccak::hashv(&[&self.try_to_vec().unwrap()]).to_bytes()
+    }
+}
+
+// Encoded the same as an `(index, points)` tuple.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LoyaltyEntry {
+    pub index: u8,
+    pub points: u64,
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct LotSlot {
+    pub points: u64,
+    pub expires_at: i64,
+    pub period: u32,
+    pub padding: [u8; 4],
+}
+
+// The user account layout before versioning, kept so old accounts can be
+// read and migrated. Every such account was allocated `SPACE` bytes.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UserAccountV1 {
+    pub user: Pubkey,
+    pub loyalty_points: u64,
+    pub last_activity: i64,
+    pub loyalty_tier: u8,
+    pub tokens_held: u64,
+    pub name: String,
+    pub bio: String,
+}
+
+impl UserAccountV1 {
+    pub const SPACE: usize = 8 + 32 + 8 + 8 + 4 + 100 + 200;
+
+    // Points earned before expiry existed go into a single lot for the
+    // current period, as if they were earned at migration. The points are
+    // split off, since they move to the user's `LoyaltyState`.
+    pub fn upgrade(self, now: i64) -> (UserAccount, u64, i64, Vec<PointLot>) {
+        let period = (now / POINT_LOT_DURATION) as u32;
+        let point_lots = if self.loyalty_points == 0 {
+            vec![]
+        } else {
+            vec![PointLot {
+                period,
+                points: self.loyalty_points,
+                expires_at: period as i64 * POINT_LOT_DURATION + POINT_EXPIRY_WINDOW,
+            }]
+        };
+        let user_account = UserAccount {
+            version: USER_ACCOUNT_VERSION,
+            user: self.user,
+            loyalty_tier: self.loyalty_tier,
+            tokens_held: self.tokens_held,
+            badges: 0,
+            badges_minted: 0,
+            redemptions: 0,
+            referrals: 0,
+            votes_cast: 0,
+            active_proposals: 0,
+            redemption_window: RateWindow::default(),
+            username: Pubkey::default(),
+            early_adopter_rewarded: false,
+            reserved: [0; ACCOUNT_RESERVED_LEN - 1],
+            name: self.name,
+            bio: self.bio,
+            avatar_uri: None,
+            socials: vec![],
+        };
+        (user_account, self.loyalty_points, self.last_activity, point_lots)
+    }
+}
+
+#[account]
+pub struct Username {
+    pub name: String,
+    pub owner: Pubkey,
+    pub user_account: Pubkey,
+    pub claimed_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SocialPlatform {
+    Twitter,
+    Discord,
+    Telegram,
+    Github,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct SocialHandle {
+    pub platform: SocialPlatform,
+    pub handle: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct PointLot {
+    pub period: u32,
+    pub points: u64,
+    pub expires_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct RateWindow {
+    pub start: i64,
+    pub used: u64,
+}
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,
+    pub bump: u8,
+    pub tier_thresholds: [u64; 4],
+    pub guardian: Pubkey,
+    pub paused: bool,
+    pub redeem_window: i64,
+    pub global_redeem_limit: u64,
+    pub user_redeem_limit: u64,
+    pub global_redemptions: RateWindow,
+    pub redemption_rate: u64,
+    pub referral_reward: u64,
+    pub emission_budget: u64,
+    pub emitted: u64,
+    pub voting_period: i64,
+    pub timelock_delay: i64,
+    pub quorum: u64,
+    pub governance_mint: Pubkey,
+    pub deposit_vault: Pubkey,
+    pub proposal_min_tokens: u64,
+    pub proposal_min_tier: u8,
+    pub proposal_deposit: u64,
+    pub max_active_proposals: u8,
+    // Voting power for proposals that carry an action or instructions.
+    pub executive_voting_power: VotingPower,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BadgeKind {
+    Redemptions,
+    LoyaltyTier,
+    Referrals,
+    ProposalVotes,
+}
+
+#[account]
+pub struct BadgeDefinition {
+    pub badge_id: u8,
+    pub kind: BadgeKind,
+    pub threshold: u64,
+    pub mint: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct LeaderboardEntry {
+    pub user: Pubkey,
+    pub points: u64,
+}
+
+#[account]
+pub struct Season {
+    pub season_id: u32,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub reward_pool: u64,
+    pub rank_shares_bps: [u16; LEADERBOARD_SIZE],
+    pub leaderboard: Vec<LeaderboardEntry>,
+}
+
+#[account]
+pub struct SeasonStats {
+    pub season: Pubkey,
+    pub user: Pubkey,
+    pub points: u64,
+    pub reward_claimed: bool,
+}
+
+#[account]
+pub struct RewardCatalog {
+    pub operator: Pubkey,
+    pub item_count: u32,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RewardKind {
+    VaultToken,
+    PartnerToken,
+    Nft,
+    Voucher,
+}
+
+#[account]
+pub struct CatalogItem {
+    pub item_id: u32,
+    pub kind: RewardKind,
+    pub point_cost: u64,
+    pub amount: u64,
+    pub vault: Pubkey,
+    pub stock_limit: u64,
+    pub redeemed: u64,
+    pub per_user_limit: u32,
+    pub available_from: i64,
+    pub available_until: i64,
+    pub active: bool,
+}
+
+#[account]
+pub struct ItemClaims {
+    pub count: u32,
+}
+
+#[account]
+pub struct Redemption {
+    pub item: Pubkey,
+    pub user: Pubkey,
+    pub points_spent: u64,
+    pub redeemed_at: i64,
+    pub fulfilled: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProposalStatus {
+    Active,
+    Succeeded,
+    Defeated,
+    Executed,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProposalAction {
+    UpdateRewardParams { redemption_rate: u64, referral_reward: u64 },
+    SetTierThresholds { tier_thresholds: [u64; 4] },
+    SetEmissionBudget { emission_budget: u64 },
+    RotateAdmin { new_admin: Pubkey },
+}
+
+impl ProposalAction {
+    pub fn serialized_len(&self) -> usize {
+        1 + match self {
+            ProposalAction::UpdateRewardParams { .. } => 8 + 8,
+            ProposalAction::SetTierThresholds { .. } => 8 * 4,
+            ProposalAction::SetEmissionBudget { .. } => 8,
+            ProposalAction::RotateAdmin { .. } => 32,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VoteMode {
+    SingleChoice,
+    Approval,
+    WeightedSplit,
+}
+
+// How a voter's weight on a proposal is derived from their user account.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VotingPower {
+    Tokens,
+    LoyaltyPoints,
+    Tier,
+    Quadratic,
+    Blend { token_bps: u16, points_bps: u16, tier_bps: u16 },
+}
+
+impl VotingPower {
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            VotingPower::Blend { .. } => 1 + 2 * 3,
+            _ => 1,
+        }
+    }
+
+    pub fn counts_tokens(&self) -> bool {
+        match *self {
+            VotingPower::Tokens | VotingPower::Quadratic => true,
+            VotingPower::Blend { token_bps, .. } => token_bps != 0,
+            VotingPower::LoyaltyPoints | VotingPower::Tier => false,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct ProposalOption {
+    pub label: String,
+    pub votes: u64,
+}
+
+// Proposals without options are decided for or against; those with options
+// are polls decided by `winning_option` under their `vote_mode`.
+// The full proposal text lives off-chain at `uri`; `content_hash` is the
+// SHA-256 of that text, so voters can check what they are voting on.
+#[account]
+pub struct Proposal {
+    pub version: u8,
+    pub title: String,
+    pub uri: String,
+    pub content_hash: [u8; 32],
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub turnout: u64,
+    pub vote_mode: VoteMode,
+    pub options: Vec<ProposalOption>,
+    pub winning_option: Option<u8>,
+    pub voting_power: VotingPower,
+    pub creator: Pubkey,
+    pub creator_account: Pubkey,
+    pub deposit: u64,
+    pub created_at: i64,
+    pub voting_ends_at: i64,
+    pub status: ProposalStatus,
+    pub executable_at: i64,
+    pub reserved: [u8; ACCOUNT_RESERVED_LEN],
+    pub action: Option<ProposalAction>,
+    pub instructions: Vec<ProposalInstruction>,
+}
+
+impl Proposal {
+    // Finalized with nothing left to execute.
+    pub fn is_settled(&self) -> bool {
+        match self.status {
+            ProposalStatus::Active => false,
+            ProposalStatus::Succeeded => self.action.is_none() && self.instructions.is_empty(),
+            ProposalStatus::Defeated | ProposalStatus::Executed => true,
+        }
+    }
+
+    // The serialized size once a winning option is set, so proposals pay rent
+    // only for what they store.
+    pub fn space(
+        title: &str,
+        uri: &str,
+        action: &Option<ProposalAction>,
+        option_labels: &[String],
+        voting_power: &VotingPower,
+        instructions: &[ProposalInstruction],
+    ) -> usize {
+        8 + 1 + 4 + title.len() + 4 + uri.len() + 32
+            + 8 + 8 + 8 + 1 + 4 + option_labels.iter().map(|label| 4 + label.len() + 8).sum::<usize>() + 2
+            + voting_power.serialized_len() + 32 + 32 + 8 + 8 + 8 + 1 + 8 + ACCOUNT_RESERVED_LEN
+            + 1 + action.as_ref().map_or(0, ProposalAction::serialized_len)
+            + 4 + instructions.iter().map(ProposalInstruction::serialized_len).sum::<usize>()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct ProposalAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct ProposalInstruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<ProposalAccountMeta>,
+    pub data: Vec<u8>,
 }
 
-#[derive(Accounts)]
-pub struct CreateProposal<'info> {
-    #[account(init, payer = authority, space = 8 + 256 + 8 + 8)]
-    pub proposal: Account<'info, Proposal>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
+impl ProposalInstruction {
+    pub fn serialized_len(&self) -> usize {
+        32 + 4 + self.accounts.len() * (32 + 1 + 1) + 4 + self.data.len()
+    }
 }
 
-#[derive(Accounts)]
-pub struct Vote<'info> {
-    #[account(mut)]
-    pub proposal: Account<'info, Proposal>,
-    #[account(mut)]
-    pub voter: Signer<'info>,
-    #[account(mut)]
-    pub voter_account: Account<'info, UserAccount>,
+impl From<Instruction> for ProposalInstruction {
+    fn from(instruction: Instruction) -> Self {
+        ProposalInstruction {
+            program_id: instruction.program_id,
+            accounts: instruction
+                .accounts
+                .into_iter()
+                .map(|meta| ProposalAccountMeta {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: instruction.data,
+        }
+    }
 }
 
-#[derive(Accounts)]
-pub struct RedeemLoyalty<'info> {
-    #[account(mut)]
-    pub user_account: Account<'info, UserAccount>,
-    #[account(mut)]
-    pub mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub recipient: Account<'info, TokenAccount>,
-    pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+impl From<&ProposalInstruction> for Instruction {
+    fn from(instruction: &ProposalInstruction) -> Self {
+        Instruction {
+            program_id: instruction.program_id,
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| AccountMeta {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        }
+    }
 }
 
-#[derive(Accounts)]
-pub struct BurnTokens<'info> {
-    #[account(mut)]
-    pub mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub token_account: Account<'info, TokenAccount>,
-    pub authority: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+#[account]
+pub struct VoteRecord {
+    pub proposal: Pubkey,
+    pub voter: Pubkey,
+    pub in_favor: bool,
+    pub weight: u64,
+    pub choices: Vec<u16>,
+    // Set when a delegate cast this vote on the voter's behalf.
+    pub delegate: Option<Pubkey>,
+    // Paid the record's rent and gets it back when the record is closed.
+    pub payer: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct TimeBasedRewards<'info> {
-    #[account(mut)]
-    pub user_account: Account<'info, UserAccount>,
+impl VoteRecord {
+    pub fn space(options: usize) -> usize {
+        8 + 32 + 32 + 1 + 8 + 4 + 2 * options + 1 + 32 + 32
+    }
 }
 
-#[derive(Accounts)]
-pub struct ReferUser<'info> {
-    #[account(mut)]
-    pub referrer_account: Account<'info, UserAccount>,
-    #[account(mut)]
-    pub user: Signer<'info>,
+// One per wallet, so a wallet is paid once however many user accounts it
+// registers.
+#[account]
+pub struct EarlyAdopterReward {
+    pub wallet: Pubkey,
+    pub user_account: Pubkey,
+    pub amount: u64,
+    pub rewarded_at: i64,
 }
 
-#[derive(Accounts)]
-pub struct ApplyInactivityPenalty<'info> {
-    #[account(mut)]
-    pub user_account: Account<'info, UserAccount>,
+impl EarlyAdopterReward {
+    pub const SPACE: usize = 8 + 32 + 32 + 8 + 8;
 }
 
-#[derive(Accounts)]
-pub struct UpdateProfile<'info> {
-    #[account(mut)]
-    pub user_account: Account<'info, UserAccount>,
+#[account]
+pub struct TokenLock {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    // The end of voting on the latest proposal a vote counted these tokens
+    // for. They can't be withdrawn before then.
+    pub locked_until: i64,
+    pub bump: u8,
 }
 
 #[account]
-pub struct UserAccount {
-    pub user: Pubkey,
-    pub loyalty_points: u64,
-    pub last_activity: i64,
-    pub loyalty_tier: u8,
-    pub tokens_held: u64,
-    pub name: String,
-    pub bio: String,
+pub struct Referral {
+    pub referrer: Pubkey,
+    pub referred: Pubkey,
+    pub referred_at: i64,
 }
 
 #[account]
-pub struct Proposal {
-    pub description: String,
-    pub votes_for: u64,
-    pub votes_against: u64,
+pub struct Delegation {
+    pub delegator: Pubkey,
+    pub delegator_account: Pubkey,
+    pub delegate: Pubkey,
+    pub delegated_at: i64,
 }
 
 #[error_code]
@@ -294,35 +3524,646 @@ pub enum LoyaltyProgramError {
     AccountAlreadyInitialized,
     #[msg("Account not initialized.")]
     AccountNotInitialized,
+    #[msg("Badge id must be below 64.")]
+    InvalidBadgeId,
+    #[msg("Badge account is not owned by this program.")]
+    InvalidBadgeAccount,
+    #[msg("Badge has not been earned.")]
+    BadgeNotEarned,
+    #[msg("Badge has already been minted.")]
+    BadgeAlreadyMinted,
+    #[msg("Season must end after it starts.")]
+    InvalidSeasonWindow,
+    #[msg("Rank shares exceed 10000 basis points.")]
+    InvalidRankShares,
+    #[msg("Season is not active.")]
+    SeasonNotActive,
+    #[msg("Season has not ended yet.")]
+    SeasonNotEnded,
+    #[msg("Season stats do not belong to this season or user.")]
+    SeasonMismatch,
+    #[msg("Season reward already claimed.")]
+    SeasonRewardClaimed,
+    #[msg("User is not ranked on the season leaderboard.")]
+    NotRanked,
+    #[msg("Availability window must end after it starts.")]
+    InvalidAvailabilityWindow,
+    #[msg("Item vault is missing or not owned by the catalog.")]
+    InvalidItemVault,
+    #[msg("Item is not available for redemption.")]
+    ItemNotAvailable,
+    #[msg("Item is out of stock.")]
+    ItemOutOfStock,
+    #[msg("Per-user redemption limit reached for this item.")]
+    ItemUserLimitReached,
+    #[msg("Program is paused.")]
+    ProgramPaused,
+    #[msg("Redemption exceeds the rate limit for the current window.")]
+    RedemptionRateLimited,
+    #[msg("Minting would exceed the emission budget.")]
+    EmissionBudgetExceeded,
+    #[msg("Proposal is not active.")]
+    ProposalNotActive,
+    #[msg("Voting period has ended.")]
+    VotingClosed,
+    #[msg("Voting period has not ended yet.")]
+    VotingStillOpen,
+    #[msg("Proposal did not pass.")]
+    ProposalNotSucceeded,
+    #[msg("Proposal timelock has not elapsed.")]
+    TimelockNotElapsed,
+    #[msg("Proposal has no action to execute.")]
+    NoProposalAction,
+    #[msg("Proposal instruction targets this program or requires a signer other than the treasury.")]
+    InvalidProposalInstruction,
+    #[msg("Proposer holds too few tokens and has too low a loyalty tier to create proposals.")]
+    ProposerBelowThreshold,
+    #[msg("Proposer has reached the limit of active proposals.")]
+    ActiveProposalLimitReached,
+    #[msg("Proposal deposit accounts are missing or invalid.")]
+    InvalidDepositAccount,
+    #[msg("Polls need 2 to 8 options with labels of at most 32 bytes, and cannot carry actions or instructions.")]
+    InvalidProposalOptions,
+    #[msg("Ballot does not match the proposal's options or voting mode.")]
+    InvalidBallot,
+    #[msg("Votes cannot be delegated to yourself.")]
+    InvalidDelegate,
+    #[msg("Delegation does not name this voter or does not match the delegator's accounts.")]
+    InvalidDelegation,
+    #[msg("Already voted on this proposal.")]
+    AlreadyVoted,
+    #[msg("Proposal title must be between 1 and 80 bytes.")]
+    InvalidProposalTitle,
+    #[msg("Proposal URI exceeds 200 bytes.")]
+    ProposalUriTooLong,
+    #[msg("Proposal metadata cannot change once voting has started.")]
+    ProposalMetadataLocked,
+    #[msg("Proposal is still active or awaiting execution.")]
+    ProposalNotSettled,
+    #[msg("User account still has active proposals or a username.")]
+    UserAccountInUse,
+    #[msg("Profile field exceeds its maximum length, or too many social handles.")]
+    ProfileFieldTooLong,
+    #[msg("Profile field is empty or contains characters that are not allowed.")]
+    InvalidProfileCharacters,
+    #[msg("Usernames are 3 to 20 letters, digits or underscores.")]
+    InvalidUsername,
+    #[msg("User account already has a username.")]
+    UsernameAlreadySet,
+    #[msg("Account is already on the latest layout.")]
+    AccountAlreadyMigrated,
+    #[msg("Batch entry refers to a missing account.")]
+    InvalidBatchEntry,
+    #[msg("Leaf or proof doesn't match the loyalty tree.")]
+    InvalidLeafProof,
+    #[msg("Loyalty tree is full.")]
+    LoyaltyTreeFull,
+    #[msg("Tokens are locked until voting ends on the proposals they were counted for.")]
+    TokensLocked,
+}
+
+// Every event starts with `version` (EVENT_VERSION) and ends with the
+// cluster `timestamp` of the change. Point and token changes carry the
+// balance before and after, so the current state of every account can be
+// rebuilt from the logs alone.
+
+#[event]
+pub struct MintInitialized {
+    pub version: u8,
+    pub mint: Pubkey,
+    pub authority: Pubkey,
+    pub decimals: u8,
+    pub timestamp: i64,
 }
 
+#[event]
+pub struct ConfigUpdated {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub admin: Pubkey,
+    pub guardian: Pubkey,
+    pub tier_thresholds: [u64; 4],
+    pub redemption_rate: u64,
+    pub referral_reward: u64,
+    pub emission_budget: u64,
+    pub voting_period: i64,
+    pub timelock_delay: i64,
+    pub quorum: u64,
+    pub governance_mint: Pubkey,
+    pub deposit_vault: Pubkey,
+    pub proposal_min_tokens: u64,
+    pub proposal_min_tier: u8,
+    pub proposal_deposit: u64,
+    pub max_active_proposals: u8,
+    pub executive_voting_power: VotingPower,
+    pub redeem_window: i64,
+    pub global_redeem_limit: u64,
+    pub user_redeem_limit: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserInitialized {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub loyalty_state: Pubkey,
+    pub timestamp: i64,
+}
+
+// `user` is the wallet that owns `token_account`.
 #[event]
 pub struct EarlyAdopterRewarded {
+    pub version: u8,
+    pub user: Pubkey,
+    pub token_account: Pubkey,
+    pub amount: u64,
+    pub previous_balance: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EarlyAdoptersBatchRewarded {
+    pub version: u8,
+    pub rewarded: u32,
+    pub skipped: u32,
+    pub accounts_created: u32,
+    pub total_amount: u64,
+    pub recipients: Vec<RewardedUser>,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct RewardedUser {
     pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub token_account: Pubkey,
     pub amount: u64,
+    pub previous_balance: u64,
+    pub new_balance: u64,
 }
 
+// For compressed users, `user_account` is the loyalty tree holding their leaf.
 #[event]
 pub struct LoyaltyPointsTracked {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub points: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+// Emitted with a leaf's new value whenever it is appended or changed; replaying
+// these rebuilds the tree off-chain.
+#[event]
+pub struct CompressedUserChanged {
+    pub version: u8,
+    pub tree: Pubkey,
+    pub leaf: CompressedUser,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoyaltyTreeCreated {
+    pub version: u8,
+    pub tree: Pubkey,
+    pub authority: Pubkey,
+    pub max_depth: u32,
+    pub max_buffer_size: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LoyaltyPointsBatchTracked {
+    pub version: u8,
+    pub entries: Vec<TrackedPoints>,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct TrackedPoints {
     pub user: Pubkey,
+    pub user_account: Pubkey,
     pub points: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
 }
 
+// `weight` is the voter's own weight; `delegated_weight` is what they cast
+// for delegators, each of which also gets a `DelegatedVoteCast`.
 #[event]
 pub struct GovernanceVoted {
+    pub version: u8,
     pub proposal: Pubkey,
     pub user: Pubkey,
+    pub voter_account: Pubkey,
     pub in_favor: bool,
+    pub choices: Vec<u16>,
+    pub weight: u64,
+    pub delegated_weight: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DelegatedVoteCast {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub delegate: Pubkey,
+    pub delegator: Pubkey,
+    pub delegator_account: Pubkey,
+    pub weight: u64,
+    pub timestamp: i64,
 }
 
+// `amount` is the number of tokens minted for the points. For compressed
+// users, `user_account` is the loyalty tree holding their leaf.
 #[event]
 pub struct LoyaltyPointsRedeemed {
+    pub version: u8,
     pub user: Pubkey,
+    pub user_account: Pubkey,
     pub points: u64,
+    pub amount: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
 }
 
+// The points are the referrer's reward and balances.
 #[event]
 pub struct UserReferred {
+    pub version: u8,
     pub referrer: Pubkey,
+    pub referrer_account: Pubkey,
     pub referred: Pubkey,
+    pub points: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+// `points` is what was actually taken, which is less than the penalty when
+// the balance runs out.
+#[event]
+pub struct InactivityPenaltyApplied {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub points: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TierChanged {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub previous_tier: u8,
+    pub new_tier: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadgeRegistered {
+    pub version: u8,
+    pub badge_id: u8,
+    pub kind: BadgeKind,
+    pub threshold: u64,
+    pub mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadgeUnlocked {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub badge_id: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BadgeMinted {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub badge_id: u8,
+    pub mint: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SeasonCreated {
+    pub version: u8,
+    pub season_id: u32,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub reward_pool: u64,
+    pub rank_shares_bps: [u16; LEADERBOARD_SIZE],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SeasonJoined {
+    pub version: u8,
+    pub season_id: u32,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub timestamp: i64,
+}
+
+// `season_points` is the user's season total after the change.
+#[event]
+pub struct SeasonPointsTracked {
+    pub version: u8,
+    pub season_id: u32,
+    pub user: Pubkey,
+    pub points: u64,
+    pub season_points: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SeasonRewardDistributed {
+    pub version: u8,
+    pub season_id: u32,
+    pub user: Pubkey,
+    pub rank: u8,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PointsExpired {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub points: u64,
+    pub lots: u8,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+// `owner` is the wallet that owns `token_account`.
+#[event]
+pub struct TokensBurned {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub token_account: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub previous_balance: u64,
+    pub new_balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CatalogInitialized {
+    pub version: u8,
+    pub catalog: Pubkey,
+    pub operator: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ItemListed {
+    pub version: u8,
+    pub item_id: u32,
+    pub kind: RewardKind,
+    pub point_cost: u64,
+    pub amount: u64,
+    pub vault: Pubkey,
+    pub stock_limit: u64,
+    pub per_user_limit: u32,
+    pub available_from: i64,
+    pub available_until: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ItemActiveChanged {
+    pub version: u8,
+    pub item_id: u32,
+    pub active: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ItemRedeemed {
+    pub version: u8,
+    pub item_id: u32,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub redemption: Pubkey,
+    pub points: u64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RedemptionFulfilled {
+    pub version: u8,
+    pub redemption: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProgramPaused {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProgramUnpaused {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+// Treasury instructions are counted rather than included; read them from
+// the proposal account.
+#[event]
+pub struct ProposalCreated {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub creator: Pubkey,
+    pub creator_account: Pubkey,
+    pub title: String,
+    pub uri: String,
+    pub content_hash: [u8; 32],
+    pub vote_mode: VoteMode,
+    pub voting_power: VotingPower,
+    pub options: Vec<String>,
+    pub action: Option<ProposalAction>,
+    pub instruction_count: u32,
+    pub deposit: u64,
+    pub voting_ends_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalMetadataUpdated {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub title: String,
+    pub uri: String,
+    pub content_hash: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalFinalized {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub succeeded: bool,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub winning_option: Option<u8>,
+    pub executable_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalDepositSettled {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub refunded: bool,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VotesDelegated {
+    pub version: u8,
+    pub delegator: Pubkey,
+    pub delegator_account: Pubkey,
+    pub delegate: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokensLocked {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub previous_amount: u64,
+    pub new_amount: u64,
+    pub locked_until: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokensUnlocked {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub previous_amount: u64,
+    pub new_amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VotesUndelegated {
+    pub version: u8,
+    pub delegator: Pubkey,
+    pub delegator_account: Pubkey,
+    pub delegate: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProposalClosed {
+    pub version: u8,
+    pub proposal: Pubkey,
+    pub creator: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VoteRecordClosed {
+    pub version: u8,
+    pub vote_record: Pubkey,
+    pub proposal: Pubkey,
+    pub voter: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserAccountClosed {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub forfeited_points: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ProfileUpdated {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub name: String,
+    pub bio: String,
+    pub avatar_uri: Option<String>,
+    pub socials: Vec<SocialHandle>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UsernameClaimed {
+    pub version: u8,
+    pub name: String,
+    pub owner: Pubkey,
+    pub user_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UsernameReleased {
+    pub version: u8,
+    pub name: String,
+    pub owner: Pubkey,
+    pub user_account: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UsernameTransferred {
+    pub version: u8,
+    pub name: String,
+    pub from: Pubkey,
+    pub from_account: Pubkey,
+    pub to: Pubkey,
+    pub to_account: Pubkey,
+    pub timestamp: i64,
+}
+
+// `account_version` is the layout the user account was migrated to.
+#[event]
+pub struct UserAccountMigrated {
+    pub version: u8,
+    pub user: Pubkey,
+    pub user_account: Pubkey,
+    pub loyalty_state: Pubkey,
+    pub account_version: u8,
+    pub loyalty_points: u64,
+    pub timestamp: i64,
 }
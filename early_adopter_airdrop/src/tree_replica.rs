@@ -0,0 +1,130 @@
+//! Off-chain copy of a `LoyaltyTree`, rebuilt from `CompressedUserChanged`
+//! events, for looking up leaves and building the proofs the compressed
+//! instructions take.
+
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use spl_concurrent_merkle_tree::node::{empty_node, Node};
+
+use crate::{CompressedUser, CompressedUserChanged, LOYALTY_TREE_DEPTH};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplicaError {
+    // The event belongs to another tree.
+    WrongTree,
+    // The event's leaf index skips past the end of the tree.
+    IndexOutOfOrder { expected: u32, found: u32 },
+    // The data isn't a `CompressedUserChanged` event.
+    NotAnEvent,
+}
+
+pub struct LoyaltyTreeReplica {
+    pub tree: Pubkey,
+    leaves: Vec<CompressedUser>,
+}
+
+impl LoyaltyTreeReplica {
+    pub fn new(tree: Pubkey) -> Self {
+        Self { tree, leaves: Vec::new() }
+    }
+
+    // Builds a replica from every event the tree has emitted, in order.
+    pub fn replay<'a>(
+        tree: Pubkey,
+        events: impl IntoIterator<Item = &'a CompressedUserChanged>,
+    ) -> std::result::Result<Self, ReplicaError> {
+        let mut replica = Self::new(tree);
+        for event in events {
+            replica.apply(event)?;
+        }
+        Ok(replica)
+    }
+
+    pub fn apply(&mut self, event: &CompressedUserChanged) -> std::result::Result<(), ReplicaError> {
+        if event.tree != self.tree {
+            return Err(ReplicaError::WrongTree);
+        }
+        let index = event.leaf.index as usize;
+        if index < self.leaves.len() {
+            self.leaves[index] = event.leaf;
+        } else if index == self.leaves.len() {
+            self.leaves.push(event.leaf);
+        } else {
+            return Err(ReplicaError::IndexOutOfOrder {
+                expected: self.leaves.len() as u32,
+                found: event.leaf.index,
+            });
+        }
+        Ok(())
+    }
+
+    // Decodes the data of a `Program data:` log line (already base64 decoded).
+    pub fn decode_event(data: &[u8]) -> std::result::Result<CompressedUserChanged, ReplicaError> {
+        let discriminator = CompressedUserChanged::discriminator();
+        match data.strip_prefix(&discriminator[..]) {
+            Some(mut body) => CompressedUserChanged::deserialize(&mut body).map_err(|_| ReplicaError::NotAnEvent),
+            None => Err(ReplicaError::NotAnEvent),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn leaf(&self, index: u32) -> Option<&CompressedUser> {
+        self.leaves.get(index as usize)
+    }
+
+    pub fn find(&self, user: &Pubkey) -> Option<&CompressedUser> {
+        self.leaves.iter().find(|leaf| leaf.user == *user)
+    }
+
+    pub fn root(&self) -> Node {
+        let levels = self.levels();
+        levels
+            .last()
+            .and_then(|level| level.first().copied())
+            .unwrap_or_else(|| empty_node(LOYALTY_TREE_DEPTH as u32))
+    }
+
+    pub fn proof(&self, index: u32) -> Option<Vec<Node>> {
+        if index as usize >= self.leaves.len() {
+            return None;
+        }
+        let levels = self.levels();
+        let mut position = index as usize;
+        let mut proof = Vec::with_capacity(LOYALTY_TREE_DEPTH);
+        for (height, level) in levels.iter().take(LOYALTY_TREE_DEPTH).enumerate() {
+            let sibling = position ^ 1;
+            proof.push(level.get(sibling).copied().unwrap_or_else(|| empty_node(height as u32)));
+            position /= 2;
+        }
+        Some(proof)
+    }
+
+    // Only the non-empty prefix of each level is kept; everything to its
+    // right is the empty node for that height.
+    fn levels(&self) -> Vec<Vec<Node>> {
+        let mut levels = vec![self.leaves.iter().map(CompressedUser::hash).collect::<Vec<_>>()];
+        for height in 0..LOYALTY_TREE_DEPTH {
+            let level = &levels[height];
+            let parents = level
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).copied().unwrap_or_else(|| empty_node(height as u32));
+                    hash_pair(&pair[0], &right)
+                })
+                .collect();
+            levels.push(parents);
+        }
+        levels
+    }
+}
+
+fn hash_pair(left: &Node, right: &Node) -> Node {
+    anchor_lang::solana_program::keccak::hashv(&[left, right]).to_bytes()
+}
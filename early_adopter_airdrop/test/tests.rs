@@ -1,529 +1,2734 @@
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::system_program;
-use anchor_lang::solana_program::sysvar::rent;
-use anchor_spl::token::{self, Mint, Token, TokenAccount};
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::instruction::{Instruction, InstructionError};
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::program_stubs::{self, SyscallStubs};
+use anchor_lang::solana_program::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use anchor_lang::solana_program::{system_instruction, system_program, sysvar};
+use anchor_lang::{Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::associated_token::spl_associated_token_account::instruction::create_associated_token_account;
+use anchor_spl::token::spl_token;
 use solana_program_test::*;
-use solana_sdk::{account::Account, signature::Keypair, transaction::Transaction};
-use early_adopter_airdrop::program::early_adopter_airdrop;
-use early_adopter_airdrop::{self, *};
-
-async fn setup_test() -> (
-    ProgramTestContext,
-    Keypair,
-    Pubkey,
-    Keypair,
-    Keypair,
-    Keypair,
-    Pubkey,
-) {
-    let program_id = Pubkey::new_unique();
-    let mint_keypair = Keypair::new();
-    let user_keypair = Keypair::new();
-    let user_account = Keypair::new();
-    let referrer_keypair = Keypair::new();
+use solana_sdk::account::AccountSharedData;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::transaction::TransactionError;
+use solana_sdk::{signature::Keypair, signer::Signer, transaction::Transaction};
+use early_adopter_airdrop::client::{
+    self, badge_address, badge_mint_address, catalog_address, catalog_item_address, claim_username, config_address,
+    delegate_votes, delegation_address, deposit_vault_address, early_adopter_reward_address, execute_proposal, initialize_catalog, loyalty_address,
+    redeem_item, redeem_loyalty, redemption_address, season_address, season_stats_address, set_paused, token_lock_address,
+    track_loyalty, treasury_address, update_profile, username_address, vote, vote_options, vote_record_address, with_delegators,
+};
+use early_adopter_airdrop::*;
 
-    let mut test = ProgramTest::new(
-        "early_adopter_airdrop",
-        program_id,
-        processor!(early_adopter_airdrop::entry),
-    );
+const TIER_THRESHOLDS: [u64; 4] = [100, 1_000, 10_000, 100_000];
+
+// `processor!` wants account infos that outlive the call, which Anchor's
+// generated entrypoint asks for but the test runtime doesn't provide.
+fn entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    early_adopter_airdrop::entry(program_id, accounts, data)
+}
 
-    let context = test.start_with_context().await;
+// Under the native processor `emit!` only prints its event to stdout. These
+// stubs wrap the test runtime's and log the event data instead, so the tests
+// can read events back from a simulation as they would from the SBF build.
+struct EventLogStubs(Box<dyn SyscallStubs>);
 
-    (context, mint_keypair, program_id, user_keypair, user_account, referrer_keypair, program_id)
+struct NoStubs;
+
+impl SyscallStubs for NoStubs {}
+
+impl SyscallStubs for EventLogStubs {
+    fn sol_log(&self, message: &str) {
+        self.0.sol_log(message)
+    }
+    fn sol_log_compute_units(&self) {
+        self.0.sol_log_compute_units()
+    }
+    fn sol_remaining_compute_units(&self) -> u64 {
+        self.0.sol_remaining_compute_units()
+    }
+    fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+        self.0.sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_last_restart_slot(var_addr)
+    }
+    unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.0.sol_memcpy(dst, src, n)
+    }
+    unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+        self.0.sol_memmove(dst, src, n)
+    }
+    unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+        self.0.sol_memcmp(s1, s2, n, result)
+    }
+    unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+        self.0.sol_memset(s, c, n)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.0.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.0.sol_set_return_data(data)
+    }
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        use base64::Engine;
+        let fields: Vec<_> = fields.iter().map(|field| base64::engine::general_purpose::STANDARD.encode(field)).collect();
+        self.0.sol_log(&format!("data: {}", fields.join(" ")))
+    }
+    fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+        self.0.sol_get_processed_sibling_instruction(index)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.0.sol_get_stack_height()
+    }
 }
 
-#[tokio::test]
-async fn test_initialize_mint() {
-    let (mut context, mint_keypair, program_id, _, _, _, _) = setup_test().await;
-    let rent = context.banks_client.get_rent().await.unwrap();
-    let rent_lamports = rent.minimum_balance(Mint::LEN);
+async fn setup_test() -> ProgramTestContext {
+    let test = ProgramTest::new("early_adopter_airdrop", early_adopter_airdrop::ID, processor!(entry));
+    let mut context = test.start_with_context().await;
+    // The runtime installs its stubs while starting up, and every test starts
+    // one before sending transactions, so nothing runs between the two swaps.
+    static LOG_EVENTS: std::sync::Once = std::sync::Once::new();
+    LOG_EVENTS.call_once(|| {
+        let runtime_stubs = program_stubs::set_syscall_stubs(Box::new(NoStubs));
+        program_stubs::set_syscall_stubs(Box::new(EventLogStubs(runtime_stubs)));
+    });
+    // The program runs as a builtin, so stand in for the loader's program
+    // data account, with the payer as upgrade authority.
+    let program_data = AccountSharedData::new_data(
+        1_000_000_000,
+        &UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(context.payer.pubkey()),
+        },
+        &bpf_loader_upgradeable::ID,
+    )
+    .unwrap();
+    context.set_account(&client::program_data_address(), &program_data);
+    context
+}
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: early_adopter_airdrop::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+// The payer signs and pays for every transaction. A fresh blockhash keeps
+// repeated, otherwise identical transactions from being dropped as duplicates.
+async fn transaction(context: &mut ProgramTestContext, instructions: &[Instruction], signers: &[&Keypair]) -> Transaction {
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend(signers);
+    Transaction::new_signed_with_payer(instructions, Some(&context.payer.pubkey()), &all_signers, blockhash)
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    let tx = transaction(context, instructions, signers).await;
+    context.banks_client.process_transaction(tx).await
+}
+
+// The event data an `emit!` logged: the SBF runtime logs it as program data,
+// `EventLogStubs` as a program log.
+fn event_data(log: &str) -> Option<&str> {
+    log.strip_prefix("Program data: ").or_else(|| log.strip_prefix("Program log: data: "))
+}
+
+// Processes the instructions and returns the `T` events they emitted.
+async fn process_events<T: anchor_lang::Event>(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Vec<T> {
+    use base64::Engine;
+    let tx = transaction(context, instructions, signers).await;
+    let simulation = context.banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    process(context, instructions, signers).await.unwrap();
+    simulation
+        .simulation_details
+        .unwrap()
+        .logs
+        .iter()
+        .filter_map(|log| event_data(log))
+        .filter_map(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+        .filter(|data| data.starts_with(&T::DISCRIMINATOR))
+        .map(|data| T::deserialize(&mut &data[8..]).unwrap())
+        .collect()
+}
+
+// The custom error code a failed transaction returned, if any.
+fn error_code(error: BanksClientError) -> Option<u32> {
+    match error.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+fn code(error: LoyaltyProgramError) -> Option<u32> {
+    Some(error.into())
+}
+
+async fn now(context: &mut ProgramTestContext) -> i64 {
+    context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+}
 
-    let tx = Transaction::new_signed_with_payer(
+async fn warp_forward(context: &mut ProgramTestContext, seconds: i64) {
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += seconds;
+    context.set_sysvar(&clock);
+}
+
+fn fund(context: &ProgramTestContext, wallet: &Pubkey) -> Instruction {
+    system_instruction::transfer(&context.payer.pubkey(), wallet, 1_000_000_000)
+}
+
+// Creates a mint with the payer as its authority.
+async fn create_mint(context: &mut ProgramTestContext) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let payer = context.payer.pubkey();
+    process(
+        context,
         &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &mint_keypair.pubkey(),
-                rent_lamports,
-                Mint::LEN as u64,
-                &token::id(),
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
             ),
-            token::instruction::initialize_mint(
-                &token::id(),
-                &mint_keypair.pubkey(),
-                &context.payer.pubkey(),
-                None,
-                9,
-            )
-            .unwrap(),
+            spl_token::instruction::initialize_mint(&spl_token::ID, &mint.pubkey(), &payer, None, 9).unwrap(),
         ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &mint_keypair],
-        context.last_blockhash,
+        &[&mint],
+    )
+    .await
+    .unwrap();
+    mint.pubkey()
+}
+
+async fn create_token_account(context: &mut ProgramTestContext, owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    let payer = context.payer.pubkey();
+    process(context, &[create_associated_token_account(&payer, owner, mint, &spl_token::ID)], &[])
+        .await
+        .unwrap();
+    get_associated_token_address(owner, mint)
+}
+
+async fn mint_to(context: &mut ProgramTestContext, mint: &Pubkey, token_account: &Pubkey, amount: u64) {
+    let payer = context.payer.pubkey();
+    let instruction = spl_token::instruction::mint_to(&spl_token::ID, mint, token_account, &payer, &[], amount).unwrap();
+    process(context, &[instruction], &[]).await.unwrap();
+}
+
+async fn get_token_account(context: &mut ProgramTestContext, token_account: &Pubkey) -> spl_token::state::Account {
+    let account = context.banks_client.get_account(*token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap()
+}
+
+async fn initialize_config(context: &mut ProgramTestContext) {
+    let admin = context.payer.pubkey();
+    process(context, &[client::initialize_config(&admin, TIER_THRESHOLDS)], &[]).await.unwrap();
+}
+
+// Registers `user` with a fresh user account paid for by the payer.
+async fn initialize_user(context: &mut ProgramTestContext, user: &Pubkey) -> Pubkey {
+    let user_account = Keypair::new();
+    let instruction = build(
+        accounts::InitializeUser {
+            user_account: user_account.pubkey(),
+            loyalty_state: loyalty_address(&user_account.pubkey()),
+            authority: context.payer.pubkey(),
+            system_program: system_program::ID,
+        },
+        instruction::InitializeUser { user: *user },
     );
+    process(context, &[instruction], &[&user_account]).await.unwrap();
+    user_account.pubkey()
+}
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+// Lists an item with no stock, per-user or time limits.
+fn list_item(admin: &Pubkey, item_id: u32, vault: Option<&Pubkey>, kind: RewardKind, point_cost: u64, amount: u64) -> Instruction {
+    build(
+        accounts::ListItem {
+            config: config_address(),
+            catalog: catalog_address(),
+            item: catalog_item_address(item_id),
+            vault: vault.copied(),
+            admin: *admin,
+            system_program: system_program::ID,
+        },
+        instruction::ListItem {
+            kind,
+            point_cost,
+            amount,
+            stock_limit: 0,
+            per_user_limit: 0,
+            available_from: 0,
+            available_until: 0,
+        },
+    )
+}
 
-    let mint_account = context
-        .banks_client
-        .get_account(mint_keypair.pubkey())
-        .await
-        .expect("account not found")
-        .expect("account empty");
+// Badge definitions go in the remaining accounts so the unlock check sees them.
+fn refer_user(
+    authority: &Pubkey,
+    referrer: &Pubkey,
+    referrer_account: &Pubkey,
+    user: &Pubkey,
+    badge_ids: &[u8],
+) -> Instruction {
+    client::with_badges(client::refer_user(authority, referrer, referrer_account, user), badge_ids)
+}
+
+// Finalizes a proposal that holds no deposit.
+fn finalize_proposal(proposal: &Pubkey, creator_account: &Pubkey) -> Instruction {
+    build(
+        accounts::FinalizeProposal {
+            config: config_address(),
+            proposal: *proposal,
+            creator_account: *creator_account,
+            deposit_vault: None,
+            creator_token_account: None,
+            treasury_token_account: None,
+            treasury: treasury_address(),
+            token_program: spl_token::ID,
+        },
+        instruction::FinalizeProposal {},
+    )
+}
 
-    let mint_info = Mint::unpack(&mint_account.data).unwrap();
-    assert_eq!(mint_info.decimals, 9);
-    assert_eq!(mint_info.mint_authority, COption::Some(context.payer.pubkey()));
+fn admin_only(admin: &Pubkey) -> accounts::AdminOnly {
+    accounts::AdminOnly {
+        config: config_address(),
+        admin: *admin,
+    }
+}
+
+async fn get_account<T: AccountDeserialize>(context: &mut ProgramTestContext, address: &Pubkey) -> T {
+    let account = context.banks_client.get_account(*address).await.unwrap().unwrap();
+    T::try_deserialize(&mut account.data.as_ref()).unwrap()
+}
+
+async fn account_exists(context: &mut ProgramTestContext, address: &Pubkey) -> bool {
+    context.banks_client.get_account(*address).await.unwrap().is_some()
+}
+
+async fn lamports(context: &mut ProgramTestContext, address: &Pubkey) -> u64 {
+    context.banks_client.get_balance(*address).await.unwrap()
+}
+
+// Token-weighted votes count locked governance tokens, so this mints tokens
+// to the wallet and locks them, returning the lock. The first call creates
+// the governance mint and registers it in the config.
+async fn give_governance_tokens(context: &mut ProgramTestContext, wallet: &Keypair, amount: u64) -> Pubkey {
+    let config: Config = get_account(context, &config_address()).await;
+    let mint = if config.governance_mint == Pubkey::default() {
+        let mint = create_mint(context).await;
+        let admin = context.payer.pubkey();
+        let instruction = client::set_proposal_requirements(&admin, &mint, 0, 0, 0, 0);
+        process(context, &[instruction], &[]).await.unwrap();
+        mint
+    } else {
+        config.governance_mint
+    };
+    let token_account = get_associated_token_address(&wallet.pubkey(), &mint);
+    if !account_exists(context, &token_account).await {
+        create_token_account(context, &wallet.pubkey(), &mint).await;
+    }
+    mint_to(context, &mint, &token_account, amount).await;
+    let fund_wallet = fund(context, &wallet.pubkey());
+    let lock = client::lock_tokens(&wallet.pubkey(), &token_account, &mint, amount);
+    process(context, &[fund_wallet, lock], &[wallet]).await.unwrap();
+    token_lock_address(&wallet.pubkey(), &mint)
+}
+
+// Creates a proposal without a token balance check or deposit.
+fn create_proposal_instruction(
+    proposal: &Pubkey,
+    authority: &Pubkey,
+    proposer_account: &Pubkey,
+    title: &str,
+    action: Option<ProposalAction>,
+    instructions: Vec<ProposalInstruction>,
+) -> Instruction {
+    build(
+        accounts::CreateProposal {
+            config: config_address(),
+            proposal: *proposal,
+            treasury: treasury_address(),
+            proposer_account: *proposer_account,
+            proposer_token_account: None,
+            deposit_vault: None,
+            authority: *authority,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::CreateProposal {
+            title: title.to_string(),
+            uri: "https://example.com/proposals/1".to_string(),
+            content_hash: [7; 32],
+            action,
+            instructions,
+            vote_mode: VoteMode::SingleChoice,
+            option_labels: vec![],
+            voting_power: VotingPower::Tokens,
+        },
+    )
+}
+
+// Creates a proposal from the payer, whose user account is `proposer_account`.
+async fn create_proposal(
+    context: &mut ProgramTestContext,
+    proposer_account: &Pubkey,
+    title: &str,
+    action: Option<ProposalAction>,
+    instructions: Vec<ProposalInstruction>,
+) -> Pubkey {
+    let proposal = Keypair::new();
+    let payer = context.payer.pubkey();
+    let instruction =
+        create_proposal_instruction(&proposal.pubkey(), &payer, proposer_account, title, action, instructions);
+    process(context, &[instruction], &[&proposal]).await.unwrap();
+    proposal.pubkey()
+}
+
+// Creates a poll from the payer with one option per label.
+async fn create_poll(
+    context: &mut ProgramTestContext,
+    proposer_account: &Pubkey,
+    vote_mode: VoteMode,
+    labels: &[&str],
+    voting_power: VotingPower,
+) -> Pubkey {
+    let proposal = Keypair::new();
+    let payer = context.payer.pubkey();
+    let instruction = build(
+        accounts::CreateProposal {
+            config: config_address(),
+            proposal: proposal.pubkey(),
+            treasury: treasury_address(),
+            proposer_account: *proposer_account,
+            proposer_token_account: None,
+            deposit_vault: None,
+            authority: payer,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::CreateProposal {
+            title: "Which chain next?".to_string(),
+            uri: String::new(),
+            content_hash: [0; 32],
+            action: None,
+            instructions: vec![],
+            vote_mode,
+            option_labels: labels.iter().map(|label| label.to_string()).collect(),
+            voting_power,
+        },
+    );
+    process(context, &[instruction], &[&proposal]).await.unwrap();
+    proposal.pubkey()
+}
+
+async fn set_governance_params(context: &mut ProgramTestContext, voting_period: i64, timelock_delay: i64, quorum: u64) {
+    let admin = context.payer.pubkey();
+    let instruction = build(
+        admin_only(&admin),
+        instruction::SetGovernanceParams {
+            voting_period,
+            timelock_delay,
+            quorum,
+            executive_voting_power: VotingPower::Tokens,
+        },
+    );
+    process(context, &[instruction], &[]).await.unwrap();
+}
+
+async fn get_user_account(context: &mut ProgramTestContext, user_account: &Pubkey) -> UserAccount {
+    get_account(context, user_account).await
+}
+
+// Raw account data for the client's decoders, or `None` when there is no
+// account at `address`.
+async fn account_data(context: &mut ProgramTestContext, address: &Pubkey) -> Option<Vec<u8>> {
+    context.banks_client.get_account(*address).await.unwrap().map(|account| account.data)
+}
+
+// Zero-copy accounts are read straight from their bytes after the discriminator.
+async fn get_loyalty_state(context: &mut ProgramTestContext, user_account: &Pubkey) -> LoyaltyState {
+    let account = context.banks_client.get_account(loyalty_address(user_account)).await.unwrap().unwrap();
+    assert_eq!(account.data[..8], LoyaltyState::DISCRIMINATOR);
+    bytemuck::pod_read_unaligned(&account.data[8..])
+}
+
+#[tokio::test]
+async fn test_initialize_mint() {
+    let mut context = setup_test().await;
+    let mint = Keypair::new();
+    let authority = context.payer.pubkey();
+
+    let instruction = client::initialize(&mint.pubkey(), &authority, 6);
+    process(&mut context, &[instruction], &[&mint]).await.unwrap();
+
+    let mint_account = context.banks_client.get_account(mint.pubkey()).await.unwrap().unwrap();
+    assert_eq!(mint_account.owner, spl_token::ID);
+    let mint_info = spl_token::state::Mint::unpack(&mint_account.data).unwrap();
+    assert_eq!(mint_info.decimals, 6);
+    assert_eq!(mint_info.mint_authority, COption::Some(authority));
+    assert_eq!(mint_info.freeze_authority, COption::Some(authority));
 }
 
 #[tokio::test]
 async fn test_initialize_user() {
-    let (mut context, _, program_id, user_keypair, user_account, _, _) = setup_test().await;
+    let mut context = setup_test().await;
+    let user = Keypair::new();
 
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+
+    let user_info = get_user_account(&mut context, &user_account).await;
+    assert_eq!(user_info.user, user.pubkey());
+    assert_eq!(user_info.loyalty_tier, 1);
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.user, user.pubkey());
+    assert_eq!(loyalty_state.user_account, user_account);
+    assert_eq!(loyalty_state.loyalty_points, 0);
+    assert_eq!(loyalty_state.last_activity, now(&mut context).await);
+}
+
+// Drives the program only through the client builders, fetch helpers and
+// error lookup.
+#[tokio::test]
+async fn test_client() {
+    let mut context = setup_test().await;
+    let admin = context.payer.pubkey();
+    process(&mut context, &[client::initialize_config(&admin, TIER_THRESHOLDS)], &[]).await.unwrap();
+
+    let user = Keypair::new();
+    let user_account = Keypair::new();
+    let instructions = [
+        fund(&context, &user.pubkey()),
+        client::initialize_user(&user_account.pubkey(), &admin, &user.pubkey()),
+    ];
+    process(&mut context, &instructions, &[&user_account]).await.unwrap();
+    let user_account = user_account.pubkey();
+    let data = account_data(&mut context, &user_account).await.unwrap();
+    let user_info = client::decode_user_account(&data).unwrap();
+    assert_eq!(user_info.user, user.pubkey());
+    assert!(account_data(&mut context, &Pubkey::new_unique()).await.is_none());
+    let data = account_data(&mut context, &config_address()).await.unwrap();
+    let result = client::decode_user_account(&data);
+    assert!(matches!(result, Err(err) if err == ErrorCode::AccountDiscriminatorMismatch.into()));
+
+    // A user listed twice shares one account
+    let instruction = client::batch_track_loyalty(&admin, &[(user_account, 30), (user_account, 20)]);
+    assert_eq!(instruction.accounts.len(), 3);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let data = account_data(&mut context, &loyalty_address(&user_account)).await.unwrap();
+    let loyalty_state = client::decode_loyalty_state(&data).unwrap();
+    assert_eq!(loyalty_state.loyalty_points, 50);
+
+    let proposal = Keypair::new();
+    let instruction = client::create_proposal(
+        &proposal.pubkey(),
+        &user.pubkey(),
+        &user_account,
+        None,
+        None,
+        instruction::CreateProposal {
+            title: "Raise referral reward".to_string(),
+            uri: "https://example.com/proposals/1".to_string(),
+            content_hash: [7; 32],
+            action: None,
+            instructions: vec![],
+            vote_mode: VoteMode::SingleChoice,
+            option_labels: vec![],
+            voting_power: VotingPower::LoyaltyPoints,
+        },
     );
+    process(&mut context, &[instruction], &[&user, &proposal]).await.unwrap();
+    let proposal = proposal.pubkey();
+    let instruction = client::vote(&proposal, &user.pubkey(), &user_account, None, true);
+    process(&mut context, &[instruction], &[&user]).await.unwrap();
+    let data = account_data(&mut context, &proposal).await.unwrap();
+    let proposal_info = client::decode_proposal(&data).unwrap();
+    assert_eq!(proposal_info.title, "Raise referral reward");
+    assert_eq!(proposal_info.creator_account, user_account);
+    assert_eq!(proposal_info.votes_for, 50);
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // Errors map back to the program's error enum
+    let err = process(&mut context, &[client::set_paused(&user.pubkey(), true)], &[&user]).await.unwrap_err();
+    assert_eq!(client::transaction_program_error(&err.unwrap()).map(u32::from), code(LoyaltyProgramError::Unauthorized));
+    assert_eq!(client::program_error(6000).map(u32::from), code(LoyaltyProgramError::InsufficientFunds));
+    assert!(client::program_error(3012).is_none());
+    for (index, error) in client::PROGRAM_ERRORS.iter().enumerate() {
+        assert_eq!(u32::from(*error), 6000 + index as u32);
+    }
+}
 
-    let user_account_data = context
-        .banks_client
-        .get_account(user_account.pubkey())
-        .await
-        .expect("account not found")
-        .expect("account empty");
+#[tokio::test]
+async fn test_initialize_config_requires_upgrade_authority() {
+    let mut context = setup_test().await;
+    let squatter = Keypair::new();
+    let fund_squatter = fund(&context, &squatter.pubkey());
+    process(&mut context, &[fund_squatter], &[]).await.unwrap();
+
+    let instruction = client::initialize_config(&squatter.pubkey(), TIER_THRESHOLDS);
+    let err = process(&mut context, &[instruction], &[&squatter]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
 
-    let user_info: UserAccount = UserAccount::try_from_slice(&user_account_data.data).unwrap();
-    assert_eq!(user_info.user, user_keypair.pubkey());
-    assert_eq!(user_info.loyalty_points, 0);
+    initialize_config(&mut context).await;
+    let config: Config = get_account(&mut context, &config_address()).await;
+    assert_eq!(config.admin, context.payer.pubkey());
 }
 
 #[tokio::test]
 async fn test_reward_early_adopter() {
-    let (mut context, mint_keypair, program_id, user_keypair, user_account, _, _) = setup_test().await;
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let other = Keypair::new();
+    initialize_config(&mut context).await;
+    let mint = create_mint(&mut context).await;
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let other_account = initialize_user(&mut context, &other.pubkey()).await;
+    let user_token_account = create_token_account(&mut context, &user.pubkey(), &mint).await;
+
+    let amount = 1000;
+    let authority = context.payer.pubkey();
+    let reward = |user_account: &Pubkey, wallet: &Pubkey, amount: u64| {
+        build(
+            accounts::RewardEarlyAdopter {
+                config: config_address(),
+                user_account: *user_account,
+                reward_record: early_adopter_reward_address(wallet),
+                mint,
+                recipient: user_token_account,
+                authority,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::RewardEarlyAdopter { amount },
+        )
+    };
+
+    // The token account must belong to the rewarded user
+    let err = process(&mut context, &[reward(&other_account, &other.pubkey(), amount)], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    process(&mut context, &[reward(&user_account, &user.pubkey(), amount)], &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &user_token_account).await.amount, amount);
+    assert!(get_user_account(&mut context, &user_account).await.early_adopter_rewarded);
+    let record: EarlyAdopterReward = get_account(&mut context, &early_adopter_reward_address(&user.pubkey())).await;
+    assert_eq!((record.user_account, record.amount), (user_account, amount));
+
+    // Paying the same wallet twice fails, even through another user account
+    let instruction = reward(&user_account, &user.pubkey(), amount + 1);
+    assert!(process(&mut context, &[instruction], &[]).await.is_err());
+    let second_account = initialize_user(&mut context, &user.pubkey()).await;
+    let instruction = reward(&second_account, &user.pubkey(), amount + 1);
+    assert!(process(&mut context, &[instruction], &[]).await.is_err());
+    assert_eq!(get_token_account(&mut context, &user_token_account).await.amount, amount);
+}
+
+#[tokio::test]
+async fn test_batch_reward_early_adopters() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let mint = create_mint(&mut context).await;
+    let users: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+    let mut user_accounts = Vec::new();
+    for user in &users {
+        user_accounts.push(initialize_user(&mut context, &user.pubkey()).await);
+    }
+    // Only the first user has a token account yet
+    create_token_account(&mut context, &users[0].pubkey(), &mint).await;
+    let recipients: Vec<_> = users
+        .iter()
+        .map(|user| get_associated_token_address(&user.pubkey(), &mint))
+        .collect();
+
+    let authority = context.payer.pubkey();
+    let batch = |amounts: Vec<u64>, count: usize| {
+        let mut instruction = build(
+            accounts::BatchRewardEarlyAdopters {
+                config: config_address(),
+                mint,
+                authority,
+                token_program: spl_token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::BatchRewardEarlyAdopters { amounts },
+        );
+        for index in 0..count {
+            instruction.accounts.extend([
+                AccountMeta::new(user_accounts[index], false),
+                AccountMeta::new_readonly(users[index].pubkey(), false),
+                AccountMeta::new(recipients[index], false),
+                AccountMeta::new(early_adopter_reward_address(&users[index].pubkey()), false),
+            ]);
+        }
+        instruction
+    };
 
-    // Initialize mint
+    // Each amount needs its own accounts, and token accounts must be the wallet's
+    let err = process(&mut context, &[batch(vec![100, 200], 1)], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidBatchEntry));
+    let mut wrong_recipient = batch(vec![100], 1);
+    wrong_recipient.accounts[8].pubkey = recipients[1];
+    let err = process(&mut context, &[wrong_recipient], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidBatchEntry));
+
+    // The first two users are rewarded, creating the missing token account
+    let first_batch = batch(vec![100, 200], 2);
+    process(&mut context, &[first_batch], &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &recipients[0]).await.amount, 100);
+    assert_eq!(get_token_account(&mut context, &recipients[1]).await.amount, 200);
+    assert!(get_user_account(&mut context, &user_accounts[1]).await.early_adopter_rewarded);
+
+    // Rewarded wallets are skipped when a later batch includes them again,
+    // even through another of their user accounts
+    let second_batch = batch(vec![100, 200, 300], 3);
+    process(&mut context, &[second_batch], &[]).await.unwrap();
+    let second_account = initialize_user(&mut context, &users[0].pubkey()).await;
+    let mut third_batch = batch(vec![100], 1);
+    third_batch.accounts[6].pubkey = second_account;
+    process(&mut context, &[third_batch], &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &recipients[0]).await.amount, 100);
+    assert_eq!(get_token_account(&mut context, &recipients[1]).await.amount, 200);
+    assert_eq!(get_token_account(&mut context, &recipients[2]).await.amount, 300);
+    let config: Config = get_account(&mut context, &config_address()).await;
+    assert_eq!(config.emitted, 600);
+}
+
+// The tree is too big to create through a CPI, so it's allocated up front
+// and then initialized by the program.
+async fn create_loyalty_tree(context: &mut ProgramTestContext) -> Pubkey {
+    let loyalty_tree = Keypair::new();
     let rent = context.banks_client.get_rent().await.unwrap();
-    let rent_lamports = rent.minimum_balance(Mint::LEN);
+    let admin = context.payer.pubkey();
+    let instructions = [
+        system_instruction::create_account(
+            &admin,
+            &loyalty_tree.pubkey(),
+            rent.minimum_balance(LoyaltyTree::SPACE),
+            LoyaltyTree::SPACE as u64,
+            &early_adopter_airdrop::ID,
+        ),
+        build(
+            accounts::CreateLoyaltyTree {
+                config: config_address(),
+                loyalty_tree: loyalty_tree.pubkey(),
+                admin,
+            },
+            instruction::CreateLoyaltyTree {},
+        ),
+    ];
+    process(context, &instructions, &[&loyalty_tree]).await.unwrap();
+    loyalty_tree.pubkey()
+}
 
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &mint_keypair.pubkey(),
-                rent_lamports,
-                Mint::LEN as u64,
-                &token::id(),
-            ),
-            token::instruction::initialize_mint(
-                &token::id(),
-                &mint_keypair.pubkey(),
-                &context.payer.pubkey(),
-                None,
-                9,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &mint_keypair],
-        context.last_blockhash,
+async fn get_loyalty_tree_root(context: &mut ProgramTestContext, loyalty_tree: &Pubkey) -> [u8; 32] {
+    let account = context.banks_client.get_account(*loyalty_tree).await.unwrap().unwrap();
+    assert_eq!(account.data[..8], LoyaltyTree::DISCRIMINATOR);
+    let loyalty_tree: Box<LoyaltyTree> = Box::new(bytemuck::pod_read_unaligned(&account.data[8..]));
+    loyalty_tree.tree.get_root()
+}
+
+fn track_compressed_loyalty(
+    loyalty_tree: &Pubkey,
+    authority: &Pubkey,
+    replica: &tree_replica::LoyaltyTreeReplica,
+    leaf: CompressedUser,
+    points: u64,
+) -> Instruction {
+    build(
+        accounts::TrackCompressedLoyalty {
+            loyalty_tree: *loyalty_tree,
+            authority: *authority,
+        },
+        instruction::TrackCompressedLoyalty {
+            root: replica.root(),
+            leaf,
+            proof: replica.proof(leaf.index).unwrap(),
+            points,
+        },
+    )
+}
+
+// The event the program emits for a leaf's new value.
+fn leaf_changed(tree: Pubkey, leaf: CompressedUser) -> CompressedUserChanged {
+    CompressedUserChanged {
+        version: EVENT_VERSION,
+        tree,
+        leaf,
+        timestamp: leaf.last_activity,
+    }
+}
+
+#[tokio::test]
+async fn test_compressed_loyalty() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let mint = create_mint(&mut context).await;
+    let loyalty_tree = create_loyalty_tree(&mut context).await;
+    let admin = context.payer.pubkey();
+    let mut replica = tree_replica::LoyaltyTreeReplica::new(loyalty_tree);
+    assert_eq!(get_loyalty_tree_root(&mut context, &loyalty_tree).await, replica.root());
+
+    // Each appended user starts at zero points; the replica follows along
+    // from the same leaves the program emits
+    let users: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+    for user in &users {
+        let instruction = build(
+            accounts::AppendCompressedUser {
+                loyalty_tree,
+                authority: admin,
+            },
+            instruction::AppendCompressedUser { user: user.pubkey() },
+        );
+        process(&mut context, &[instruction], &[]).await.unwrap();
+        let leaf = CompressedUser {
+            user: user.pubkey(),
+            index: replica.len() as u32,
+            loyalty_points: 0,
+            last_activity: now(&mut context).await,
+            redemption_window: RateWindow::default(),
+        };
+        replica.apply(&leaf_changed(loyalty_tree, leaf)).unwrap();
+    }
+    assert_eq!(get_loyalty_tree_root(&mut context, &loyalty_tree).await, replica.root());
+
+    // Only the tree authority can append
+    let outsider = Keypair::new();
+    let instruction = build(
+        accounts::AppendCompressedUser {
+            loyalty_tree,
+            authority: outsider.pubkey(),
+        },
+        instruction::AppendCompressedUser { user: outsider.pubkey() },
     );
+    let err = process(&mut context, &[instruction], &[&outsider]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // Two updates proved against the same root both land, since the second
+    // proof is fast-forwarded through the tree's change log
+    let first = *replica.leaf(0).unwrap();
+    let second = *replica.leaf(2).unwrap();
+    let instructions = [
+        track_compressed_loyalty(&loyalty_tree, &admin, &replica, first, 50),
+        track_compressed_loyalty(&loyalty_tree, &admin, &replica, second, 70),
+    ];
+    process(&mut context, &instructions, &[]).await.unwrap();
+    let current_time = now(&mut context).await;
+    for (leaf, points) in [(first, 50), (second, 70)] {
+        let updated = CompressedUser {
+            loyalty_points: points,
+            last_activity: current_time,
+            ..leaf
+        };
+        replica.apply(&leaf_changed(loyalty_tree, updated)).unwrap();
+    }
+    assert_eq!(get_loyalty_tree_root(&mut context, &loyalty_tree).await, replica.root());
 
-    // Initialize user account
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    // A leaf that doesn't match what the tree holds is rejected
+    let forged = CompressedUser {
+        loyalty_points: 1_000,
+        ..*replica.leaf(1).unwrap()
+    };
+    let err = process(&mut context, &[track_compressed_loyalty(&loyalty_tree, &admin, &replica, forged, 1)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidLeafProof));
+
+    // Only the tree authority credits points
+    let leaf = *replica.leaf(1).unwrap();
+    let fund_outsider = fund(&context, &outsider.pubkey());
+    let instruction = track_compressed_loyalty(&loyalty_tree, &outsider.pubkey(), &replica, leaf, 1_000);
+    let err = process(&mut context, &[fund_outsider, instruction], &[&outsider]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    // Redeeming needs the leaf's owner and enough points, and counts towards
+    // the same per-user limit as full accounts
+    let config: Config = get_account(&mut context, &config_address()).await;
+    let rate = config.redemption_rate;
+    let set_limits = client::set_redemption_limits(&admin, 86_400, 0, 25 * rate);
+    process(&mut context, &[set_limits], &[]).await.unwrap();
+    let recipient = create_token_account(&mut context, &users[0].pubkey(), &mint).await;
+    let redeem = |replica: &tree_replica::LoyaltyTreeReplica, owner: &Pubkey, points: u64| {
+        let leaf = *replica.leaf(0).unwrap();
+        build(
+            accounts::RedeemCompressedLoyalty {
+                config: config_address(),
+                loyalty_tree,
+                mint,
+                recipient,
+                owner: *owner,
+                authority: admin,
+                token_program: spl_token::ID,
+            },
+            instruction::RedeemCompressedLoyalty {
+                root: replica.root(),
+                leaf,
+                proof: replica.proof(leaf.index).unwrap(),
+                points,
+            },
+        )
+    };
+    let err = process(&mut context, &[redeem(&replica, &users[0].pubkey(), 51)], &[&users[0]])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InsufficientFunds));
+    let err = process(&mut context, &[redeem(&replica, &users[1].pubkey(), 10)], &[&users[1]])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    process(&mut context, &[redeem(&replica, &users[0].pubkey(), 20)], &[&users[0]])
+        .await
+        .unwrap();
+    assert_eq!(get_token_account(&mut context, &recipient).await.amount, 20 * rate);
+    let current_time = now(&mut context).await;
+    let updated = CompressedUser {
+        loyalty_points: 30,
+        last_activity: current_time,
+        redemption_window: RateWindow {
+            start: current_time,
+            used: 20 * rate,
+        },
+        ..*replica.leaf(0).unwrap()
+    };
+    replica.apply(&leaf_changed(loyalty_tree, updated)).unwrap();
+    assert_eq!(get_loyalty_tree_root(&mut context, &loyalty_tree).await, replica.root());
+    let err = process(&mut context, &[redeem(&replica, &users[0].pubkey(), 10)], &[&users[0]])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::RedemptionRateLimited));
+
+    // Replaying the events in order rebuilds the same tree; events for other
+    // trees or that skip ahead are refused
+    let events: Vec<_> = (0..replica.len() as u32)
+        .map(|index| leaf_changed(loyalty_tree, *replica.leaf(index).unwrap()))
+        .collect();
+    let rebuilt = tree_replica::LoyaltyTreeReplica::replay(loyalty_tree, &events).unwrap();
+    assert_eq!(rebuilt.root(), replica.root());
+    assert_eq!(rebuilt.find(&users[2].pubkey()).unwrap().loyalty_points, 70);
+    let mut other = tree_replica::LoyaltyTreeReplica::new(Pubkey::new_unique());
+    assert_eq!(other.apply(&events[0]), Err(tree_replica::ReplicaError::WrongTree));
+    let mut fresh = tree_replica::LoyaltyTreeReplica::new(loyalty_tree);
+    assert_eq!(
+        fresh.apply(&events[2]),
+        Err(tree_replica::ReplicaError::IndexOutOfOrder { expected: 0, found: 2 })
     );
+    let mut data = CompressedUserChanged::discriminator().to_vec();
+    data.extend(events[1].try_to_vec().unwrap());
+    assert_eq!(tree_replica::LoyaltyTreeReplica::decode_event(&data).unwrap().leaf, events[1].leaf);
+}
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+#[tokio::test]
+async fn test_track_loyalty() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let payer = context.payer.pubkey();
+    let user = Keypair::new();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+
+    let points = 50;
+    process(&mut context, &[track_loyalty(&payer, &user_account, &user.pubkey(), None, points)], &[])
+        .await
+        .unwrap();
+
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, points);
+
+    // Only the admin credits points
+    let fund_user = fund(&context, &user.pubkey());
+    let instruction = track_loyalty(&user.pubkey(), &user_account, &user.pubkey(), None, points);
+    let err = process(&mut context, &[fund_user, instruction], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+}
 
-    // Create associated token account for the user
-    let user_token_account = token::create_associated_token_account(
-        &context.payer.pubkey(),
-        &user_keypair.pubkey(),
-        &mint_keypair.pubkey(),
+// Before the split, every point update decoded and re-encoded the whole user
+// account, so its cost grew with the profile. That round trip is measured
+// here as a no-op profile update.
+#[tokio::test]
+async fn test_track_loyalty_skips_profile() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let payer = context.payer.pubkey();
+    let full_user = Keypair::new();
+    let full_account = initialize_user(&mut context, &full_user.pubkey()).await;
+    let fund_wallet = fund(&context, &full_user.pubkey());
+    process(&mut context, &[fund_wallet], &[]).await.unwrap();
+    let avatar_uri = Some(format!("https://{}", "a".repeat(MAX_AVATAR_URI_LEN - 8)));
+    let socials: Vec<_> = [SocialPlatform::Twitter, SocialPlatform::Discord, SocialPlatform::Telegram, SocialPlatform::Github]
+        .into_iter()
+        .take(MAX_SOCIAL_HANDLES)
+        .map(|platform| SocialHandle {
+            platform,
+            handle: "h".repeat(MAX_HANDLE_LEN),
+        })
+        .collect();
+    let full_profile = update_profile(
+        &full_user.pubkey(),
+        &full_account,
+        &"n".repeat(MAX_NAME_LEN),
+        &"b".repeat(MAX_BIO_LEN),
+        avatar_uri,
+        socials,
     );
+    process(&mut context, &[full_profile], &[&full_user]).await.unwrap();
 
-    let amount = 1000;
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            early_adopter_airdrop::instruction::reward_early_adopter(
-                &program_id,
-                &mint_keypair.pubkey(),
-                &user_token_account,
-                &context.payer.pubkey(),
-                amount,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    // Point updates only touch the fixed-size loyalty state, however large the profile
+    let instruction = track_loyalty(&payer, &full_account, &full_user.pubkey(), None, 50);
+    assert!(instruction.accounts.iter().all(|meta| meta.pubkey != full_account));
+    assert!(instruction.accounts.iter().any(|meta| meta.pubkey == loyalty_address(&full_account)));
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let state = get_loyalty_state(&mut context, &full_account).await;
+    assert_eq!(state.loyalty_points, 50);
+}
+
+#[tokio::test]
+async fn test_batch_track_loyalty() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let mut users = Vec::new();
+    for _ in 0..20 {
+        let user = Keypair::new();
+        let user_account = initialize_user(&mut context, &user.pubkey()).await;
+        users.push((user, user_account));
+    }
+    let batch_from = |authority: &Pubkey, entries: Vec<LoyaltyEntry>, accounts: &[Pubkey]| {
+        let mut instruction = build(
+            accounts::BatchTrackLoyalty {
+                config: config_address(),
+                authority: *authority,
+            },
+            instruction::BatchTrackLoyalty { entries },
+        );
+        instruction
+            .accounts
+            .extend(accounts.iter().map(|address| AccountMeta::new(*address, false)));
+        instruction
+    };
+    let loyalty_accounts: Vec<_> = users.iter().map(|(_, user_account)| loyalty_address(user_account)).collect();
+    let entries: Vec<_> = (0..20u8)
+        .map(|index| LoyaltyEntry {
+            index,
+            points: 10 * (index as u64 + 1),
+        })
+        .collect();
+    let batch = |entries: Vec<LoyaltyEntry>, accounts: &[Pubkey]| batch_from(&admin, entries, accounts);
+
+    // Only the admin credits points
+    let outsider = &users[0].0;
+    let fund_outsider = fund(&context, &outsider.pubkey());
+    let instruction = batch_from(&outsider.pubkey(), entries.clone(), &loyalty_accounts);
+    let err = process(&mut context, &[fund_outsider, instruction], &[outsider]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    // Entries must point at loyalty state accounts that were passed in
+    let missing = LoyaltyEntry { index: 20, points: 1 };
+    let err = process(&mut context, &[batch(vec![missing], &loyalty_accounts)], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidBatchEntry));
+    let err = process(&mut context, &[batch(vec![entries[0]], &[users[0].1])], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::AccountDiscriminatorMismatch.into()));
+
+    // Twenty users fit in one transaction and share a single event. Their
+    // compute cost is not benchmarked here.
+    let instruction = batch(entries, &loyalty_accounts);
+    let tx = transaction(&mut context, std::slice::from_ref(&instruction), &[]).await;
+    assert!(1 + tx.signatures.len() * 64 + tx.message.serialize().len() <= PACKET_DATA_SIZE);
+    let events: Vec<LoyaltyPointsBatchTracked> = process_events(&mut context, &[instruction], &[]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].entries.len(), 20);
+    for (index, (_, user_account)) in users.iter().enumerate() {
+        let loyalty_state = get_loyalty_state(&mut context, user_account).await;
+        assert_eq!(loyalty_state.loyalty_points, 10 * (index as u64 + 1));
+        assert_eq!(loyalty_state.last_activity, now(&mut context).await);
+    }
+}
+
+#[tokio::test]
+async fn test_burn_tokens() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let mint = create_mint(&mut context).await;
+    let user_token_account = create_token_account(&mut context, &user.pubkey(), &mint).await;
+
+    let mint_amount = 1000;
+    mint_to(&mut context, &mint, &user_token_account, mint_amount).await;
+
+    let burn_amount = 500;
+    let instruction = build(
+        accounts::BurnTokens {
+            mint,
+            token_account: user_token_account,
+            authority: user.pubkey(),
+            token_program: spl_token::ID,
+        },
+        instruction::BurnTokens { amount: burn_amount },
     );
+    process(&mut context, &[instruction], &[&user]).await.unwrap();
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &user_token_account).await.amount, mint_amount - burn_amount);
+}
 
-    // Fetch the user's token account
-    let user_token_account_info = context
-        .banks_client
-        .get_account(user_token_account)
+#[tokio::test]
+async fn test_apply_inactivity_penalty() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let payer = context.payer.pubkey();
+    let user = Keypair::new();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+
+    let points = 50;
+    process(&mut context, &[track_loyalty(&payer, &user_account, &user.pubkey(), None, points)], &[])
         .await
-        .expect("account not found")
-        .expect("account empty");
+        .unwrap();
+
+    // A week without activity costs 10 points
+    warp_forward(&mut context, 7 * 86400).await;
+    let instruction = build(
+        accounts::ApplyInactivityPenalty {
+            loyalty_state: loyalty_address(&user_account),
+        },
+        instruction::ApplyInactivityPenalty {},
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
 
-    let user_token_info = TokenAccount::unpack(&user_token_account_info.data).unwrap();
-    assert_eq!(user_token_info.amount, amount);
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, points - 10);
 }
 
+// Instructions that used to emit nothing now report what changed, with the
+// balances on either side and the wallet rather than the token account.
 #[tokio::test]
-async fn test_track_loyalty() {
-    let (mut context, _, program_id, user_keypair, user_account, _, _) = setup_test().await;
+async fn test_event_schema() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let user = Keypair::new();
+    let user_account = Keypair::new();
+    let authority = context.payer.pubkey();
+    let fund_user = fund(&context, &user.pubkey());
+    process(&mut context, &[fund_user], &[]).await.unwrap();
 
-    // Initialize user account
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    let mint = Keypair::new();
+    let instruction = client::initialize(&mint.pubkey(), &authority, 9);
+    let events: Vec<MintInitialized> = process_events(&mut context, &[instruction], &[&mint]).await;
+    let mint = mint.pubkey();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].mint, events[0].authority), (mint, authority));
+    assert_eq!(events[0].decimals, 9);
+
+    let instruction = build(
+        accounts::InitializeUser {
+            user_account: user_account.pubkey(),
+            loyalty_state: loyalty_address(&user_account.pubkey()),
+            authority,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeUser { user: user.pubkey() },
     );
+    let events: Vec<UserInitialized> = process_events(&mut context, &[instruction], &[&user_account]).await;
+    let user_account = user_account.pubkey();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].version, EVENT_VERSION);
+    assert_eq!((events[0].user, events[0].user_account), (user.pubkey(), user_account));
+    assert_eq!(events[0].timestamp, now(&mut context).await);
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    let instruction = track_loyalty(&authority, &user_account, &user.pubkey(), None, 50);
+    let events: Vec<LoyaltyPointsTracked> = process_events(&mut context, &[instruction], &[]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].previous_points, events[0].new_points), (0, 50));
+    assert_eq!((events[0].user, events[0].user_account), (user.pubkey(), user_account));
 
-    let points = 50;
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            early_adopter_airdrop::instruction::track_loyalty(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                points,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    warp_forward(&mut context, 7 * 86400).await;
+    let instruction = build(
+        accounts::ApplyInactivityPenalty {
+            loyalty_state: loyalty_address(&user_account),
+        },
+        instruction::ApplyInactivityPenalty {},
     );
+    let events: Vec<InactivityPenaltyApplied> = process_events(&mut context, &[instruction], &[]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].points, 10);
+    assert_eq!((events[0].previous_points, events[0].new_points), (50, 40));
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    let token_account = create_token_account(&mut context, &user.pubkey(), &mint).await;
+    let instruction = client::reward_early_adopter(&user_account, &user.pubkey(), &mint, &token_account, &authority, 1000);
+    let events: Vec<EarlyAdopterRewarded> = process_events(&mut context, &[instruction], &[]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].user, events[0].token_account), (user.pubkey(), token_account));
+    assert_eq!((events[0].previous_balance, events[0].new_balance), (0, 1000));
 
-    // Fetch the user account to verify loyalty points
-    let user_account_data = context
-        .banks_client
-        .get_account(user_account.pubkey())
-        .await
-        .expect("account not found")
-        .expect("account empty");
+    let instruction = build(
+        accounts::BurnTokens {
+            mint,
+            token_account,
+            authority: user.pubkey(),
+            token_program: spl_token::ID,
+        },
+        instruction::BurnTokens { amount: 400 },
+    );
+    let events: Vec<TokensBurned> = process_events(&mut context, &[instruction], &[&user]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].owner, user.pubkey());
+    assert_eq!((events[0].previous_balance, events[0].new_balance), (1000, 600));
+
+    let instruction = update_profile(&user.pubkey(), &user_account, "Name", "Bio.", None, Vec::new());
+    let events: Vec<ProfileUpdated> = process_events(&mut context, &[instruction], &[&user]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].name.as_str(), events[0].bio.as_str()), ("Name", "Bio."));
 
-    let user_info: UserAccount = UserAccount::try_from_slice(&user_account_data.data).unwrap();
-    assert_eq!(user_info.loyalty_points, points);
+    let proposal = Keypair::new();
+    let instruction = create_proposal_instruction(&proposal.pubkey(), &user.pubkey(), &user_account, "Title", None, vec![]);
+    let events: Vec<ProposalCreated> = process_events(&mut context, &[instruction], &[&user, &proposal]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].proposal, events[0].creator_account), (proposal.pubkey(), user_account));
+    assert_eq!(events[0].title, "Title");
+    assert_eq!(get_token_account(&mut context, &token_account).await.amount, 600);
+    assert_eq!(get_loyalty_state(&mut context, &user_account).await.loyalty_points, 40);
+    // The referrer is the wallet the referrer account belongs to
+    let referred = Keypair::new();
+    let fund_referred = fund(&context, &referred.pubkey());
+    process(&mut context, &[fund_referred], &[]).await.unwrap();
+    let instruction = refer_user(&authority, &user.pubkey(), &user_account, &referred.pubkey(), &[]);
+    let events: Vec<UserReferred> = process_events(&mut context, &[instruction], &[&referred]).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].referrer, events[0].referrer_account), (user.pubkey(), user_account));
+    assert_eq!(events[0].referred, referred.pubkey());
 }
 
 #[tokio::test]
-async fn test_burn_tokens() {
-    let (mut context, mint_keypair, program_id, user_keypair, user_account, _, _) = setup_test().await;
+async fn test_update_profile() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let fund_user = fund(&context, &user.pubkey());
+    process(&mut context, &[fund_user], &[]).await.unwrap();
+
+    let name = "Users Name";
+    let bio = "Users Bio.";
+    let socials = vec![SocialHandle {
+        platform: SocialPlatform::Github,
+        handle: "users-handle".to_string(),
+    }];
+    let avatar_uri = Some("https://example.com/avatar.png".to_string());
+    let instruction = update_profile(&user.pubkey(), &user_account, name, bio, avatar_uri.clone(), socials.clone());
+    process(&mut context, &[instruction], &[&user]).await.unwrap();
+
+    let user_info = get_user_account(&mut context, &user_account).await;
+    assert_eq!(user_info.name, name);
+    assert_eq!(user_info.bio, bio);
+    assert_eq!(user_info.avatar_uri, avatar_uri);
+    assert!(user_info.socials == socials);
+}
 
-    // Initialize mint
+#[tokio::test]
+async fn test_profile_validation_and_resizing() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let other = Keypair::new();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let fund_wallets = [fund(&context, &user.pubkey()), fund(&context, &other.pubkey())];
+    process(&mut context, &fund_wallets, &[]).await.unwrap();
+    let initial_len = context.banks_client.get_account(user_account).await.unwrap().unwrap().data.len();
+    let update = |name: &str, bio: &str, socials: Vec<SocialHandle>| {
+        update_profile(&user.pubkey(), &user_account, name, bio, None, socials)
+    };
+
+    // Only the owner edits the profile
+    let err = process(&mut context, &[update_profile(&other.pubkey(), &user_account, "Name", "", None, vec![])], &[&other])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    let long_name = "x".repeat(MAX_NAME_LEN + 1);
+    let err = process(&mut context, &[update(&long_name, "", vec![])], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProfileFieldTooLong));
+    let err = process(&mut context, &[update("Na\u{7}me", "", vec![])], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidProfileCharacters));
+    let bad_handle = vec![SocialHandle {
+        platform: SocialPlatform::Twitter,
+        handle: "not a handle".to_string(),
+    }];
+    let err = process(&mut context, &[update("Name", "", bad_handle)], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidProfileCharacters));
+
+    // The owner pays for a longer profile
+    let bio = "Early adopter. Ça va? 🚀";
+    let balance = context.banks_client.get_balance(user.pubkey()).await.unwrap();
+    process(&mut context, &[update("Name", bio, vec![])], &[&user]).await.unwrap();
+    let account = context.banks_client.get_account(user_account).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), initial_len + "Name".len() + bio.len());
     let rent = context.banks_client.get_rent().await.unwrap();
-    let rent_lamports = rent.minimum_balance(Mint::LEN);
+    let growth = rent.minimum_balance(account.data.len()) - rent.minimum_balance(initial_len);
+    assert_eq!(context.banks_client.get_balance(user.pubkey()).await.unwrap(), balance - growth);
 
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &mint_keypair.pubkey(),
-                rent_lamports,
-                Mint::LEN as u64,
-                &token::id(),
-            ),
-            token::instruction::initialize_mint(
-                &token::id(),
-                &mint_keypair.pubkey(),
-                &context.payer.pubkey(),
-                None,
-                9,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &mint_keypair],
-        context.last_blockhash,
+    // and gets the rent back when it shrinks
+    process(&mut context, &[update("", "", vec![])], &[&user]).await.unwrap();
+    let account = context.banks_client.get_account(user_account).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), initial_len);
+    assert_eq!(context.banks_client.get_balance(user.pubkey()).await.unwrap(), balance);
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_user_accounts() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let payer = Keypair::new();
+    let fund_wallets = [fund(&context, &payer.pubkey()), fund(&context, &user.pubkey())];
+    process(&mut context, &fund_wallets, &[]).await.unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+
+    // Write a user in the original layout, byte by byte
+    let mut data = UserAccount::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(user.pubkey().as_ref());
+    data.extend_from_slice(&1_234u64.to_le_bytes());
+    data.extend_from_slice(&42i64.to_le_bytes());
+    data.push(2);
+    data.extend_from_slice(&500u64.to_le_bytes());
+    for text in ["Old Timer", "Here since v1"] {
+        data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        data.extend_from_slice(text.as_bytes());
+    }
+    data.resize(UserAccountV1::SPACE, 0);
+    assert_eq!(data.len(), 360);
+
+    // Points from the original layout start a lot in the current period
+    let period = (now(&mut context).await / POINT_LOT_DURATION) as u32;
+    let expected_lots = vec![(period, 1_234, period as i64 * POINT_LOT_DURATION + POINT_EXPIRY_WINDOW)];
+    let user_account = Pubkey::new_unique();
+    let mut account = AccountSharedData::new(rent.minimum_balance(data.len()), data.len(), &early_adopter_airdrop::ID);
+    account.set_data_from_slice(&data);
+    context.set_account(&user_account, &account);
+
+    let migrate = build(
+        accounts::MigrateUserAccount {
+            user_account,
+            loyalty_state: loyalty_address(&user_account),
+            payer: payer.pubkey(),
+            system_program: system_program::ID,
+        },
+        instruction::MigrateUserAccount {},
     );
+    let balance = lamports(&mut context, &payer.pubkey()).await;
+    process(&mut context, std::slice::from_ref(&migrate), &[&payer]).await.unwrap();
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // A smaller layout keeps its rent, a larger one is topped up; the
+    // payer covers that and the loyalty state
+    let space = UserAccount::space("Old Timer", "Here since v1", &None, &[]);
+    let account = context.banks_client.get_account(user_account).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), space);
+    let account_rent = rent.minimum_balance(data.len()).max(rent.minimum_balance(space));
+    assert_eq!(account.lamports, account_rent);
+    assert_eq!(
+        lamports(&mut context, &payer.pubkey()).await,
+        balance - rent.minimum_balance(LoyaltyState::SPACE) - (account_rent - rent.minimum_balance(data.len()))
+    );
+    let migrated = get_user_account(&mut context, &user_account).await;
+    assert_eq!(migrated.version, USER_ACCOUNT_VERSION);
+    assert!(!migrated.early_adopter_rewarded);
+    assert_eq!(migrated.reserved, [0; ACCOUNT_RESERVED_LEN - 1]);
+    assert_eq!(migrated.user, user.pubkey());
+    assert_eq!(migrated.loyalty_tier, 2);
+    assert_eq!(migrated.tokens_held, 500);
+    assert_eq!(migrated.badges, 0);
+    assert_eq!(migrated.votes_cast, 0);
+    assert_eq!(migrated.name, "Old Timer");
+    assert_eq!(migrated.bio, "Here since v1");
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.user, user.pubkey());
+    assert_eq!(loyalty_state.user_account, user_account);
+    assert_eq!(loyalty_state.loyalty_points, 1_234);
+    assert_eq!(loyalty_state.last_activity, 42);
+    let lots: Vec<_> = loyalty_state.lots().iter().map(|lot| (lot.period, lot.points, lot.expires_at)).collect();
+    assert_eq!(lots, expected_lots);
 
-    // Initialize user account
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    // Migrating twice is rejected, and the account works as normal again
+    let err = process(&mut context, &[migrate], &[&payer]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::AccountAlreadyMigrated));
+    let update = update_profile(&user.pubkey(), &user_account, "Old Timer", "", None, vec![]);
+    process(&mut context, &[update], &[&user]).await.unwrap();
+    assert_eq!(get_user_account(&mut context, &user_account).await.bio, "");
+}
+
+#[tokio::test]
+async fn test_username_registry() {
+    let mut context = setup_test().await;
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let alice_account = initialize_user(&mut context, &alice.pubkey()).await;
+    let bob_account = initialize_user(&mut context, &bob.pubkey()).await;
+    let fund_wallets = [fund(&context, &alice.pubkey()), fund(&context, &bob.pubkey())];
+    process(&mut context, &fund_wallets, &[]).await.unwrap();
+    let handle = username_address("alice_01");
+
+    // Names longer than a seed can hold are rejected like any other
+    let too_long_for_a_seed = "n".repeat(40);
+    for name in ["ab", "has space", "way_too_long_for_a_username", &too_long_for_a_seed] {
+        let err = process(&mut context, &[claim_username(&alice.pubkey(), &alice_account, name)], &[&alice])
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidUsername));
+    }
+
+    // Names are stored lowercase and can't be claimed twice in any case
+    process(&mut context, &[claim_username(&alice.pubkey(), &alice_account, "Alice_01")], &[&alice])
+        .await
+        .unwrap();
+    let username: Username = get_account(&mut context, &handle).await;
+    assert_eq!(username.name, "alice_01");
+    assert_eq!(username.owner, alice.pubkey());
+    assert_eq!(username.user_account, alice_account);
+    assert_eq!(get_account::<UserAccount>(&mut context, &alice_account).await.username, handle);
+    assert!(process(&mut context, &[claim_username(&bob.pubkey(), &bob_account, "ALICE_01")], &[&bob])
+        .await
+        .is_err());
+
+    // One name per account
+    let err = process(&mut context, &[claim_username(&alice.pubkey(), &alice_account, "alice_02")], &[&alice])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::UsernameAlreadySet));
+
+    // Transfers need both owners
+    let transfer = build(
+        accounts::TransferUsername {
+            user_account: alice_account,
+            username: handle,
+            owner: alice.pubkey(),
+            new_user_account: bob_account,
+            new_owner: bob.pubkey(),
+        },
+        instruction::TransferUsername {},
+    );
+    process(&mut context, &[transfer], &[&alice, &bob]).await.unwrap();
+    let username: Username = get_account(&mut context, &handle).await;
+    assert_eq!(username.owner, bob.pubkey());
+    assert_eq!(username.user_account, bob_account);
+    assert_eq!(get_account::<UserAccount>(&mut context, &alice_account).await.username, Pubkey::default());
+    assert_eq!(get_account::<UserAccount>(&mut context, &bob_account).await.username, handle);
+
+    // Accounts holding a name can't be closed until it's released
+    let close = build(
+        accounts::CloseUserAccount {
+            user_account: bob_account,
+            loyalty_state: loyalty_address(&bob_account),
+            owner: bob.pubkey(),
+        },
+        instruction::CloseUserAccount {},
     );
+    let err = process(&mut context, std::slice::from_ref(&close), &[&bob]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::UserAccountInUse));
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    let release = |owner: &Pubkey, user_account: &Pubkey| {
+        build(
+            accounts::ReleaseUsername {
+                user_account: *user_account,
+                username: handle,
+                owner: *owner,
+            },
+            instruction::ReleaseUsername {},
+        )
+    };
+    let err = process(&mut context, &[release(&alice.pubkey(), &bob_account)], &[&alice]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+    process(&mut context, &[release(&bob.pubkey(), &bob_account)], &[&bob]).await.unwrap();
+    assert!(!account_exists(&mut context, &handle).await);
+    assert_eq!(get_account::<UserAccount>(&mut context, &bob_account).await.username, Pubkey::default());
 
-    // Create associated token account for the user
-    let user_token_account = token::create_associated_token_account(
-        &context.payer.pubkey(),
-        &user_keypair.pubkey(),
-        &mint_keypair.pubkey(),
+    // A released name is free to claim again
+    process(&mut context, &[claim_username(&alice.pubkey(), &alice_account, "alice_01"), close], &[&alice, &bob])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_referral_badge_unlock_and_claim() {
+    let mut context = setup_test().await;
+    let referrer = Keypair::new();
+    let referred = Keypair::new();
+    let badge_id: u8 = 3;
+
+    // Register a badge for the first referral
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let register = build(
+        accounts::RegisterBadge {
+            config: config_address(),
+            badge: badge_address(badge_id),
+            badge_mint: badge_mint_address(badge_id),
+            admin,
+            rent: sysvar::rent::ID,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::RegisterBadge {
+            badge_id,
+            kind: BadgeKind::Referrals,
+            threshold: 1,
+        },
+    );
+    process(&mut context, &[register], &[]).await.unwrap();
+    let referrer_account = initialize_user(&mut context, &referrer.pubkey()).await;
+    let recipient = create_token_account(&mut context, &referrer.pubkey(), &badge_mint_address(badge_id)).await;
+    let claim = build(
+        accounts::ClaimBadge {
+            config: config_address(),
+            user_account: referrer_account,
+            badge_mint: badge_mint_address(badge_id),
+            recipient,
+            owner: referrer.pubkey(),
+            token_program: spl_token::ID,
+        },
+        instruction::ClaimBadge { badge_id },
     );
 
-    // Mint tokens to user
-    let mint_amount = 1000;
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            token::instruction::mint_to(
-                &token::id(),
-                &mint_keypair.pubkey(),
-                &user_token_account,
-                &context.payer.pubkey(),
-                &[],
-                mint_amount,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    // Nothing to claim before the milestone
+    let err = process(&mut context, std::slice::from_ref(&claim), &[&referrer]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::BadgeNotEarned));
+
+    // Refer a user, passing the badge definition so the unlock check runs.
+    // The referred user pays for the referral record.
+    let fund_referred = fund(&context, &referred.pubkey());
+    let instruction = refer_user(&admin, &referrer.pubkey(), &referrer_account, &referred.pubkey(), &[badge_id]);
+    process(&mut context, &[fund_referred, instruction], &[&referred]).await.unwrap();
+
+    // Each referral pays once, and only the admin can record one
+    let instruction = refer_user(&admin, &referrer.pubkey(), &referrer_account, &referred.pubkey(), &[]);
+    assert!(process(&mut context, &[instruction], &[&referred]).await.is_err());
+    let other = Keypair::new();
+    let fund_other = fund(&context, &other.pubkey());
+    let instruction = client::refer_user(&other.pubkey(), &referrer.pubkey(), &referrer_account, &other.pubkey());
+    let err = process(&mut context, &[fund_other, instruction], &[&other]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    let user_info = get_user_account(&mut context, &referrer_account).await;
+    assert_eq!(user_info.referrals, 1);
+    assert_eq!(user_info.badges, 1 << badge_id);
+    assert_eq!(user_info.badges_minted, 0);
+
+    // The badge is minted once, into an account frozen so it can't be moved
+    process(&mut context, std::slice::from_ref(&claim), &[&referrer]).await.unwrap();
+    let badge = get_token_account(&mut context, &recipient).await;
+    assert_eq!(badge.amount, 1);
+    assert!(badge.is_frozen());
+    let user_info = get_user_account(&mut context, &referrer_account).await;
+    assert_eq!(user_info.badges_minted, 1 << badge_id);
+
+    let err = process(&mut context, &[claim], &[&referrer]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::BadgeAlreadyMinted));
+}
+
+#[tokio::test]
+async fn test_season_points_and_leaderboard() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let season_id: u32 = 1;
+    let season = season_address(season_id);
+
+    initialize_config(&mut context).await;
+    let start = now(&mut context).await;
+    let end = start + 86400 * 30;
+    let mut rank_shares_bps = [0u16; LEADERBOARD_SIZE];
+    rank_shares_bps[0] = 5_000;
+    let admin = context.payer.pubkey();
+    let create_season = build(
+        accounts::CreateSeason {
+            config: config_address(),
+            season,
+            admin,
+            system_program: system_program::ID,
+        },
+        instruction::CreateSeason {
+            season_id,
+            start_time: start - 1,
+            end_time: end,
+            reward_pool: 1_000_000,
+            rank_shares_bps,
+        },
+    );
+    process(&mut context, &[create_season], &[]).await.unwrap();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let join_season = build(
+        accounts::JoinSeason {
+            season,
+            user_account,
+            season_stats: season_stats_address(&season, &user.pubkey()),
+            payer: admin,
+            system_program: system_program::ID,
+        },
+        instruction::JoinSeason {},
     );
+    process(&mut context, &[join_season], &[]).await.unwrap();
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    let points = 75;
+    process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), Some(&season), points)], &[])
+        .await
+        .unwrap();
 
-    // Burn tokens from user
-    let burn_amount = 500;
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            early_adopter_airdrop::instruction::burn_tokens(
-                &program_id,
-                &mint_keypair.pubkey(),
-                &user_token_account,
-                &context.payer.pubkey(),
-                burn_amount,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    let stats_info: SeasonStats = get_account(&mut context, &season_stats_address(&season, &user.pubkey())).await;
+    assert_eq!(stats_info.points, points);
+    let season_info: Season = get_account(&mut context, &season).await;
+    assert_eq!(season_info.leaderboard.len(), 1);
+    assert_eq!(season_info.leaderboard[0].user, user.pubkey());
+    assert_eq!(season_info.leaderboard[0].points, points);
+
+    // Lifetime points count the seasonal ones too
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, points);
+
+    // Rewards are paid once the season is over, by leaderboard rank
+    let mint = create_mint(&mut context).await;
+    let recipient = create_token_account(&mut context, &user.pubkey(), &mint).await;
+    let distribute = build(
+        accounts::DistributeSeasonReward {
+            config: config_address(),
+            season,
+            season_stats: season_stats_address(&season, &user.pubkey()),
+            mint,
+            recipient,
+            authority: admin,
+            token_program: spl_token::ID,
+        },
+        instruction::DistributeSeasonReward {},
     );
+    let err = process(&mut context, std::slice::from_ref(&distribute), &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::SeasonNotEnded));
+
+    warp_forward(&mut context, end - start).await;
+    let err = process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), Some(&season), points)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::SeasonNotActive));
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    process(&mut context, std::slice::from_ref(&distribute), &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &recipient).await.amount, 500_000);
+    let err = process(&mut context, &[distribute], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::SeasonRewardClaimed));
+}
 
-    // Fetch the user's token account
-    let user_token_account_info = context
-        .banks_client
-        .get_account(user_token_account)
+#[tokio::test]
+async fn test_expire_points() {
+    let mut context = setup_test().await;
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let user = Keypair::new();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), None, 50)], &[])
         .await
-        .expect("account not found")
-        .expect("account empty");
+        .unwrap();
+
+    // Points earned in a later period go into a lot of their own
+    warp_forward(&mut context, POINT_LOT_DURATION).await;
+    process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), None, 30)], &[])
+        .await
+        .unwrap();
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, 80);
+    assert_eq!(loyalty_state.lots().len(), 2);
+    let expires_at = loyalty_state.lots()[0].expires_at;
+
+    // Move the clock to the first lot's expiry and run the crank
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = expires_at;
+    context.set_sysvar(&clock);
+    let instruction = build(
+        accounts::ExpirePoints {
+            loyalty_state: loyalty_address(&user_account),
+        },
+        instruction::ExpirePoints {},
+    );
+    process(&mut context, &[instruction], &[]).await.unwrap();
 
-    let user_token_info = TokenAccount::unpack(&user_token_account_info.data).unwrap();
-    assert_eq!(user_token_info.amount, mint_amount - burn_amount);
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, 30);
+    assert_eq!(loyalty_state.lots().len(), 1);
+    assert_eq!(loyalty_state.lots()[0].points, 30);
 }
 
 #[tokio::test]
-async fn test_apply_inactivity_penalty() {
-    let (mut context, _, program_id, user_keypair, user_account, _, _) = setup_test().await;
+async fn test_redeem_voucher_item() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let operator = Keypair::new();
+    let item_id: u32 = 0;
 
-    // Initialize user account
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let list_voucher = build(
+        accounts::ListItem {
+            config: config_address(),
+            catalog: catalog_address(),
+            item: catalog_item_address(item_id),
+            vault: None,
+            admin,
+            system_program: system_program::ID,
+        },
+        instruction::ListItem {
+            kind: RewardKind::Voucher,
+            point_cost: 40,
+            amount: 0,
+            stock_limit: 10,
+            per_user_limit: 1,
+            available_from: 0,
+            available_until: 0,
+        },
     );
+    process(&mut context, &[initialize_catalog(&admin, &operator.pubkey()), list_voucher], &[])
+        .await
+        .unwrap();
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // Fund the user wallet so it can pay for the receipt
+    let fund_user = fund(&context, &user.pubkey());
+    process(&mut context, &[fund_user, track_loyalty(&admin, &user_account, &user.pubkey(), None, 100)], &[])
+        .await
+        .unwrap();
 
-    // Track loyalty points
-    let points = 50;
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            early_adopter_airdrop::instruction::track_loyalty(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                points,
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    process(&mut context, &[redeem_item(&user.pubkey(), &user_account, item_id, 0, None, None)], &[&user])
+        .await
+        .unwrap();
+
+    assert_eq!(get_loyalty_state(&mut context, &user_account).await.loyalty_points, 60);
+    let user_info = get_user_account(&mut context, &user_account).await;
+    assert_eq!(user_info.redemptions, 1);
+
+    let item = catalog_item_address(item_id);
+    let redemption = redemption_address(&item, &user.pubkey(), 0);
+    let redemption_info: Redemption = get_account(&mut context, &redemption).await;
+    assert_eq!(redemption_info.points_spent, 40);
+    assert!(!redemption_info.fulfilled);
+
+    // The per-user limit is one
+    let err = process(&mut context, &[redeem_item(&user.pubkey(), &user_account, item_id, 1, None, None)], &[&user])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ItemUserLimitReached));
+
+    // The operator closes the receipt once the voucher is delivered
+    let close = build(
+        accounts::CloseRedemption {
+            catalog: catalog_address(),
+            redemption,
+            user: user.pubkey(),
+            operator: operator.pubkey(),
+        },
+        instruction::CloseRedemption {},
     );
+    process(&mut context, &[close], &[&operator]).await.unwrap();
+    assert!(!account_exists(&mut context, &redemption).await);
+}
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+#[tokio::test]
+async fn test_redeem_token_item_from_vault() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let item_id: u32 = 0;
 
-    // Apply inactivity penalty
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            early_adopter_airdrop::instruction::apply_inactivity_penalty(
-                &program_id,
-                &user_account.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let mint = create_mint(&mut context).await;
+    let vault = create_token_account(&mut context, &catalog_address(), &mint).await;
+    process(&mut context, &[initialize_catalog(&admin, &admin)], &[]).await.unwrap();
+
+    // A vault the catalog doesn't own is refused
+    let other_vault = create_token_account(&mut context, &admin, &mint).await;
+    let err = process(&mut context, &[list_item(&admin, item_id, Some(&other_vault), RewardKind::VaultToken, 25, 500)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidItemVault));
+
+    process(&mut context, &[list_item(&admin, item_id, Some(&vault), RewardKind::VaultToken, 25, 500)], &[])
+        .await
+        .unwrap();
+    mint_to(&mut context, &mint, &vault, 1_000).await;
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let recipient = create_token_account(&mut context, &user.pubkey(), &mint).await;
+    let fund_user = fund(&context, &user.pubkey());
+    process(&mut context, &[fund_user, track_loyalty(&admin, &user_account, &user.pubkey(), None, 30)], &[])
+        .await
+        .unwrap();
+
+    process(
+        &mut context,
+        &[redeem_item(&user.pubkey(), &user_account, item_id, 0, Some(&vault), Some(&recipient))],
+        &[&user],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(get_token_account(&mut context, &recipient).await.amount, 500);
+    assert_eq!(get_token_account(&mut context, &vault).await.amount, 500);
+    let loyalty_state = get_loyalty_state(&mut context, &user_account).await;
+    assert_eq!(loyalty_state.loyalty_points, 5);
+    let redemption_info: Redemption =
+        get_account(&mut context, &redemption_address(&catalog_item_address(item_id), &user.pubkey(), 0)).await;
+    assert!(redemption_info.fulfilled);
+
+    // Too few points left for another
+    let err = process(
+        &mut context,
+        &[redeem_item(&user.pubkey(), &user_account, item_id, 1, Some(&vault), Some(&recipient))],
+        &[&user],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InsufficientFunds));
+}
+
+#[tokio::test]
+async fn test_pause_blocks_redemption() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+    let guardian = Keypair::new();
+
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let set_guardian = build(admin_only(&admin), instruction::SetGuardian { guardian: guardian.pubkey() });
+    process(&mut context, &[set_guardian], &[]).await.unwrap();
+    let mint = create_mint(&mut context).await;
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let user_token_account = create_token_account(&mut context, &user.pubkey(), &mint).await;
+    process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), None, 10)], &[])
+        .await
+        .unwrap();
+
+    // Nobody else can pause
+    let err = process(&mut context, &[set_paused(&user.pubkey(), true)], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+
+    // The guardian halts minting
+    process(&mut context, &[set_paused(&guardian.pubkey(), true)], &[&guardian]).await.unwrap();
+    let redeem = redeem_loyalty(&user_account, &mint, &user_token_account, &admin, 10);
+    let err = process(&mut context, std::slice::from_ref(&redeem), &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProgramPaused));
+
+    // and the admin can resume it
+    process(&mut context, &[set_paused(&admin, false)], &[]).await.unwrap();
+    process(&mut context, &[redeem], &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &user_token_account).await.amount, 1_000);
+}
+
+#[tokio::test]
+async fn test_redemption_rate_limits() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
+
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    let set_limits = build(
+        admin_only(&admin),
+        instruction::SetRedemptionLimits {
+            redeem_window: 86400,
+            global_redeem_limit: 0,
+            user_redeem_limit: 1_000,
+        },
     );
+    process(&mut context, &[set_limits], &[]).await.unwrap();
+    let mint = create_mint(&mut context).await;
+    let user_account = initialize_user(&mut context, &user.pubkey()).await;
+    let user_token_account = create_token_account(&mut context, &user.pubkey(), &mint).await;
+    process(&mut context, &[track_loyalty(&admin, &user_account, &user.pubkey(), None, 20)], &[])
+        .await
+        .unwrap();
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // 500 tokens fit in the window, another 600 don't
+    process(&mut context, &[redeem_loyalty(&user_account, &mint, &user_token_account, &admin, 5)], &[])
+        .await
+        .unwrap();
+    let err = process(&mut context, &[redeem_loyalty(&user_account, &mint, &user_token_account, &admin, 6)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::RedemptionRateLimited));
 
-    // Fetch the user account to verify loyalty points after penalty
-    let user_account_data = context
-        .banks_client
-        .get_account(user_account.pubkey())
+    // A new window starts once the day is over
+    warp_forward(&mut context, 86400).await;
+    process(&mut context, &[redeem_loyalty(&user_account, &mint, &user_token_account, &admin, 6)], &[])
         .await
-        .expect("account not found")
-        .expect("account empty");
+        .unwrap();
+    assert_eq!(get_token_account(&mut context, &user_token_account).await.amount, 1_100);
 
-    let user_info: UserAccount = UserAccount::try_from_slice(&user_account_data.data).unwrap();
-    // Assuming the penalty reduced points by 10
-    assert_eq!(user_info.loyalty_points, points - 10);
+    // A token amount that doesn't fit in a u64 fails instead of wrapping
+    process(&mut context, &[client::set_reward_params(&admin, u64::MAX, 100)], &[]).await.unwrap();
+    let instruction = redeem_loyalty(&user_account, &mint, &user_token_account, &admin, 2);
+    let err = process(&mut context, &[instruction], &[]).await.unwrap_err();
+    assert!(matches!(
+        err.unwrap(),
+        TransactionError::InstructionError(_, InstructionError::ArithmeticOverflow)
+    ));
 }
 
 #[tokio::test]
-async fn test_update_profile() {
-    let (mut context, _, program_id, user_keypair, user_account, _, _) = setup_test().await;
+async fn test_set_reward_params() {
+    let mut context = setup_test().await;
+    let user = Keypair::new();
 
-    // Initialize user account
-    let tx = Transaction::new_signed_with_payer(
-        &[
-            system_program::create_account(
-                &context.payer.pubkey(),
-                &user_account.pubkey(),
-                Rent::default().minimum_balance(8 + 32 + 8 + 8 + 4 + 100 + 200),
-                8 + 32 + 8 + 8 + 4 + 100 + 200,
-                &program_id,
-            ),
-            early_adopter_airdrop::instruction::initialize_user(
-                &program_id,
-                &user_account.pubkey(),
-                &user_keypair.pubkey(),
-                &context.payer.pubkey(),
-            )
-            .unwrap(),
-        ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer, &user_account],
-        context.last_blockhash,
+    initialize_config(&mut context).await;
+    let admin = context.payer.pubkey();
+    process(&mut context, &[client::set_reward_params(&admin, 7, 250)], &[]).await.unwrap();
+    let config: Config = get_account(&mut context, &config_address()).await;
+    assert_eq!(config.redemption_rate, 7);
+    assert_eq!(config.referral_reward, 250);
+
+    // Only the admin sets them directly
+    let fund_user = fund(&context, &user.pubkey());
+    let instruction = client::set_reward_params(&user.pubkey(), 1, 1);
+    let err = process(&mut context, &[fund_user, instruction], &[&user]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+}
+
+#[tokio::test]
+async fn test_execute_proposal_updates_config() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+    let referrer = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 50, 10).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 20).await;
+    let action = ProposalAction::UpdateRewardParams {
+        redemption_rate: 7,
+        referral_reward: 250,
+    };
+    let proposal = create_proposal(&mut context, &proposer_account, "Raise the referral reward", Some(action), vec![]).await;
+
+    let fund_voter = fund(&context, &voter.pubkey());
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[fund_voter, instruction], &[&voter]).await.unwrap();
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&proposal, &voter.pubkey())).await;
+    assert_eq!(record.weight, 20);
+    assert!(record.in_favor);
+
+    // The vote record already exists, so a second vote fails, even from
+    // another user account registered to the same wallet
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    assert!(process(&mut context, &[instruction], &[&voter]).await.is_err());
+    let second_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let instruction = vote(&proposal, &voter.pubkey(), &second_account, Some(&voter_tokens), true);
+    let err = process(&mut context, &[instruction], &[&voter]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::AlreadyVoted));
+
+    let err = process(&mut context, &[finalize_proposal(&proposal, &proposer_account)], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::VotingStillOpen));
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize_proposal(&proposal, &proposer_account)], &[]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert!(proposal_info.status == ProposalStatus::Succeeded);
+
+    // The change waits out the timelock
+    let err = process(&mut context, &[execute_proposal(&proposal, vec![])], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::TimelockNotElapsed));
+    warp_forward(&mut context, 50).await;
+    process(&mut context, &[execute_proposal(&proposal, vec![])], &[]).await.unwrap();
+
+    let config: Config = get_account(&mut context, &config_address()).await;
+    assert_eq!(config.redemption_rate, 7);
+    assert_eq!(config.referral_reward, 250);
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert!(proposal_info.status == ProposalStatus::Executed);
+
+    // Referrals now pay the new reward
+    let referrer_account = initialize_user(&mut context, &referrer.pubkey()).await;
+    let instruction = refer_user(&payer, &referrer.pubkey(), &referrer_account, &voter.pubkey(), &[]);
+    process(&mut context, &[instruction], &[&voter])
+        .await
+        .unwrap();
+    assert_eq!(get_loyalty_state(&mut context, &referrer_account).await.loyalty_points, 250);
+}
+
+#[tokio::test]
+async fn test_proposal_below_quorum_is_defeated() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 50).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 20).await;
+    let action = ProposalAction::SetEmissionBudget { emission_budget: 1 };
+    let proposal = create_proposal(&mut context, &proposer_account, "Cap emissions", Some(action), vec![]).await;
+
+    let fund_voter = fund(&context, &voter.pubkey());
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[fund_voter, instruction], &[&voter]).await.unwrap();
+    warp_forward(&mut context, 100).await;
+
+    // Voting is over
+    let late_voter = Keypair::new();
+    let late_account = initialize_user(&mut context, &late_voter.pubkey()).await;
+    let fund_late_voter = fund(&context, &late_voter.pubkey());
+    let err = process(
+        &mut context,
+        &[fund_late_voter, vote(&proposal, &late_voter.pubkey(), &late_account, None, true)],
+        &[&late_voter],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::VotingClosed));
+
+    process(&mut context, &[finalize_proposal(&proposal, &proposer_account)], &[]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert!(proposal_info.status == ProposalStatus::Defeated);
+    let err = process(&mut context, &[execute_proposal(&proposal, vec![])], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposalNotSucceeded));
+}
+
+#[tokio::test]
+async fn test_treasury_proposal_transfers_tokens() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+    let recipient_wallet = Keypair::new();
+    let treasury = treasury_address();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let mint = create_mint(&mut context).await;
+    let treasury_tokens = create_token_account(&mut context, &treasury, &mint).await;
+    let recipient = create_token_account(&mut context, &recipient_wallet.pubkey(), &mint).await;
+    mint_to(&mut context, &mint, &treasury_tokens, 1_000).await;
+
+    // Instructions needing any signer but the treasury are refused
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let foreign_signer =
+        spl_token::instruction::transfer(&spl_token::ID, &treasury_tokens, &recipient, &payer, &[], 400).unwrap();
+    let rejected = Keypair::new();
+    let err = process(
+        &mut context,
+        &[create_proposal_instruction(&rejected.pubkey(), &payer, &proposer_account, "Pay", None, vec![foreign_signer.into()])],
+        &[&rejected],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidProposalInstruction));
+
+    let transfer =
+        spl_token::instruction::transfer(&spl_token::ID, &treasury_tokens, &recipient, &treasury, &[], 400).unwrap();
+    let proposal = create_proposal(&mut context, &proposer_account, "Fund the grant", None, vec![transfer.into()]).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 10).await;
+    let fund_voter = fund(&context, &voter.pubkey());
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[fund_voter, instruction], &[&voter]).await.unwrap();
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize_proposal(&proposal, &proposer_account)], &[]).await.unwrap();
+
+    let remaining_accounts = vec![
+        AccountMeta::new(treasury_tokens, false),
+        AccountMeta::new(recipient, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+    ];
+    process(&mut context, &[execute_proposal(&proposal, remaining_accounts.clone())], &[])
+        .await
+        .unwrap();
+
+    assert_eq!(get_token_account(&mut context, &recipient).await.amount, 400);
+    assert_eq!(get_token_account(&mut context, &treasury_tokens).await.amount, 600);
+
+    // Executed proposals don't run again
+    let err = process(&mut context, &[execute_proposal(&proposal, remaining_accounts)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposalNotSucceeded));
+}
+
+#[tokio::test]
+async fn test_proposal_thresholds_and_deposit() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+    let treasury = treasury_address();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 10).await;
+    let payer = context.payer.pubkey();
+    let mint = create_mint(&mut context).await;
+    let deposit_vault = deposit_vault_address(&mint);
+    let set_requirements = build(
+        accounts::SetProposalRequirements {
+            config: config_address(),
+            governance_mint: mint,
+            deposit_vault,
+            admin: payer,
+            rent: sysvar::rent::ID,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::SetProposalRequirements {
+            proposal_min_tokens: 100,
+            proposal_min_tier: 0,
+            proposal_deposit: 50,
+            max_active_proposals: 1,
+        },
     );
+    process(&mut context, &[set_requirements], &[]).await.unwrap();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let proposer_tokens = create_token_account(&mut context, &payer, &mint).await;
+    let treasury_tokens = create_token_account(&mut context, &treasury, &mint).await;
+    mint_to(&mut context, &mint, &proposer_tokens, 200).await;
+
+    let create = |proposal: &Pubkey, token_account: Option<Pubkey>| {
+        build(
+            accounts::CreateProposal {
+                config: config_address(),
+                proposal: *proposal,
+                treasury,
+                proposer_account,
+                proposer_token_account: token_account,
+                deposit_vault: Some(deposit_vault),
+                authority: payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::CreateProposal {
+                title: "Fund the grant".to_string(),
+                uri: String::new(),
+                content_hash: [0; 32],
+                action: None,
+                instructions: vec![],
+                vote_mode: VoteMode::SingleChoice,
+                option_labels: vec![],
+                voting_power: VotingPower::Tokens,
+            },
+        )
+    };
+    let finalize = |proposal: &Pubkey| {
+        build(
+            accounts::FinalizeProposal {
+                config: config_address(),
+                proposal: *proposal,
+                creator_account: proposer_account,
+                deposit_vault: Some(deposit_vault),
+                creator_token_account: Some(proposer_tokens),
+                treasury_token_account: Some(treasury_tokens),
+                treasury,
+                token_program: spl_token::ID,
+            },
+            instruction::FinalizeProposal {},
+        )
+    };
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    // Without a token balance and at the lowest tier, nobody can propose
+    let first = Keypair::new();
+    let err = process(&mut context, &[create(&first.pubkey(), None)], &[&first]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposerBelowThreshold));
 
-    // Update user profile
-    let name = "Users Name".to_string();
-    let bio = "Users Bio.".to_string();
-    let tx = Transaction::new_signed_with_payer(
+    process(&mut context, &[create(&first.pubkey(), Some(proposer_tokens))], &[&first])
+        .await
+        .unwrap();
+    assert_eq!(get_token_account(&mut context, &deposit_vault).await.amount, 50);
+    assert_eq!(get_user_account(&mut context, &proposer_account).await.active_proposals, 1);
+
+    // One active proposal at a time
+    let second = Keypair::new();
+    let err = process(&mut context, &[create(&second.pubkey(), Some(proposer_tokens))], &[&second])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ActiveProposalLimitReached));
+
+    // Nobody votes, so the deposit goes to the treasury
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize(&first.pubkey())], &[]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &treasury_tokens).await.amount, 50);
+    assert_eq!(get_user_account(&mut context, &proposer_account).await.active_proposals, 0);
+
+    // A proposal that reaches quorum gets its deposit back, even if defeated
+    process(&mut context, &[create(&second.pubkey(), Some(proposer_tokens))], &[&second])
+        .await
+        .unwrap();
+    assert_eq!(get_token_account(&mut context, &proposer_tokens).await.amount, 100);
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 10).await;
+    let fund_voter = fund(&context, &voter.pubkey());
+    let instruction = vote(&second.pubkey(), &voter.pubkey(), &voter_account, Some(&voter_tokens), false);
+    process(&mut context, &[fund_voter, instruction], &[&voter]).await.unwrap();
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize(&second.pubkey())], &[]).await.unwrap();
+
+    let proposal_info: Proposal = get_account(&mut context, &second.pubkey()).await;
+    assert!(proposal_info.status == ProposalStatus::Defeated);
+    assert_eq!(get_token_account(&mut context, &proposer_tokens).await.amount, 150);
+    assert_eq!(get_token_account(&mut context, &deposit_vault).await.amount, 0);
+}
+
+#[tokio::test]
+async fn test_weighted_split_poll_picks_winner() {
+    let mut context = setup_test().await;
+    let first_voter = Keypair::new();
+    let second_voter = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let labels = ["Base", "Sui", "Aptos"];
+    let poll = create_poll(&mut context, &proposer_account, VoteMode::WeightedSplit, &labels, VotingPower::Tokens).await;
+    let first_account = initialize_user(&mut context, &first_voter.pubkey()).await;
+    let second_account = initialize_user(&mut context, &second_voter.pubkey()).await;
+    let first_tokens = give_governance_tokens(&mut context, &first_voter, 30).await;
+    let second_tokens = give_governance_tokens(&mut context, &second_voter, 20).await;
+    let fund_voters = [fund(&context, &first_voter.pubkey()), fund(&context, &second_voter.pubkey())];
+    process(&mut context, &fund_voters, &[]).await.unwrap();
+
+    // Polls take a split over the options, not a for/against vote
+    let instruction = vote(&poll, &first_voter.pubkey(), &first_account, Some(&first_tokens), true);
+    let err = process(&mut context, &[instruction], &[&first_voter]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidBallot));
+    let err = process(
+        &mut context,
+        &[vote_options(&poll, &first_voter.pubkey(), &first_account, Some(&first_tokens), vec![5_000, 4_000, 0])],
+        &[&first_voter],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidBallot));
+
+    process(
+        &mut context,
         &[
-            early_adopter_airdrop::instruction::update_profile(
-                &program_id,
-                &user_account.pubkey(),
-                name.clone(),
-                bio.clone(),
-            )
-            .unwrap(),
+            vote_options(&poll, &first_voter.pubkey(), &first_account, Some(&first_tokens), vec![5_000, 5_000, 0]),
+            vote_options(&poll, &second_voter.pubkey(), &second_account, Some(&second_tokens), vec![0, 10_000, 0]),
         ],
-        Some(&context.payer.pubkey()),
-        &[&context.payer],
-        context.last_blockhash,
+        &[&first_voter, &second_voter],
+    )
+    .await
+    .unwrap();
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&poll, &first_voter.pubkey())).await;
+    assert_eq!(record.choices, vec![5_000, 5_000, 0]);
+
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize_proposal(&poll, &proposer_account)], &[]).await.unwrap();
+
+    let poll_info: Proposal = get_account(&mut context, &poll).await;
+    let tallies: Vec<u64> = poll_info.options.iter().map(|option| option.votes).collect();
+    assert_eq!(tallies, vec![15, 35, 0]);
+    assert_eq!(poll_info.turnout, 50);
+    assert_eq!(poll_info.winning_option, Some(1));
+    assert!(poll_info.status == ProposalStatus::Succeeded);
+}
+
+#[tokio::test]
+async fn test_delegated_votes_count_once() {
+    let mut context = setup_test().await;
+    let delegator = Keypair::new();
+    let first_delegate = Keypair::new();
+    let second_delegate = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let delegator_account = initialize_user(&mut context, &delegator.pubkey()).await;
+    let first_account = initialize_user(&mut context, &first_delegate.pubkey()).await;
+    let second_account = initialize_user(&mut context, &second_delegate.pubkey()).await;
+    let delegator_tokens = give_governance_tokens(&mut context, &delegator, 30).await;
+    let first_tokens = give_governance_tokens(&mut context, &first_delegate, 10).await;
+    let second_tokens = give_governance_tokens(&mut context, &second_delegate, 5).await;
+    let governance_mint = get_account::<Config>(&mut context, &config_address()).await.governance_mint;
+    let fund_wallets = [
+        fund(&context, &delegator.pubkey()),
+        fund(&context, &first_delegate.pubkey()),
+        fund(&context, &second_delegate.pubkey()),
+    ];
+    process(&mut context, &fund_wallets, &[]).await.unwrap();
+
+    let err = process(
+        &mut context,
+        &[delegate_votes(&delegator.pubkey(), &delegator_account, &delegator.pubkey())],
+        &[&delegator],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidDelegate));
+    process(
+        &mut context,
+        &[delegate_votes(&delegator.pubkey(), &delegator_account, &first_delegate.pubkey())],
+        &[&delegator],
+    )
+    .await
+    .unwrap();
+    let proposal = create_proposal(&mut context, &proposer_account, "Cap emissions", None, vec![]).await;
+
+    // The delegate votes with the delegator's weight as well as their own
+    let first_vote = vote(&proposal, &first_delegate.pubkey(), &first_account, Some(&first_tokens), true);
+    let delegators = [(delegator.pubkey(), delegator_account)];
+    let instruction = with_delegators(first_vote, &proposal, &governance_mint, &delegators);
+    process(&mut context, &[instruction], &[&first_delegate]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.votes_for, 40);
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&proposal, &delegator.pubkey())).await;
+    assert_eq!(record.delegate, Some(first_delegate.pubkey()));
+
+    // Voting directly overrides the delegate
+    let instruction = vote(&proposal, &delegator.pubkey(), &delegator_account, Some(&delegator_tokens), false);
+    process(&mut context, &[instruction], &[&delegator]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!((proposal_info.votes_for, proposal_info.votes_against), (10, 30));
+    let instruction = vote(&proposal, &delegator.pubkey(), &delegator_account, Some(&delegator_tokens), true);
+    let err = process(&mut context, &[instruction], &[&delegator]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::AlreadyVoted));
+
+    // Moving the delegation mid-proposal doesn't count the weight again
+    let undelegate = build(
+        accounts::Undelegate {
+            delegation: delegation_address(&delegator_account),
+            delegator: delegator.pubkey(),
+        },
+        instruction::Undelegate {},
     );
+    process(
+        &mut context,
+        &[undelegate, delegate_votes(&delegator.pubkey(), &delegator_account, &second_delegate.pubkey())],
+        &[&delegator],
+    )
+    .await
+    .unwrap();
+    let second_vote = vote(&proposal, &second_delegate.pubkey(), &second_account, Some(&second_tokens), true);
+    let instruction = with_delegators(second_vote, &proposal, &governance_mint, &delegators);
+    process(&mut context, &[instruction], &[&second_delegate]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!((proposal_info.votes_for, proposal_info.votes_against), (15, 30));
+    assert_eq!(proposal_info.turnout, 45);
+}
+
+#[tokio::test]
+async fn test_delegated_tokens_count_once() {
+    let mut context = setup_test().await;
+    let delegator = Keypair::new();
+    let fresh = Keypair::new();
+    let first_delegate = Keypair::new();
+    let second_delegate = Keypair::new();
 
-    context.banks_client.process_transaction(tx).await.unwrap();
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let delegator_account = initialize_user(&mut context, &delegator.pubkey()).await;
+    let fresh_account = initialize_user(&mut context, &fresh.pubkey()).await;
+    let first_account = initialize_user(&mut context, &first_delegate.pubkey()).await;
+    let second_account = initialize_user(&mut context, &second_delegate.pubkey()).await;
+    give_governance_tokens(&mut context, &delegator, 30).await;
+    let first_tokens = give_governance_tokens(&mut context, &first_delegate, 10).await;
+    let second_tokens = give_governance_tokens(&mut context, &second_delegate, 5).await;
+    let mint = get_account::<Config>(&mut context, &config_address()).await.governance_mint;
+    let delegator_tokens = get_associated_token_address(&delegator.pubkey(), &mint);
+    let fresh_tokens = create_token_account(&mut context, &fresh.pubkey(), &mint).await;
+    let fund_fresh = fund(&context, &fresh.pubkey());
+    process(&mut context, &[fund_fresh], &[]).await.unwrap();
+    process(
+        &mut context,
+        &[delegate_votes(&delegator.pubkey(), &delegator_account, &first_delegate.pubkey())],
+        &[&delegator],
+    )
+    .await
+    .unwrap();
+    let proposal = create_proposal(&mut context, &proposer_account, "Cap emissions", None, vec![]).await;
 
-    // Fetch the user account to verify profile update
-    let user_account_data = context
-        .banks_client
-        .get_account(user_account.pubkey())
+    let first_vote = vote(&proposal, &first_delegate.pubkey(), &first_account, Some(&first_tokens), true);
+    let instruction = with_delegators(first_vote, &proposal, &mint, &[(delegator.pubkey(), delegator_account)]);
+    process(&mut context, &[instruction], &[&first_delegate]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.votes_for, 40);
+
+    // The delegate's vote keeps the delegator's tokens locked too
+    let token_lock: TokenLock = get_account(&mut context, &token_lock_address(&delegator.pubkey(), &mint)).await;
+    assert_eq!(token_lock.locked_until, proposal_info.voting_ends_at);
+    let unlock = client::unlock_tokens(&delegator.pubkey(), &delegator_tokens, &mint, 30);
+    let err = process(&mut context, &[unlock], &[&delegator]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::TokensLocked));
+
+    // Tokens the delegator moves to a fresh wallet that delegates elsewhere
+    // aren't locked, so they add nothing
+    mint_to(&mut context, &mint, &delegator_tokens, 30).await;
+    let transfer =
+        spl_token::instruction::transfer(&spl_token::ID, &delegator_tokens, &fresh_tokens, &delegator.pubkey(), &[], 30)
+            .unwrap();
+    let delegate = delegate_votes(&fresh.pubkey(), &fresh_account, &second_delegate.pubkey());
+    process(&mut context, &[transfer, delegate], &[&delegator, &fresh]).await.unwrap();
+    let second_vote = vote(&proposal, &second_delegate.pubkey(), &second_account, Some(&second_tokens), true);
+    let instruction = with_delegators(second_vote, &proposal, &mint, &[(fresh.pubkey(), fresh_account)]);
+    process(&mut context, &[instruction], &[&second_delegate]).await.unwrap();
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&proposal, &fresh.pubkey())).await;
+    assert_eq!(record.weight, 0);
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.votes_for, 45);
+    assert_eq!(proposal_info.turnout, 45);
+}
+
+#[tokio::test]
+async fn test_voting_power_modes() {
+    let mut context = setup_test().await;
+    let whale = Keypair::new();
+    let regular = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let whale_account = initialize_user(&mut context, &whale.pubkey()).await;
+    let regular_account = initialize_user(&mut context, &regular.pubkey()).await;
+    let whale_tokens = give_governance_tokens(&mut context, &whale, 10_000).await;
+    let regular_tokens = give_governance_tokens(&mut context, &regular, 100).await;
+    let earn_points = [
+        track_loyalty(&payer, &whale_account, &whale.pubkey(), None, 10),
+        track_loyalty(&payer, &regular_account, &regular.pubkey(), None, 500),
+        fund(&context, &whale.pubkey()),
+        fund(&context, &regular.pubkey()),
+    ];
+    process(&mut context, &earn_points, &[]).await.unwrap();
+
+    let labels = ["Yes", "No"];
+    let single = VoteMode::SingleChoice;
+    let quadratic = create_poll(&mut context, &proposer_account, single, &labels, VotingPower::Quadratic).await;
+    let loyalty = create_poll(&mut context, &proposer_account, single, &labels, VotingPower::LoyaltyPoints).await;
+    let blend = VotingPower::Blend {
+        token_bps: 1_000,
+        points_bps: 10_000,
+        tier_bps: 0,
+    };
+    let blended = create_poll(&mut context, &proposer_account, single, &labels, blend).await;
+    for poll in [quadratic, loyalty, blended] {
+        process(
+            &mut context,
+            &[
+                vote_options(&poll, &whale.pubkey(), &whale_account, Some(&whale_tokens), vec![1, 0]),
+                vote_options(&poll, &regular.pubkey(), &regular_account, Some(&regular_tokens), vec![0, 1]),
+            ],
+            &[&whale, &regular],
+        )
         .await
-        .expect("account not found")
-        .expect("account empty");
+        .unwrap();
+    }
 
-    let user_info: UserAccount = UserAccount::try_from_slice(&user_account_data.data).unwrap();
-    assert_eq!(user_info.name, name);
-    assert_eq!(user_info.bio, bio);
+    // sqrt(10000) against sqrt(100), then points, then a tenth of tokens plus points
+    for (poll, tallies) in [(quadratic, [100, 10]), (loyalty, [10, 500]), (blended, [1_010, 510])] {
+        let poll_info: Proposal = get_account(&mut context, &poll).await;
+        assert_eq!([poll_info.options[0].votes, poll_info.options[1].votes], tallies);
+    }
+}
+
+// Proposals that change the program are weighed the way the config says,
+// whatever the proposer asked for.
+#[tokio::test]
+async fn test_executable_proposals_use_config_voting_power() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 10).await;
+    let earn_points = [track_loyalty(&payer, &voter_account, &voter.pubkey(), None, 500), fund(&context, &voter.pubkey())];
+    process(&mut context, &earn_points, &[]).await.unwrap();
+
+    let propose = |proposal: &Pubkey, voting_power| {
+        build(
+            accounts::CreateProposal {
+                config: config_address(),
+                proposal: *proposal,
+                treasury: treasury_address(),
+                proposer_account,
+                proposer_token_account: None,
+                deposit_vault: None,
+                authority: payer,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::CreateProposal {
+                title: "Cap emissions".to_string(),
+                uri: String::new(),
+                content_hash: [0; 32],
+                action: Some(ProposalAction::SetEmissionBudget { emission_budget: 1 }),
+                instructions: vec![],
+                vote_mode: VoteMode::SingleChoice,
+                option_labels: vec![],
+                voting_power,
+            },
+        )
+    };
+    let proposal = Keypair::new();
+    process(&mut context, &[propose(&proposal.pubkey(), VotingPower::LoyaltyPoints)], &[&proposal])
+        .await
+        .unwrap();
+    let proposal = proposal.pubkey();
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[instruction], &[&voter]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert!(proposal_info.voting_power == VotingPower::Tokens);
+    assert_eq!(proposal_info.votes_for, 10);
+
+    // Votes only count tokens in the voter's own lock
+    let instruction = vote(&proposal, &payer, &proposer_account, Some(&voter_tokens), true);
+    let err = process(&mut context, &[instruction], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::ConstraintSeeds.into()));
+
+    // The admin can switch executable proposals to points
+    let instruction = client::set_governance_params(&payer, 100, 0, 0, VotingPower::LoyaltyPoints);
+    process(&mut context, &[instruction], &[]).await.unwrap();
+    let proposal = Keypair::new();
+    process(&mut context, &[propose(&proposal.pubkey(), VotingPower::Tokens)], &[&proposal])
+        .await
+        .unwrap();
+    let proposal = proposal.pubkey();
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[instruction], &[&voter]).await.unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.votes_for, 500);
+}
+
+#[tokio::test]
+async fn test_locked_tokens_count_once() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+    let other = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let other_account = initialize_user(&mut context, &other.pubkey()).await;
+    let voter_lock = give_governance_tokens(&mut context, &voter, 20).await;
+    let mint = get_account::<Config>(&mut context, &config_address()).await.governance_mint;
+    let voter_tokens = get_associated_token_address(&voter.pubkey(), &mint);
+    let other_tokens = create_token_account(&mut context, &other.pubkey(), &mint).await;
+    let proposal = create_proposal(&mut context, &proposer_account, "Rotate the admin", None, vec![]).await;
+
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_lock), true);
+    process(&mut context, &[instruction], &[&voter]).await.unwrap();
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&proposal, &voter.pubkey())).await;
+    assert_eq!(record.weight, 20);
+    let token_lock: TokenLock = get_account(&mut context, &voter_lock).await;
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(token_lock.locked_until, proposal_info.voting_ends_at);
+
+    // The counted tokens can't leave the lock while voting is open
+    let unlock = client::unlock_tokens(&voter.pubkey(), &voter_tokens, &mint, 20);
+    let err = process(&mut context, std::slice::from_ref(&unlock), &[&voter]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::TokensLocked));
+
+    // Tokens moved to another wallet between votes carry no weight unless
+    // they're locked, and these never were
+    mint_to(&mut context, &mint, &voter_tokens, 5).await;
+    let transfer = spl_token::instruction::transfer(&spl_token::ID, &voter_tokens, &other_tokens, &voter.pubkey(), &[], 5)
+        .unwrap();
+    process(&mut context, &[transfer], &[&voter]).await.unwrap();
+    let fund_other = fund(&context, &other.pubkey());
+    let instruction = vote(&proposal, &other.pubkey(), &other_account, None, true);
+    process(&mut context, &[fund_other, instruction], &[&other]).await.unwrap();
+    let record: VoteRecord = get_account(&mut context, &vote_record_address(&proposal, &other.pubkey())).await;
+    assert_eq!(record.weight, 0);
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.votes_for, 20);
+
+    // Once voting ends the tokens can be withdrawn
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[unlock], &[&voter]).await.unwrap();
+    assert_eq!(get_token_account(&mut context, &voter_tokens).await.amount, 20);
+    let token_lock: TokenLock = get_account(&mut context, &voter_lock).await;
+    assert_eq!(token_lock.amount, 0);
+}
+
+#[tokio::test]
+async fn test_proposal_metadata_sizing() {
+    let mut context = setup_test().await;
+    let voter = Keypair::new();
+
+    initialize_config(&mut context).await;
+    let payer = context.payer.pubkey();
+    let proposer_account = initialize_user(&mut context, &payer).await;
+
+    let rejected = Keypair::new();
+    let long_title = "x".repeat(MAX_TITLE_LEN + 1);
+    let err = process(
+        &mut context,
+        &[create_proposal_instruction(&rejected.pubkey(), &payer, &proposer_account, &long_title, None, vec![])],
+        &[&rejected],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::InvalidProposalTitle));
+
+    // The account holds exactly the serialized proposal, plus the byte the
+    // winning option takes once it is set
+    let proposal = create_proposal(&mut context, &proposer_account, "Cap emissions", None, vec![]).await;
+    let account = context.banks_client.get_account(proposal).await.unwrap().unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    let mut data = Vec::new();
+    proposal_info.try_serialize(&mut data).unwrap();
+    assert_eq!(account.data.len(), data.len() + 1);
+    assert_eq!(proposal_info.content_hash, [7; 32]);
+
+    // Longer metadata grows the account to fit
+    let update = |title: &str, uri: String| {
+        build(
+            accounts::UpdateProposalMetadata {
+                proposal,
+                creator: payer,
+                system_program: system_program::ID,
+            },
+            instruction::UpdateProposalMetadata {
+                title: title.to_string(),
+                uri,
+                content_hash: [9; 32],
+            },
+        )
+    };
+    let err = process(&mut context, &[update("Cap emissions", "x".repeat(MAX_URI_LEN + 1))], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposalUriTooLong));
+    let uri = "https://example.com/proposals/cap-emissions-at-ten-million".to_string();
+    process(&mut context, &[update("Cap yearly emissions", uri.clone())], &[]).await.unwrap();
+    let resized = context.banks_client.get_account(proposal).await.unwrap().unwrap();
+    let proposal_info: Proposal = get_account(&mut context, &proposal).await;
+    assert_eq!(proposal_info.uri, uri);
+    assert_eq!(proposal_info.content_hash, [9; 32]);
+    assert_eq!(
+        resized.data.len(),
+        account.data.len() + "Cap yearly emissions".len() - "Cap emissions".len() + uri.len()
+            - "https://example.com/proposals/1".len()
+    );
+
+    // Metadata is frozen once votes are in
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 10).await;
+    let fund_voter = fund(&context, &voter.pubkey());
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[fund_voter, instruction], &[&voter]).await.unwrap();
+    let err = process(&mut context, &[update("Cap emissions", String::new())], &[]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposalMetadataLocked));
+}
+
+#[tokio::test]
+async fn test_close_finished_accounts_returns_rent() {
+    let mut context = setup_test().await;
+    let payer = context.payer.pubkey();
+    let creator = Keypair::new();
+    let voter = Keypair::new();
+
+    initialize_config(&mut context).await;
+    set_governance_params(&mut context, 100, 0, 0).await;
+    let fund_wallets = [fund(&context, &creator.pubkey()), fund(&context, &voter.pubkey())];
+    process(&mut context, &fund_wallets, &[]).await.unwrap();
+    let creator_account = initialize_user(&mut context, &creator.pubkey()).await;
+    let voter_account = initialize_user(&mut context, &voter.pubkey()).await;
+    let voter_tokens = give_governance_tokens(&mut context, &voter, 10).await;
+
+    let proposal = Keypair::new();
+    let create = create_proposal_instruction(&proposal.pubkey(), &creator.pubkey(), &creator_account, "Poll", None, vec![]);
+    process(&mut context, &[create], &[&proposal, &creator]).await.unwrap();
+    let proposal = proposal.pubkey();
+    let instruction = vote(&proposal, &voter.pubkey(), &voter_account, Some(&voter_tokens), true);
+    process(&mut context, &[instruction], &[&voter]).await.unwrap();
+
+    let close_proposal = build(
+        accounts::CloseProposal {
+            proposal,
+            creator: creator.pubkey(),
+        },
+        instruction::CloseProposal {},
+    );
+    let vote_record = vote_record_address(&proposal, &voter.pubkey());
+    let close_vote_record = build(
+        accounts::CloseVoteRecord {
+            vote_record,
+            proposal,
+            payer: voter.pubkey(),
+        },
+        instruction::CloseVoteRecord {},
+    );
+    let close_user_account = build(
+        accounts::CloseUserAccount {
+            user_account: creator_account,
+            loyalty_state: loyalty_address(&creator_account),
+            owner: creator.pubkey(),
+        },
+        instruction::CloseUserAccount {},
+    );
+
+    // Nothing closes while the proposal is open
+    let err = process(&mut context, std::slice::from_ref(&close_proposal), &[&creator]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::ProposalNotSettled));
+    let err = process(&mut context, std::slice::from_ref(&close_vote_record), &[&voter]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::VotingStillOpen));
+    let err = process(&mut context, std::slice::from_ref(&close_user_account), &[&creator]).await.unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::UserAccountInUse));
+
+    warp_forward(&mut context, 100).await;
+    process(&mut context, &[finalize_proposal(&proposal, &creator_account)], &[]).await.unwrap();
+
+    // Only the creator can close the proposal, and gets its rent back
+    let err = process(
+        &mut context,
+        &[build(
+            accounts::CloseProposal {
+                proposal,
+                creator: voter.pubkey(),
+            },
+            instruction::CloseProposal {},
+        )],
+        &[&voter],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error_code(err), code(LoyaltyProgramError::Unauthorized));
+    let rent = lamports(&mut context, &proposal).await;
+    let balance = lamports(&mut context, &creator.pubkey()).await;
+    process(&mut context, &[close_proposal], &[&creator]).await.unwrap();
+    assert!(!account_exists(&mut context, &proposal).await);
+    assert_eq!(lamports(&mut context, &creator.pubkey()).await, balance + rent);
+
+    // Vote records can still be closed after their proposal is gone
+    let rent = lamports(&mut context, &vote_record).await;
+    let balance = lamports(&mut context, &voter.pubkey()).await;
+    process(&mut context, &[close_vote_record], &[&voter]).await.unwrap();
+    assert!(!account_exists(&mut context, &vote_record).await);
+    assert_eq!(lamports(&mut context, &voter.pubkey()).await, balance + rent);
+
+    // Closing a user account forfeits its points
+    process(&mut context, &[track_loyalty(&payer, &creator_account, &creator.pubkey(), None, 50)], &[])
+        .await
+        .unwrap();
+    let rent = lamports(&mut context, &creator_account).await + lamports(&mut context, &loyalty_address(&creator_account)).await;
+    let balance = lamports(&mut context, &creator.pubkey()).await;
+    process(&mut context, &[close_user_account], &[&creator]).await.unwrap();
+    assert!(!account_exists(&mut context, &creator_account).await);
+    assert!(!account_exists(&mut context, &loyalty_address(&creator_account)).await);
+    assert_eq!(lamports(&mut context, &creator.pubkey()).await, balance + rent);
 }
@@ -0,0 +1,754 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anchor_lang::prelude::Pubkey;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+
+use crate::{IndexerError, ProgramEvent, TransactionRecord};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transactions (
+    signature TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    succeeded INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS events (
+    signature TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    PRIMARY KEY (signature, event_index)
+);
+CREATE TABLE IF NOT EXISTS users (
+    user_account TEXT PRIMARY KEY,
+    wallet TEXT NOT NULL,
+    loyalty_points INTEGER NOT NULL DEFAULT 0,
+    loyalty_tier INTEGER NOT NULL DEFAULT 1,
+    username TEXT,
+    name TEXT NOT NULL DEFAULT '',
+    bio TEXT NOT NULL DEFAULT '',
+    early_adopter_rewarded INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL,
+    closed_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS users_by_wallet ON users (wallet);
+CREATE TABLE IF NOT EXISTS points_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    wallet TEXT NOT NULL,
+    user_account TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    points INTEGER NOT NULL,
+    previous_points INTEGER NOT NULL,
+    new_points INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS points_history_by_wallet ON points_history (wallet);
+CREATE TABLE IF NOT EXISTS token_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    signature TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    wallet TEXT NOT NULL,
+    token_account TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    previous_balance INTEGER NOT NULL,
+    new_balance INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS proposals (
+    proposal TEXT PRIMARY KEY,
+    creator TEXT NOT NULL,
+    creator_account TEXT NOT NULL,
+    title TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    options TEXT NOT NULL,
+    has_action INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    votes_for INTEGER NOT NULL DEFAULT 0,
+    votes_against INTEGER NOT NULL DEFAULT 0,
+    winning_option INTEGER,
+    created_at INTEGER NOT NULL,
+    voting_ends_at INTEGER NOT NULL,
+    executable_at INTEGER,
+    closed_at INTEGER
+);
+CREATE TABLE IF NOT EXISTS votes (
+    proposal TEXT NOT NULL,
+    voter_account TEXT NOT NULL,
+    voter TEXT NOT NULL,
+    in_favor INTEGER NOT NULL,
+    choices TEXT NOT NULL,
+    weight INTEGER NOT NULL,
+    delegate TEXT,
+    signature TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    PRIMARY KEY (proposal, voter)
+);
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointsChangeKind {
+    Tracked,
+    Referral,
+    Redeemed,
+    ItemRedeemed,
+    Penalty,
+    Expired,
+}
+
+impl PointsChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PointsChangeKind::Tracked => "tracked",
+            PointsChangeKind::Referral => "referral",
+            PointsChangeKind::Redeemed => "redeemed",
+            PointsChangeKind::ItemRedeemed => "item_redeemed",
+            PointsChangeKind::Penalty => "penalty",
+            PointsChangeKind::Expired => "expired",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        [
+            PointsChangeKind::Tracked,
+            PointsChangeKind::Referral,
+            PointsChangeKind::Redeemed,
+            PointsChangeKind::ItemRedeemed,
+            PointsChangeKind::Penalty,
+            PointsChangeKind::Expired,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == kind)
+    }
+}
+
+// One change to a user's points. `points` is negative for anything that
+// takes points away.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PointsChange {
+    pub signature: String,
+    pub slot: u64,
+    pub user_account: Pubkey,
+    pub kind: PointsChangeKind,
+    pub points: i64,
+    pub previous_points: u64,
+    pub new_points: u64,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserRecord {
+    pub user_account: Pubkey,
+    pub wallet: Pubkey,
+    pub loyalty_points: u64,
+    pub loyalty_tier: u8,
+    pub username: Option<String>,
+    pub name: String,
+    pub bio: String,
+    pub early_adopter_rewarded: bool,
+    pub created_at: i64,
+    pub closed_at: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProposalRecord {
+    pub proposal: Pubkey,
+    pub creator: Pubkey,
+    pub creator_account: Pubkey,
+    pub title: String,
+    pub uri: String,
+    pub options: Vec<String>,
+    pub has_action: bool,
+    // `active`, `succeeded`, `defeated` or `executed`.
+    pub status: String,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub winning_option: Option<u8>,
+    pub created_at: i64,
+    pub voting_ends_at: i64,
+    pub executable_at: Option<i64>,
+    pub closed_at: Option<i64>,
+}
+
+// `delegate` is set when the vote was cast by the voter's delegate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProposalVote {
+    pub proposal: Pubkey,
+    pub voter_account: Pubkey,
+    pub voter: Pubkey,
+    pub in_favor: bool,
+    pub choices: Vec<u16>,
+    pub weight: u64,
+    pub delegate: Option<Pubkey>,
+    pub timestamp: i64,
+}
+
+pub struct Indexer {
+    conn: Connection,
+    program_id: Pubkey,
+}
+
+impl Indexer {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, IndexerError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, IndexerError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, IndexerError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn,
+            program_id: early_adopter_airdrop::ID,
+        })
+    }
+
+    // Indexes every record in `json`, a single transaction or an array of
+    // them, and returns how many events were applied.
+    pub fn index_json(&mut self, json: &str) -> Result<usize, IndexerError> {
+        let mut applied = 0;
+        for record in TransactionRecord::parse_all(json)? {
+            applied += self.index_transaction(&record)?;
+        }
+        Ok(applied)
+    }
+
+    // Records must be indexed in the order they landed. A transaction that
+    // was already indexed, or that failed, applies nothing.
+    pub fn index_transaction(&mut self, record: &TransactionRecord) -> Result<usize, IndexerError> {
+        let signature = record.signature()?;
+        let tx = self.conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO transactions (signature, slot, block_time, succeeded) VALUES (?1, ?2, ?3, ?4)",
+            params![signature, record.slot as i64, record.block_time, record.succeeded()],
+        )?;
+        if inserted == 0 || !record.succeeded() {
+            tx.commit()?;
+            return Ok(0);
+        }
+
+        let mut applied = 0;
+        for (event_index, data) in record.program_data(&self.program_id).iter().enumerate() {
+            let Some(event) = ProgramEvent::decode(data) else {
+                continue;
+            };
+            tx.execute(
+                "INSERT INTO events (signature, event_index, slot, name, version, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![signature, event_index as i64, record.slot as i64, event.name(), event.version(), event.timestamp()],
+            )?;
+            apply_event(&tx, signature, record.slot, &event)?;
+            applied += 1;
+        }
+        tx.commit()?;
+        Ok(applied)
+    }
+
+    // Every points change across all of the wallet's user accounts, oldest first.
+    pub fn points_history(&self, wallet: &Pubkey) -> Result<Vec<PointsChange>, IndexerError> {
+        let mut statement = self.conn.prepare(
+            "SELECT signature, slot, user_account, kind, points, previous_points, new_points, timestamp
+             FROM points_history WHERE wallet = ?1 ORDER BY id",
+        )?;
+        let rows = statement.query_map([wallet.to_string()], |row| {
+            Ok(PointsChange {
+                signature: row.get(0)?,
+                slot: row.get::<_, i64>(1)? as u64,
+                user_account: pubkey_column(row, 2)?,
+                kind: PointsChangeKind::parse(&row.get::<_, String>(3)?).ok_or(rusqlite::Error::InvalidColumnType(
+                    3,
+                    "kind".to_string(),
+                    rusqlite::types::Type::Text,
+                ))?,
+                points: row.get(4)?,
+                previous_points: row.get::<_, i64>(5)? as u64,
+                new_points: row.get::<_, i64>(6)? as u64,
+                timestamp: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn user(&self, user_account: &Pubkey) -> Result<Option<UserRecord>, IndexerError> {
+        let user = self
+            .conn
+            .query_row(
+                "SELECT user_account, wallet, loyalty_points, loyalty_tier, username, name, bio,
+                        early_adopter_rewarded, created_at, closed_at
+                 FROM users WHERE user_account = ?1",
+                [user_account.to_string()],
+                user_row,
+            )
+            .optional()?;
+        Ok(user)
+    }
+
+    pub fn users_for_wallet(&self, wallet: &Pubkey) -> Result<Vec<UserRecord>, IndexerError> {
+        let mut statement = self.conn.prepare(
+            "SELECT user_account, wallet, loyalty_points, loyalty_tier, username, name, bio,
+                    early_adopter_rewarded, created_at, closed_at
+             FROM users WHERE wallet = ?1 ORDER BY created_at, user_account",
+        )?;
+        let rows = statement.query_map([wallet.to_string()], user_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn proposal(&self, proposal: &Pubkey) -> Result<Option<ProposalRecord>, IndexerError> {
+        let proposal = self
+            .conn
+            .query_row(
+                "SELECT proposal, creator, creator_account, title, uri, options, has_action, status, votes_for,
+                        votes_against, winning_option, created_at, voting_ends_at, executable_at, closed_at
+                 FROM proposals WHERE proposal = ?1",
+                [proposal.to_string()],
+                |row| {
+                    Ok(ProposalRecord {
+                        proposal: pubkey_column(row, 0)?,
+                        creator: pubkey_column(row, 1)?,
+                        creator_account: pubkey_column(row, 2)?,
+                        title: row.get(3)?,
+                        uri: row.get(4)?,
+                        options: json_column(row, 5)?,
+                        has_action: row.get(6)?,
+                        status: row.get(7)?,
+                        votes_for: row.get::<_, i64>(8)? as u64,
+                        votes_against: row.get::<_, i64>(9)? as u64,
+                        winning_option: row.get(10)?,
+                        created_at: row.get(11)?,
+                        voting_ends_at: row.get(12)?,
+                        executable_at: row.get(13)?,
+                        closed_at: row.get(14)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(proposal)
+    }
+
+    pub fn votes(&self, proposal: &Pubkey) -> Result<Vec<ProposalVote>, IndexerError> {
+        let mut statement = self.conn.prepare(
+            "SELECT proposal, voter_account, voter, in_favor, choices, weight, delegate, timestamp
+             FROM votes WHERE proposal = ?1 ORDER BY timestamp, voter",
+        )?;
+        let rows = statement.query_map([proposal.to_string()], |row| {
+            Ok(ProposalVote {
+                proposal: pubkey_column(row, 0)?,
+                voter_account: pubkey_column(row, 1)?,
+                voter: pubkey_column(row, 2)?,
+                in_favor: row.get(3)?,
+                choices: json_column(row, 4)?,
+                weight: row.get::<_, i64>(5)? as u64,
+                delegate: row
+                    .get::<_, Option<String>>(6)?
+                    .map(|delegate| parse_pubkey(6, &delegate))
+                    .transpose()?,
+                timestamp: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // How many of the program's events have been indexed under each name.
+    pub fn event_counts(&self) -> Result<Vec<(String, u64)>, IndexerError> {
+        let mut statement = self.conn.prepare("SELECT name, COUNT(*) FROM events GROUP BY name ORDER BY name")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+fn user_row(row: &Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        user_account: pubkey_column(row, 0)?,
+        wallet: pubkey_column(row, 1)?,
+        loyalty_points: row.get::<_, i64>(2)? as u64,
+        loyalty_tier: row.get(3)?,
+        username: row.get(4)?,
+        name: row.get(5)?,
+        bio: row.get(6)?,
+        early_adopter_rewarded: row.get(7)?,
+        created_at: row.get(8)?,
+        closed_at: row.get(9)?,
+    })
+}
+
+fn parse_pubkey(column: usize, value: &str) -> rusqlite::Result<Pubkey> {
+    Pubkey::from_str(value).map_err(|err| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+fn pubkey_column(row: &Row, column: usize) -> rusqlite::Result<Pubkey> {
+    parse_pubkey(column, &row.get::<_, String>(column)?)
+}
+
+fn json_column<T: serde::de::DeserializeOwned>(row: &Row, column: usize) -> rusqlite::Result<T> {
+    serde_json::from_str(&row.get::<_, String>(column)?)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+struct PointsRow<'a> {
+    wallet: &'a Pubkey,
+    user_account: &'a Pubkey,
+    kind: PointsChangeKind,
+    points: i64,
+    previous_points: u64,
+    new_points: u64,
+    timestamp: i64,
+}
+
+// Logs the change and moves the user's balance to the event's new balance.
+// Compressed users report their loyalty tree as the user account, so they
+// get history but no `users` row.
+fn record_points(tx: &Transaction, signature: &str, slot: u64, change: PointsRow) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO points_history
+            (signature, slot, wallet, user_account, kind, points, previous_points, new_points, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            signature,
+            slot as i64,
+            change.wallet.to_string(),
+            change.user_account.to_string(),
+            change.kind.as_str(),
+            change.points,
+            change.previous_points as i64,
+            change.new_points as i64,
+            change.timestamp,
+        ],
+    )?;
+    tx.execute(
+        "UPDATE users SET loyalty_points = ?2 WHERE user_account = ?1",
+        params![change.user_account.to_string(), change.new_points as i64],
+    )?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_tokens(
+    tx: &Transaction,
+    signature: &str,
+    slot: u64,
+    wallet: &Pubkey,
+    token_account: &Pubkey,
+    kind: &str,
+    amount: i64,
+    previous_balance: u64,
+    new_balance: u64,
+    timestamp: i64,
+) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO token_history
+            (signature, slot, wallet, token_account, kind, amount, previous_balance, new_balance, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            signature,
+            slot as i64,
+            wallet.to_string(),
+            token_account.to_string(),
+            kind,
+            amount,
+            previous_balance as i64,
+            new_balance as i64,
+            timestamp,
+        ],
+    )?;
+    Ok(())
+}
+
+// Events that don't feed the tables below are still listed in `events`.
+fn apply_event(tx: &Transaction, signature: &str, slot: u64, event: &ProgramEvent) -> rusqlite::Result<()> {
+    match event {
+        ProgramEvent::UserInitialized(event) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO users (user_account, wallet, created_at) VALUES (?1, ?2, ?3)",
+                params![event.user_account.to_string(), event.user.to_string(), event.timestamp],
+            )?;
+        }
+        ProgramEvent::UserAccountMigrated(event) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO users (user_account, wallet, created_at) VALUES (?1, ?2, ?3)",
+                params![event.user_account.to_string(), event.user.to_string(), event.timestamp],
+            )?;
+            tx.execute(
+                "UPDATE users SET loyalty_points = ?2 WHERE user_account = ?1",
+                params![event.user_account.to_string(), event.loyalty_points as i64],
+            )?;
+        }
+        ProgramEvent::UserAccountClosed(event) => {
+            tx.execute(
+                "UPDATE users SET closed_at = ?2, loyalty_points = 0 WHERE user_account = ?1",
+                params![event.user_account.to_string(), event.timestamp],
+            )?;
+        }
+        ProgramEvent::ProfileUpdated(event) => {
+            tx.execute(
+                "UPDATE users SET name = ?2, bio = ?3 WHERE user_account = ?1",
+                params![event.user_account.to_string(), event.name, event.bio],
+            )?;
+        }
+        ProgramEvent::TierChanged(event) => {
+            tx.execute(
+                "UPDATE users SET loyalty_tier = ?2 WHERE user_account = ?1",
+                params![event.user_account.to_string(), event.new_tier],
+            )?;
+        }
+        ProgramEvent::UsernameClaimed(event) => {
+            tx.execute(
+                "UPDATE users SET username = ?2 WHERE user_account = ?1",
+                params![event.user_account.to_string(), event.name],
+            )?;
+        }
+        ProgramEvent::UsernameReleased(event) => {
+            tx.execute(
+                "UPDATE users SET username = NULL WHERE user_account = ?1",
+                params![event.user_account.to_string()],
+            )?;
+        }
+        ProgramEvent::UsernameTransferred(event) => {
+            tx.execute(
+                "UPDATE users SET username = NULL WHERE user_account = ?1",
+                params![event.from_account.to_string()],
+            )?;
+            tx.execute(
+                "UPDATE users SET username = ?2 WHERE user_account = ?1",
+                params![event.to_account.to_string(), event.name],
+            )?;
+        }
+        ProgramEvent::LoyaltyPointsTracked(event) => {
+            let change = PointsRow {
+                wallet: &event.user,
+                user_account: &event.user_account,
+                kind: PointsChangeKind::Tracked,
+                points: event.points as i64,
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::LoyaltyPointsBatchTracked(event) => {
+            for entry in &event.entries {
+                let change = PointsRow {
+                    wallet: &entry.user,
+                    user_account: &entry.user_account,
+                    kind: PointsChangeKind::Tracked,
+                    points: entry.points as i64,
+                    previous_points: entry.previous_points,
+                    new_points: entry.new_points,
+                    timestamp: event.timestamp,
+                };
+                record_points(tx, signature, slot, change)?;
+            }
+        }
+        ProgramEvent::UserReferred(event) => {
+            let change = PointsRow {
+                wallet: &event.referrer,
+                user_account: &event.referrer_account,
+                kind: PointsChangeKind::Referral,
+                points: event.points as i64,
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::LoyaltyPointsRedeemed(event) => {
+            let change = PointsRow {
+                wallet: &event.user,
+                user_account: &event.user_account,
+                kind: PointsChangeKind::Redeemed,
+                points: -(event.points as i64),
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::ItemRedeemed(event) => {
+            let change = PointsRow {
+                wallet: &event.user,
+                user_account: &event.user_account,
+                kind: PointsChangeKind::ItemRedeemed,
+                points: -(event.points as i64),
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::InactivityPenaltyApplied(event) => {
+            let change = PointsRow {
+                wallet: &event.user,
+                user_account: &event.user_account,
+                kind: PointsChangeKind::Penalty,
+                points: -(event.points as i64),
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::PointsExpired(event) => {
+            let change = PointsRow {
+                wallet: &event.user,
+                user_account: &event.user_account,
+                kind: PointsChangeKind::Expired,
+                points: -(event.points as i64),
+                previous_points: event.previous_points,
+                new_points: event.new_points,
+                timestamp: event.timestamp,
+            };
+            record_points(tx, signature, slot, change)?;
+        }
+        ProgramEvent::EarlyAdopterRewarded(event) => {
+            record_tokens(
+                tx,
+                signature,
+                slot,
+                &event.user,
+                &event.token_account,
+                "early_adopter_reward",
+                event.amount as i64,
+                event.previous_balance,
+                event.new_balance,
+                event.timestamp,
+            )?;
+        }
+        ProgramEvent::EarlyAdoptersBatchRewarded(event) => {
+            for recipient in &event.recipients {
+                record_tokens(
+                    tx,
+                    signature,
+                    slot,
+                    &recipient.user,
+                    &recipient.token_account,
+                    "early_adopter_reward",
+                    recipient.amount as i64,
+                    recipient.previous_balance,
+                    recipient.new_balance,
+                    event.timestamp,
+                )?;
+                tx.execute(
+                    "UPDATE users SET early_adopter_rewarded = 1 WHERE user_account = ?1",
+                    params![recipient.user_account.to_string()],
+                )?;
+            }
+        }
+        ProgramEvent::TokensBurned(event) => {
+            record_tokens(
+                tx,
+                signature,
+                slot,
+                &event.owner,
+                &event.token_account,
+                "burn",
+                -(event.amount as i64),
+                event.previous_balance,
+                event.new_balance,
+                event.timestamp,
+            )?;
+        }
+        ProgramEvent::ProposalCreated(event) => {
+            let options = serde_json::to_string(&event.options).expect("labels serialize");
+            tx.execute(
+                "INSERT OR REPLACE INTO proposals
+                    (proposal, creator, creator_account, title, uri, options, has_action, status, created_at, voting_ends_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'active', ?8, ?9)",
+                params![
+                    event.proposal.to_string(),
+                    event.creator.to_string(),
+                    event.creator_account.to_string(),
+                    event.title,
+                    event.uri,
+                    options,
+                    event.action.is_some() || event.instruction_count != 0,
+                    event.timestamp,
+                    event.voting_ends_at,
+                ],
+            )?;
+        }
+        ProgramEvent::ProposalMetadataUpdated(event) => {
+            tx.execute(
+                "UPDATE proposals SET title = ?2, uri = ?3 WHERE proposal = ?1",
+                params![event.proposal.to_string(), event.title, event.uri],
+            )?;
+        }
+        ProgramEvent::ProposalFinalized(event) => {
+            tx.execute(
+                "UPDATE proposals
+                 SET status = ?2, votes_for = ?3, votes_against = ?4, winning_option = ?5, executable_at = ?6
+                 WHERE proposal = ?1",
+                params![
+                    event.proposal.to_string(),
+                    if event.succeeded { "succeeded" } else { "defeated" },
+                    event.votes_for as i64,
+                    event.votes_against as i64,
+                    event.winning_option,
+                    event.executable_at,
+                ],
+            )?;
+        }
+        ProgramEvent::ProposalExecuted(event) => {
+            tx.execute(
+                "UPDATE proposals SET status = 'executed' WHERE proposal = ?1",
+                params![event.proposal.to_string()],
+            )?;
+        }
+        ProgramEvent::ProposalClosed(event) => {
+            tx.execute(
+                "UPDATE proposals SET closed_at = ?2 WHERE proposal = ?1",
+                params![event.proposal.to_string(), event.timestamp],
+            )?;
+        }
+        // Votes are recorded per wallet, like the program's vote records, so a
+        // direct vote replaces any vote a delegate cast for the same wallet,
+        // whichever of its user accounts either came from.
+        ProgramEvent::GovernanceVoted(event) => {
+            let choices = serde_json::to_string(&event.choices).expect("choices serialize");
+            tx.execute(
+                "INSERT OR REPLACE INTO votes
+                    (proposal, voter_account, voter, in_favor, choices, weight, delegate, signature, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL, ?7, ?8)",
+                params![
+                    event.proposal.to_string(),
+                    event.voter_account.to_string(),
+                    event.user.to_string(),
+                    event.in_favor,
+                    choices,
+                    event.weight as i64,
+                    signature,
+                    event.timestamp,
+                ],
+            )?;
+            // Delegated votes are logged before the delegate's own vote and
+            // follow its ballot.
+            tx.execute(
+                "UPDATE votes SET in_favor = ?3, choices = ?4
+                 WHERE proposal = ?1 AND delegate = ?2 AND signature = ?5",
+                params![event.proposal.to_string(), event.user.to_string(), event.in_favor, choices, signature],
+            )?;
+        }
+        ProgramEvent::DelegatedVoteCast(event) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO votes
+                    (proposal, voter_account, voter, in_favor, choices, weight, delegate, signature, timestamp)
+                 VALUES (?1, ?2, ?3, 0, '[]', ?4, ?5, ?6, ?7)",
+                params![
+                    event.proposal.to_string(),
+                    event.delegator_account.to_string(),
+                    event.delegator.to_string(),
+                    event.weight as i64,
+                    event.delegate.to_string(),
+                    signature,
+                    event.timestamp,
+                ],
+            )?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
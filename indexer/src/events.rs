@@ -0,0 +1,98 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use early_adopter_airdrop::*;
+
+// Lists every event the program emits, so each gets a variant, a name for
+// the `events` table and a decoder keyed by its discriminator.
+macro_rules! program_events {
+    ($($event:ident),* $(,)?) => {
+        pub enum ProgramEvent {
+            $($event($event),)*
+        }
+
+        impl ProgramEvent {
+            // Decodes the bytes of a `Program data:` log line. Returns `None`
+            // for data that isn't one of the program's events.
+            pub fn decode(data: &[u8]) -> Option<Self> {
+                if data.len() < 8 {
+                    return None;
+                }
+                let (discriminator, mut body) = data.split_at(8);
+                $(
+                    if discriminator == $event::DISCRIMINATOR {
+                        return $event::deserialize(&mut body).ok().map(ProgramEvent::$event);
+                    }
+                )*
+                None
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(ProgramEvent::$event(_) => stringify!($event),)*
+                }
+            }
+
+            pub fn version(&self) -> u8 {
+                match self {
+                    $(ProgramEvent::$event(event) => event.version,)*
+                }
+            }
+
+            pub fn timestamp(&self) -> i64 {
+                match self {
+                    $(ProgramEvent::$event(event) => event.timestamp,)*
+                }
+            }
+        }
+    };
+}
+
+program_events!(
+    MintInitialized,
+    ConfigUpdated,
+    UserInitialized,
+    EarlyAdopterRewarded,
+    EarlyAdoptersBatchRewarded,
+    LoyaltyPointsTracked,
+    CompressedUserChanged,
+    LoyaltyTreeCreated,
+    LoyaltyPointsBatchTracked,
+    GovernanceVoted,
+    DelegatedVoteCast,
+    LoyaltyPointsRedeemed,
+    UserReferred,
+    InactivityPenaltyApplied,
+    TierChanged,
+    BadgeRegistered,
+    BadgeUnlocked,
+    BadgeMinted,
+    SeasonCreated,
+    SeasonJoined,
+    SeasonPointsTracked,
+    SeasonRewardDistributed,
+    PointsExpired,
+    TokensBurned,
+    CatalogInitialized,
+    ItemListed,
+    ItemActiveChanged,
+    ItemRedeemed,
+    RedemptionFulfilled,
+    ProgramPaused,
+    ProgramUnpaused,
+    ProposalCreated,
+    ProposalMetadataUpdated,
+    ProposalFinalized,
+    ProposalExecuted,
+    ProposalDepositSettled,
+    TokensLocked,
+    TokensUnlocked,
+    VotesDelegated,
+    VotesUndelegated,
+    ProposalClosed,
+    VoteRecordClosed,
+    UserAccountClosed,
+    ProfileUpdated,
+    UsernameClaimed,
+    UsernameReleased,
+    UsernameTransferred,
+    UserAccountMigrated,
+);
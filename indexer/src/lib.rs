@@ -0,0 +1,42 @@
+//! Replays early_adopter_airdrop events from transaction records into a
+//! SQLite database of users, points history, proposals and votes.
+
+mod db;
+mod events;
+mod transaction;
+
+pub use db::{Indexer, PointsChange, PointsChangeKind, ProposalRecord, ProposalVote, UserRecord};
+pub use events::ProgramEvent;
+pub use transaction::{program_data, TransactionBody, TransactionMeta, TransactionRecord};
+
+#[derive(Debug)]
+pub enum IndexerError {
+    Json(serde_json::Error),
+    Database(rusqlite::Error),
+    // The record has no signature to key it by.
+    MissingSignature,
+}
+
+impl std::fmt::Display for IndexerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexerError::Json(err) => write!(f, "invalid transaction record: {err}"),
+            IndexerError::Database(err) => write!(f, "database error: {err}"),
+            IndexerError::MissingSignature => write!(f, "transaction record has no signature"),
+        }
+    }
+}
+
+impl std::error::Error for IndexerError {}
+
+impl From<serde_json::Error> for IndexerError {
+    fn from(err: serde_json::Error) -> Self {
+        IndexerError::Json(err)
+    }
+}
+
+impl From<rusqlite::Error> for IndexerError {
+    fn from(err: rusqlite::Error) -> Self {
+        IndexerError::Database(err)
+    }
+}
@@ -0,0 +1,100 @@
+use anchor_lang::prelude::Pubkey;
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::IndexerError;
+
+// The subset of a `getTransaction` RPC response the indexer reads. Records
+// saved from the RPC, in `json` or `jsonParsed` encoding, can be loaded as is.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionRecord {
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub transaction: TransactionBody,
+    pub meta: Option<TransactionMeta>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TransactionBody {
+    pub signatures: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionMeta {
+    pub err: Option<serde_json::Value>,
+    #[serde(default)]
+    pub log_messages: Option<Vec<String>>,
+}
+
+impl TransactionRecord {
+    // Accepts a single record, an array of them, or a JSON-RPC response
+    // wrapping either.
+    pub fn parse_all(json: &str) -> Result<Vec<Self>, IndexerError> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        if let Some(result) = value.get_mut("result") {
+            value = result.take();
+        }
+        if value.is_array() {
+            Ok(serde_json::from_value(value)?)
+        } else {
+            Ok(vec![serde_json::from_value(value)?])
+        }
+    }
+
+    pub fn signature(&self) -> Result<&str, IndexerError> {
+        self.transaction
+            .signatures
+            .first()
+            .map(String::as_str)
+            .ok_or(IndexerError::MissingSignature)
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.meta.as_ref().is_some_and(|meta| meta.err.is_none())
+    }
+
+    pub fn logs(&self) -> &[String] {
+        self.meta
+            .as_ref()
+            .and_then(|meta| meta.log_messages.as_deref())
+            .unwrap_or_default()
+    }
+
+    // The decoded `Program data:` lines logged by `program_id` itself, in order.
+    pub fn program_data(&self, program_id: &Pubkey) -> Vec<Vec<u8>> {
+        program_data(self.logs(), program_id)
+    }
+}
+
+// Follows the invoke/success lines to know which program is running, so
+// data logged by other programs, including ones this program calls, is left out.
+pub fn program_data(logs: &[String], program_id: &Pubkey) -> Vec<Vec<u8>> {
+    let program_id = program_id.to_string();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut data = Vec::new();
+    for log in logs {
+        if let Some(encoded) = log.strip_prefix("Program data: ") {
+            if stack.last() == Some(&program_id.as_str()) {
+                // Anchor events are logged as a single base64 field.
+                if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(encoded) {
+                    data.push(bytes);
+                }
+            }
+            continue;
+        }
+        let Some(rest) = log.strip_prefix("Program ") else {
+            continue;
+        };
+        let words: Vec<&str> = rest.split(' ').collect();
+        match words.as_slice() {
+            [program, "invoke", _depth] => stack.push(program),
+            [program, "success"] | [program, "failed:", ..] if stack.last() == Some(program) => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    data
+}
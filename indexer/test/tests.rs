@@ -0,0 +1,175 @@
+use anchor_lang::prelude::Pubkey;
+use early_adopter_indexer::*;
+
+const TRANSACTIONS: &str = include_str!("fixtures/transactions.json");
+const RPC_RESPONSE: &str = include_str!("fixtures/rpc_response.json");
+
+// The fixtures use keys filled with a single byte: wallets 1 and 3 own user
+// accounts 2 and 4, and 5 is the proposal.
+fn key(byte: u8) -> Pubkey {
+    Pubkey::new_from_array([byte; 32])
+}
+
+fn indexed() -> Indexer {
+    let mut indexer = Indexer::open_in_memory().unwrap();
+    indexer.index_json(TRANSACTIONS).unwrap();
+    indexer
+}
+
+#[test]
+fn test_program_data_attribution() {
+    let records = TransactionRecord::parse_all(TRANSACTIONS).unwrap();
+    // The batch airdrop runs after a memo instruction that logs data of its
+    // own, and makes nested token program calls.
+    let airdrop = &records[4];
+    assert!(airdrop.logs().iter().filter(|log| log.starts_with("Program data: ")).count() == 2);
+    let data = airdrop.program_data(&early_adopter_airdrop::ID);
+    assert_eq!(data.len(), 1);
+    let event = ProgramEvent::decode(&data[0]).unwrap();
+    assert_eq!(event.name(), "EarlyAdoptersBatchRewarded");
+    assert_eq!(event.version(), early_adopter_airdrop::EVENT_VERSION);
+    assert_eq!(event.timestamp(), airdrop.block_time.unwrap());
+
+    assert!(ProgramEvent::decode(&[0; 4]).is_none());
+    assert!(ProgramEvent::decode(&[0; 40]).is_none());
+}
+
+#[test]
+fn test_points_history() {
+    let indexer = indexed();
+
+    let history = indexer.points_history(&key(1)).unwrap();
+    let changes: Vec<_> = history
+        .iter()
+        .map(|change| (change.kind, change.points, change.previous_points, change.new_points))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (PointsChangeKind::Tracked, 50, 0, 50),
+            (PointsChangeKind::Tracked, 20, 50, 70),
+            (PointsChangeKind::Redeemed, -10, 70, 60),
+        ]
+    );
+    assert!(history.iter().all(|change| change.user_account == key(2)));
+    assert_eq!(history[0].slot, 102);
+
+    // The failed transaction's event is ignored
+    let history = indexer.points_history(&key(3)).unwrap();
+    let changes: Vec<_> = history.iter().map(|change| (change.kind, change.points)).collect();
+    assert_eq!(changes, vec![(PointsChangeKind::Tracked, 30), (PointsChangeKind::Penalty, -10)]);
+    assert!(indexer.points_history(&key(11)).unwrap().is_empty());
+}
+
+#[test]
+fn test_users() {
+    let indexer = indexed();
+
+    let alice = indexer.user(&key(2)).unwrap().unwrap();
+    assert_eq!(alice.wallet, key(1));
+    assert_eq!(alice.loyalty_points, 60);
+    assert_eq!(alice.username.as_deref(), Some("alice"));
+    assert_eq!((alice.name.as_str(), alice.bio.as_str()), ("Alice", "Early adopter."));
+    assert!(alice.early_adopter_rewarded);
+    assert_eq!(alice.created_at, 1_760_000_000);
+
+    let users = indexer.users_for_wallet(&key(3)).unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].loyalty_points, 20);
+    assert_eq!(users[0].username, None);
+
+    // Data logged by the memo program didn't create a user
+    assert!(indexer.user(&key(12)).unwrap().is_none());
+}
+
+#[test]
+fn test_proposals_and_votes() {
+    let indexer = indexed();
+
+    let proposal = indexer.proposal(&key(5)).unwrap().unwrap();
+    assert_eq!(proposal.creator, key(1));
+    assert_eq!(proposal.title, "Raise referral reward");
+    assert!(proposal.has_action);
+    assert_eq!(proposal.status, "succeeded");
+    assert_eq!((proposal.votes_for, proposal.votes_against), (90, 0));
+    assert_eq!(proposal.executable_at, Some(1_760_000_011 + 86400));
+
+    // The delegated vote takes the delegate's ballot
+    let votes = indexer.votes(&key(5)).unwrap();
+    let votes: Vec<_> = votes
+        .iter()
+        .map(|vote| (vote.voter, vote.voter_account, vote.in_favor, vote.weight, vote.delegate))
+        .collect();
+    assert_eq!(
+        votes,
+        vec![(key(1), key(2), true, 60, None), (key(3), key(4), true, 30, Some(key(1)))]
+    );
+}
+
+#[test]
+fn test_vote_override_from_another_account() {
+    use anchor_lang::Event;
+    use base64::Engine;
+    use early_adopter_airdrop::{GovernanceVoted, EVENT_VERSION};
+
+    let mut indexer = indexed();
+
+    // Wallet 3 votes itself from a second user account, 6, replacing the
+    // vote its delegate cast through account 4
+    let event = GovernanceVoted {
+        version: EVENT_VERSION,
+        proposal: key(5),
+        user: key(3),
+        voter_account: key(6),
+        in_favor: false,
+        choices: vec![],
+        weight: 30,
+        delegated_weight: 0,
+        timestamp: 1_760_000_013,
+    };
+    let program_id = early_adopter_airdrop::ID;
+    let record = serde_json::json!({
+        "blockTime": 1_760_000_013,
+        "meta": {
+            "err": null,
+            "logMessages": [
+                format!("Program {program_id} invoke [1]"),
+                format!("Program data: {}", base64::engine::general_purpose::STANDARD.encode(event.data())),
+                format!("Program {program_id} success"),
+            ],
+        },
+        "slot": 113,
+        "transaction": { "signatures": ["override"] },
+    });
+    assert_eq!(indexer.index_json(&record.to_string()).unwrap(), 1);
+
+    let votes = indexer.votes(&key(5)).unwrap();
+    let votes: Vec<_> = votes
+        .iter()
+        .map(|vote| (vote.voter, vote.voter_account, vote.in_favor, vote.weight, vote.delegate))
+        .collect();
+    assert_eq!(
+        votes,
+        vec![(key(1), key(2), true, 60, None), (key(3), key(6), false, 30, None)]
+    );
+}
+
+#[test]
+fn test_reindexing_is_idempotent() {
+    let mut indexer = indexed();
+    let counts = indexer.event_counts().unwrap();
+    assert!(counts.contains(&("LoyaltyPointsTracked".to_string(), 1)));
+    assert!(counts.contains(&("UserInitialized".to_string(), 2)));
+
+    assert_eq!(indexer.index_json(TRANSACTIONS).unwrap(), 0);
+    assert_eq!(indexer.event_counts().unwrap(), counts);
+    assert_eq!(indexer.points_history(&key(1)).unwrap().len(), 3);
+
+    // Records straight from the RPC are accepted too
+    assert_eq!(indexer.index_json(RPC_RESPONSE).unwrap(), 1);
+    let referral = indexer.points_history(&key(1)).unwrap().pop().unwrap();
+    assert_eq!((referral.kind, referral.points, referral.new_points), (PointsChangeKind::Referral, 150, 210));
+    assert_eq!(indexer.user(&key(2)).unwrap().unwrap().loyalty_points, 210);
+
+    assert!(matches!(indexer.index_json("{\"slot\": 1}"), Err(IndexerError::Json(_))));
+}